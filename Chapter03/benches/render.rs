@@ -0,0 +1,53 @@
+// Compares rendering a large report into a String with streaming it through render_to.
+// Run with `cargo bench`.
+use std::collections::HashMap;
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use template_engine::filters::FilterRegistry;
+use template_engine::loader::MemoryLoader;
+use template_engine::render::Renderer;
+use template_engine::value::Value;
+
+const REPORT: &str = "<table>
+{%- for row in rows %}
+  <tr><td>{{ loop.index }}</td><td>{{ row.name | upper }}</td><td>{{ row.total | round(2) }}</td></tr>
+{%- endfor %}
+</table>";
+
+// About 20k rows, a few megabytes of output
+fn context() -> HashMap<String, Value> {
+    let rows: Vec<Value> = (0..20_000)
+        .map(|i| {
+            let mut row = HashMap::new();
+            row.insert("name".to_string(), Value::from(format!("customer {}", i)));
+            row.insert("total".to_string(), Value::Number(i as f64 * 1.5));
+            Value::Map(row)
+        })
+        .collect();
+    let mut context = HashMap::new();
+    context.insert("rows".to_string(), Value::List(rows));
+    context
+}
+
+fn render_benchmark(c: &mut Criterion) {
+    let mut loader = MemoryLoader::new();
+    loader.add("report.html", REPORT);
+    let filters = FilterRegistry::new();
+    let renderer = Renderer::new(&loader, &filters);
+    let context = context();
+
+    c.bench_function("render to string", |b| {
+        b.iter(|| renderer.render("report.html", &context).unwrap().len())
+    });
+    c.bench_function("render_to io::sink", |b| {
+        b.iter(|| {
+            renderer
+                .render_to("report.html", &context, &mut io::sink())
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, render_benchmark);
+criterion_main!(benches);
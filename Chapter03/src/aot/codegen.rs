@@ -0,0 +1,741 @@
+// Translates parsed templates into Rust source. The walk mirrors RenderState in render.rs, but
+// instead of writing output it writes the code that will: extends, blocks, static includes and
+// macro calls are resolved here, so the generated functions only evaluate expressions.
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error::{did_you_mean, ErrorKind, TemplateError};
+use crate::expr::ast::{BinOp, Expr};
+use crate::filters::FilterRegistry;
+use crate::loader::Loader;
+use crate::template::{Import, Node, Span, Template};
+use crate::value::Value;
+
+// How the generated code reaches a template variable
+#[derive(Debug, Clone)]
+enum Binding {
+    // A typed Rust place, eg. a loop item borrowed from the context; attributes are field accesses
+    Typed(String),
+    // A local holding a Value, from `set`, `include ... with`, a macro parameter or `loop`
+    Dynamic(String),
+    // Hides the bindings below it, for `include ... only` and macro bodies; `ctx` tells
+    // whether the context fields stay visible
+    Barrier { ctx: bool },
+}
+
+pub(crate) struct Generator<'a> {
+    loader: &'a dyn Loader,
+    filters: FilterRegistry,
+    parsed: HashMap<String, Rc<Template>>,
+    // Templates whose name and source the generated code quotes in error messages
+    constants: Vec<Rc<Template>>,
+    code: String,
+    indent: usize,
+    // Template variable names and how to reach them, innermost last
+    bindings: Vec<(String, Binding)>,
+    // The template being compiled followed by the templates it extends, most derived first
+    chain: Vec<Rc<Template>>,
+    // Blocks being compiled, with the position in `chain` of the definition in use
+    block_stack: Vec<(String, usize)>,
+    // Templates being compiled, to detect include cycles
+    include_stack: Vec<String>,
+    // Templates whose nodes are being compiled, innermost last; errors are located in the last one
+    current: Vec<Rc<Template>>,
+    // Macros being inlined, to refuse recursion
+    macro_stack: Vec<String>,
+    // `if` branches entered since the innermost for, include or macro body
+    branches: usize,
+    // For unique names of generated locals
+    counter: usize,
+}
+
+impl<'a> Generator<'a> {
+    pub(crate) fn new(loader: &'a dyn Loader) -> Self {
+        Generator {
+            loader,
+            filters: FilterRegistry::new(),
+            parsed: HashMap::new(),
+            constants: Vec::new(),
+            code: String::new(),
+            indent: 0,
+            bindings: Vec::new(),
+            chain: Vec::new(),
+            block_stack: Vec::new(),
+            include_stack: Vec::new(),
+            current: Vec::new(),
+            macro_stack: Vec::new(),
+            branches: 0,
+            counter: 0,
+        }
+    }
+
+    // Generates `function(ctx)`, returning a String, and `function_to(ctx, out)`, writing to out
+    pub(crate) fn function(
+        &mut self,
+        template: &str,
+        function: &str,
+        context: &str,
+    ) -> Result<(), TemplateError> {
+        self.line(&format!("// Rendered from {:?}", template));
+        self.line(&format!(
+            "pub fn {}(ctx: &{}) -> Result<String, rt::TemplateError> {{",
+            function, context
+        ));
+        self.indent += 1;
+        self.line("let mut buf = Vec::new();");
+        self.line(&format!("{}_to(ctx, &mut buf)?;", function));
+        self.line("Ok(rt::into_string(buf))");
+        self.indent -= 1;
+        self.line("}");
+        self.line("");
+        // The code is written to be simple to generate, not to please lints
+        self.line("#[allow(unused_variables, unused_mut, clippy::all)]");
+        self.line(&format!(
+            "pub fn {}_to<W: std::io::Write>(ctx: &{}, out: &mut W) -> Result<(), rt::TemplateError> {{",
+            function, context
+        ));
+        self.indent += 1;
+        self.line("let mut writer = std::io::BufWriter::new(out);");
+        self.line("let out = &mut writer;");
+        self.render_template(template)?;
+        self.line("rt::finish(out)");
+        self.indent -= 1;
+        self.line("}");
+        self.line("");
+        Ok(())
+    }
+
+    // The generated file: the functions, preceded by the templates they quote in errors
+    pub(crate) fn finish(self) -> String {
+        let mut file = String::from(
+            "// Generated by template_engine::aot::Compiler. Do not edit.\n\
+             use ::template_engine::aot::runtime as rt;\n\n",
+        );
+        for (i, template) in self.constants.iter().enumerate() {
+            file.push_str(&format!(
+                "const TEMPLATE_{}: &str = {:?};\nconst SOURCE_{}: &str = {:?};\n",
+                i, template.name, i, template.source
+            ));
+        }
+        file.push('\n');
+        file.push_str(&self.code);
+        file
+    }
+}
+
+// Walking templates
+impl<'a> Generator<'a> {
+    fn render_template(&mut self, name: &str) -> Result<(), TemplateError> {
+        if self.include_stack.iter().any(|n| n == name) {
+            let mut cycle = self.include_stack.clone();
+            cycle.push(name.to_string());
+            return Err(ErrorKind::CyclicTemplate(cycle.join(" -> ")).into());
+        }
+        let chain = self.load_chain(name)?;
+        let root = chain[chain.len() - 1].clone();
+
+        self.include_stack.push(name.to_string());
+        let saved_chain = mem::replace(&mut self.chain, chain);
+        let saved_blocks = mem::take(&mut self.block_stack);
+        self.current.push(root.clone());
+        let result = self.nodes(&root.nodes);
+        self.current.pop();
+        self.chain = saved_chain;
+        self.block_stack = saved_blocks;
+        self.include_stack.pop();
+        result
+    }
+
+    fn load_chain(&mut self, name: &str) -> Result<Vec<Rc<Template>>, TemplateError> {
+        let mut chain: Vec<Rc<Template>> = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            if chain.iter().any(|t| t.name == name) {
+                let mut cycle: Vec<&str> = chain.iter().map(|t| t.name.as_str()).collect();
+                cycle.push(&name);
+                return Err(ErrorKind::CyclicTemplate(cycle.join(" -> ")).into());
+            }
+            let template = self.load(&name)?;
+            next = template.parent.clone();
+            chain.push(template);
+        }
+        Ok(chain)
+    }
+
+    // Parses a template with the same parser the renderer uses, once per template
+    fn load(&mut self, name: &str) -> Result<Rc<Template>, TemplateError> {
+        if let Some(template) = self.parsed.get(name) {
+            return Ok(template.clone());
+        }
+        let source = self.loader.load(name)?;
+        let template = Rc::new(Template::parse(name, &source)?);
+        self.parsed.insert(name.to_string(), template.clone());
+        Ok(template)
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<(), TemplateError> {
+        for node in nodes {
+            self.node(node)?;
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), TemplateError> {
+        match node {
+            Node::Text(text) => self.line(&format!("rt::write_str(out, {:?})?;", text)),
+            Node::Expr(expr, span) => {
+                let value = self.expr_at(expr, *span)?;
+                self.line(&format!("rt::write_value(out, &{})?;", value));
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, branch) in branches.iter().enumerate() {
+                    let condition = self.expr_at(&branch.condition, branch.span)?;
+                    let keyword = if i == 0 { "if" } else { "} else if" };
+                    self.line(&format!("{} {}.is_truthy() {{", keyword, condition));
+                    self.branch(&branch.body)?;
+                }
+                if !otherwise.is_empty() {
+                    self.line("} else {");
+                    self.branch(otherwise)?;
+                }
+                self.line("}");
+            }
+            Node::For {
+                var,
+                iterable,
+                span,
+                body,
+                otherwise,
+            } => {
+                let n = self.next_id();
+                let (items, typed) = match self.typed_place(iterable) {
+                    // Items borrowed from the context keep their Rust type
+                    Some(place) => (format!("rt::items_of(&{})", place), true),
+                    None => {
+                        let value = self.expr(iterable).map_err(|e| self.locate(e, *span))?;
+                        let (t, s) = self.location_constants();
+                        let items = format!(
+                            "rt::at({}, {}, {}, {}, || rt::items({}))?",
+                            t, s, span.line, span.column, value
+                        );
+                        (items, false)
+                    }
+                };
+                self.line("{");
+                self.indent += 1;
+                self.line(&format!("let items_{} = {};", n, items));
+                self.line(&format!("if items_{}.is_empty() {{", n));
+                self.branch(otherwise)?;
+                self.line("} else {");
+                self.indent += 1;
+                self.line(&format!("let length_{} = items_{}.len();", n, n));
+                let item = format!("v_{}", var);
+                self.line(&format!(
+                    "for (i_{}, {}) in items_{}.into_iter().enumerate() {{",
+                    n, item, n
+                ));
+                self.indent += 1;
+                self.line(&format!(
+                    "let v_loop = rt::loop_info(i_{}, length_{});",
+                    n, n
+                ));
+                let binding = if typed {
+                    Binding::Typed(item)
+                } else {
+                    Binding::Dynamic(item)
+                };
+                self.frame(
+                    vec![
+                        (var.clone(), binding),
+                        ("loop".to_string(), Binding::Dynamic("v_loop".to_string())),
+                    ],
+                    |g| g.nodes(body),
+                )?;
+                self.indent -= 1;
+                self.line("}");
+                self.indent -= 1;
+                self.line("}");
+                self.indent -= 1;
+                self.line("}");
+            }
+            Node::Block(name) => {
+                self.render_block(name, 0)?;
+            }
+            Node::Include {
+                name,
+                with,
+                only,
+                span,
+            } => {
+                let name = match name {
+                    Expr::Literal(Value::Str(name)) => name.clone(),
+                    _ => {
+                        let e = ErrorKind::RenderError(
+                            "Included templates must be named by a string literal to be compiled ahead of time"
+                                .to_string(),
+                        );
+                        return Err(self.locate(e.into(), *span));
+                    }
+                };
+                self.line("{");
+                self.indent += 1;
+                let mut bindings = Vec::new();
+                if *only {
+                    bindings.push((String::new(), Binding::Barrier { ctx: false }));
+                }
+                for (key, value) in with {
+                    let value = self.expr_at(value, *span)?;
+                    let n = self.next_id();
+                    self.line(&format!("let with_{} = {};", n, value));
+                    bindings.push((key.clone(), Binding::Dynamic(format!("with_{}", n))));
+                }
+                self.frame(bindings, |g| g.render_template(&name))
+                    .map_err(|e| self.locate(e, *span))?;
+                self.indent -= 1;
+                self.line("}");
+            }
+            Node::Set { name, value, span } => {
+                // The variable would go out of scope at the end of the branch in Rust, while
+                // the renderer keeps it until the end of the enclosing for, macro or template
+                if self.branches > 0 {
+                    let e = ErrorKind::RenderError(
+                        "`set` inside `if` is not supported when compiling ahead of time; move it before the `if`"
+                            .to_string(),
+                    );
+                    return Err(self.locate(e.into(), *span));
+                }
+                let value = self.expr_at(value, *span)?;
+                let n = self.next_id();
+                let local = format!("set_{}", n);
+                self.line(&format!("let {} = {};", local, value));
+                self.bindings.push((name.clone(), Binding::Dynamic(local)));
+            }
+            Node::Trans { span, .. } => {
+                let e = ErrorKind::RenderError(
+                    "`trans` needs translations loaded at runtime and cannot be compiled ahead of time"
+                        .to_string(),
+                );
+                return Err(self.locate(e.into(), *span));
+            }
+        }
+        Ok(())
+    }
+
+    // The body of an `if` branch or a `for ... else`, in its own Rust block
+    fn branch(&mut self, nodes: &[Node]) -> Result<(), TemplateError> {
+        self.indent += 1;
+        self.branches += 1;
+        let saved = self.bindings.len();
+        let result = self.nodes(nodes);
+        self.bindings.truncate(saved);
+        self.branches -= 1;
+        self.indent -= 1;
+        result
+    }
+
+    // Runs `f` with extra bindings that go out of scope afterwards, like a Frame in the renderer
+    fn frame<F>(&mut self, bindings: Vec<(String, Binding)>, f: F) -> Result<(), TemplateError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), TemplateError>,
+    {
+        let saved = self.bindings.len();
+        let saved_branches = mem::take(&mut self.branches);
+        self.bindings.extend(bindings);
+        let result = f(self);
+        self.branches = saved_branches;
+        self.bindings.truncate(saved);
+        result
+    }
+
+    // Compiles the first definition of a block found in the chain at or after `level`
+    fn render_block(&mut self, name: &str, level: usize) -> Result<bool, TemplateError> {
+        let found = self.chain[level..]
+            .iter()
+            .position(|t| t.blocks.contains_key(name));
+        let level = match found {
+            Some(i) => level + i,
+            None => return Ok(false),
+        };
+        let template = self.chain[level].clone();
+        self.block_stack.push((name.to_string(), level));
+        self.current.push(template.clone());
+        let result = self.nodes(&template.blocks[name]);
+        self.current.pop();
+        self.block_stack.pop();
+        result.map(|_| true)
+    }
+
+    // Code writing the output of `f` to a buffer, evaluating to the output as a Value
+    fn capture<F>(&mut self, f: F) -> Result<String, TemplateError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), TemplateError>,
+    {
+        let saved = mem::take(&mut self.code);
+        self.indent += 1;
+        self.line("let mut buf = Vec::<u8>::new();");
+        self.line("{");
+        self.indent += 1;
+        self.line("let out = &mut buf;");
+        let result = f(self);
+        self.indent -= 1;
+        self.line("}");
+        self.line("rt::captured(buf)");
+        self.indent -= 1;
+        let body = mem::replace(&mut self.code, saved);
+        result?;
+        Ok(format!("{{\n{}{}}}", body, "    ".repeat(self.indent)))
+    }
+}
+
+// Expressions: each one becomes a Rust expression of type Value
+impl<'a> Generator<'a> {
+    // An expression whose runtime errors point at `span`
+    fn expr_at(&mut self, expr: &Expr, span: Span) -> Result<String, TemplateError> {
+        let code = self.expr(expr).map_err(|e| self.locate(e, span))?;
+        let (t, s) = self.location_constants();
+        Ok(format!(
+            "rt::at({}, {}, {}, {}, || Ok({}))?",
+            t, s, span.line, span.column, code
+        ))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<String, TemplateError> {
+        Ok(match expr {
+            Expr::Variable(path) => self.variable(path)?,
+            Expr::Literal(value) => literal(value)?,
+            Expr::Filter { expr, name, args } => {
+                if !self.filters.contains(name) {
+                    return Err(ErrorKind::UnknownFilter(name.to_string()).into());
+                }
+                let value = self.expr(expr)?;
+                let args = self.args(args)?;
+                format!(
+                    "rt::filter({:?}, &{}, &[{}])?",
+                    name,
+                    value,
+                    args.join(", ")
+                )
+            }
+            Expr::Call { name, args } => match name.as_str() {
+                "super" if args.is_empty() => self.render_super()?,
+                _ => match self.find_macro(name)? {
+                    Some((template, macro_name)) => {
+                        self.inline_macro(template, &macro_name, args)?
+                    }
+                    None => {
+                        return Err(
+                            ErrorKind::RenderError(format!("Unknown function `{}`", name)).into(),
+                        )
+                    }
+                },
+            },
+            Expr::Not(expr) => format!("rt::Value::Bool(!{}.is_truthy())", self.expr(expr)?),
+            Expr::Binary { op, left, right } => {
+                let left = self.expr(left)?;
+                let right = self.expr(right)?;
+                match op {
+                    BinOp::And => format!(
+                        "rt::Value::Bool({}.is_truthy() && {}.is_truthy())",
+                        left, right
+                    ),
+                    BinOp::Or => format!(
+                        "rt::Value::Bool({}.is_truthy() || {}.is_truthy())",
+                        left, right
+                    ),
+                    _ => format!(
+                        "rt::Value::Bool(rt::BinOp::{:?}.apply(&{}, &{}))",
+                        op, left, right
+                    ),
+                }
+            }
+        })
+    }
+
+    fn args(&mut self, args: &[Expr]) -> Result<Vec<String>, TemplateError> {
+        args.iter().map(|a| self.expr(a)).collect()
+    }
+
+    // Context fields are read as `ctx.field`, so a field the context type lacks fails to compile
+    fn variable(&self, path: &[String]) -> Result<String, TemplateError> {
+        if let Some(place) = self.place(path)? {
+            return Ok(format!("rt::to_value(&{})", place));
+        }
+        match self.lookup(&path[0]) {
+            Some(Binding::Dynamic(local)) if path.len() == 1 => Ok(format!("{}.clone()", local)),
+            Some(Binding::Dynamic(local)) => {
+                let attrs: Vec<String> = path[1..].iter().map(|a| format!("{:?}", a)).collect();
+                Ok(format!("rt::attr(&{}, &[{}])", local, attrs.join(", ")))
+            }
+            _ => {
+                let e = TemplateError::new(ErrorKind::UndefinedVariable(path.join(".")));
+                Err(match did_you_mean(&path[0], &self.visible_names()) {
+                    Some(hint) => e.with_hint(hint),
+                    None => e,
+                })
+            }
+        }
+    }
+
+    // The Rust place for a variable that is typed, ie. a context field or an item of a loop
+    // over one; None if it holds a Value
+    fn place(&self, path: &[String]) -> Result<Option<String>, TemplateError> {
+        let base = match self.lookup(&path[0]) {
+            Some(Binding::Typed(place)) => place,
+            Some(_) => return Ok(None),
+            None if self.context_visible() => format!("ctx.{}", field(&path[0])),
+            None => return Ok(None),
+        };
+        let fields: String = path[1..].iter().map(|a| format!(".{}", field(a))).collect();
+        Ok(Some(base + &fields))
+    }
+
+    fn typed_place(&self, expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Variable(path) => self.place(path).ok().flatten(),
+            _ => None,
+        }
+    }
+
+    // The innermost binding of `name`; None if it is not bound or is hidden by a barrier
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        for (bound, binding) in self.bindings.iter().rev() {
+            match binding {
+                Binding::Barrier { .. } => return None,
+                _ if bound == name => return Some(binding.clone()),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn context_visible(&self) -> bool {
+        self.bindings
+            .iter()
+            .rev()
+            .all(|(_, binding)| match binding {
+                Binding::Barrier { ctx } => *ctx,
+                _ => true,
+            })
+    }
+
+    // Names bound above the innermost barrier, to suggest a fix for an undefined variable
+    fn visible_names(&self) -> Vec<String> {
+        self.bindings
+            .iter()
+            .rev()
+            .take_while(|(_, binding)| !matches!(binding, Binding::Barrier { .. }))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // `{{ super() }}` becomes the parent template's version of the current block
+    fn render_super(&mut self) -> Result<String, TemplateError> {
+        let (name, level) = self.block_stack.last().cloned().ok_or_else(|| {
+            ErrorKind::RenderError("`super()` used outside of a block".to_string())
+        })?;
+        let mut found = false;
+        let code = self.capture(|g| {
+            found = g.render_block(&name, level + 1)?;
+            Ok(())
+        })?;
+        if !found {
+            return Err(ErrorKind::RenderError(format!(
+                "`super()` used in block `{}`, which has no parent block",
+                name
+            ))
+            .into());
+        }
+        Ok(code)
+    }
+
+    // Finds a macro the way RenderState::find_macro does
+    fn find_macro(&mut self, name: &str) -> Result<Option<(Rc<Template>, String)>, TemplateError> {
+        let templates: Vec<Rc<Template>> = self
+            .current
+            .last()
+            .into_iter()
+            .chain(self.chain.iter())
+            .cloned()
+            .collect();
+        for template in templates {
+            if let Some((alias, macro_name)) = name.split_once('.') {
+                for import in &template.imports {
+                    if let Import::Module {
+                        template: module,
+                        alias: a,
+                    } = import
+                    {
+                        if a == alias {
+                            return self.imported_macro(module, macro_name).map(Some);
+                        }
+                    }
+                }
+                continue;
+            }
+            if template.macros.contains_key(name) {
+                return Ok(Some((template.clone(), name.to_string())));
+            }
+            for import in &template.imports {
+                if let Import::Names {
+                    template: module,
+                    names,
+                } = import
+                {
+                    if let Some((macro_name, _)) = names.iter().find(|(_, local)| local == name) {
+                        return self.imported_macro(module, macro_name).map(Some);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn imported_macro(
+        &mut self,
+        module: &str,
+        name: &str,
+    ) -> Result<(Rc<Template>, String), TemplateError> {
+        let template = self.load(module)?;
+        if !template.macros.contains_key(name) {
+            let e = ErrorKind::RenderError(format!("`{}` has no macro `{}`", module, name));
+            let hint = did_you_mean(name, template.macros.keys());
+            return Err(match hint {
+                Some(hint) => TemplateError::new(e).with_hint(hint),
+                None => e.into(),
+            });
+        }
+        Ok((template, name.to_string()))
+    }
+
+    // Macro calls are inlined: the arguments are evaluated into locals, then the body is
+    // compiled in a capture block that only sees the parameters
+    fn inline_macro(
+        &mut self,
+        template: Rc<Template>,
+        name: &str,
+        args: &[Expr],
+    ) -> Result<String, TemplateError> {
+        let m = &template.macros[name];
+        if args.len() > m.params.len() {
+            return Err(ErrorKind::RenderError(format!(
+                "Macro `{}` takes {} arguments, got {}",
+                name,
+                m.params.len(),
+                args.len()
+            ))
+            .into());
+        }
+        let key = format!("{}:{}", template.name, name);
+        if self.macro_stack.contains(&key) {
+            return Err(ErrorKind::RenderError(format!(
+                "Macro `{}` calls itself, which cannot be compiled ahead of time",
+                name
+            ))
+            .into());
+        }
+        let args = self.args(args)?;
+        let n = self.next_id();
+        self.capture(|g| {
+            for (i, arg) in args.iter().enumerate() {
+                g.line(&format!("let arg_{}_{} = {};", n, i, arg));
+            }
+            g.macro_stack.push(key);
+            g.current.push(template.clone());
+            let barrier = vec![(String::new(), Binding::Barrier { ctx: false })];
+            let result = g.frame(barrier, |g| {
+                for (i, (param, default)) in m.params.iter().enumerate() {
+                    let value = match default {
+                        _ if i < args.len() => format!("arg_{}_{}", n, i),
+                        Some(default) => g.expr(default)?,
+                        None => format!("rt::Value::Undefined({:?}.to_string())", param),
+                    };
+                    let local = format!("param_{}_{}", n, i);
+                    g.line(&format!("let {} = {};", local, value));
+                    g.bindings.push((param.clone(), Binding::Dynamic(local)));
+                }
+                g.nodes(&m.body)
+            });
+            g.current.pop();
+            g.macro_stack.pop();
+            result
+        })
+    }
+}
+
+// Helpers
+impl<'a> Generator<'a> {
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.code.push_str(&"    ".repeat(self.indent));
+            self.code.push_str(text);
+        }
+        self.code.push('\n');
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.counter += 1;
+        self.counter
+    }
+
+    // Names of the constants holding the name and source of the template being compiled
+    fn location_constants(&mut self) -> (String, String) {
+        let template = match self.current.last() {
+            Some(template) => template.clone(),
+            None => return ("\"\"".to_string(), "\"\"".to_string()),
+        };
+        let i = match self.constants.iter().position(|t| t.name == template.name) {
+            Some(i) => i,
+            None => {
+                self.constants.push(template);
+                self.constants.len() - 1
+            }
+        };
+        (format!("TEMPLATE_{}", i), format!("SOURCE_{}", i))
+    }
+
+    // Points an error that has no location yet at `span` in the template being compiled
+    fn locate(&self, e: TemplateError, span: Span) -> TemplateError {
+        match self.current.last() {
+            Some(template) if !e.has_location() => {
+                e.with_location(&template.name, &template.source, span.line, span.column)
+            }
+            _ => e,
+        }
+    }
+}
+
+// A template literal as Rust code
+fn literal(value: &Value) -> Result<String, TemplateError> {
+    Ok(match value {
+        Value::Str(s) => format!("rt::Value::Str({:?}.to_string())", s),
+        Value::Number(n) => format!("rt::Value::Number({:?})", n),
+        Value::Bool(b) => format!("rt::Value::Bool({})", b),
+        Value::Null => "rt::Value::Null".to_string(),
+        other => {
+            return Err(ErrorKind::RenderError(format!(
+                "Cannot compile a {} literal",
+                other.type_name()
+            ))
+            .into())
+        }
+    })
+}
+
+// A struct field name; Rust keywords need the raw identifier syntax, eg. `r#type`
+fn field(name: &str) -> String {
+    const KEYWORDS: [&str; 35] = [
+        "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern",
+        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use",
+        "where", "while", "yield",
+    ];
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
@@ -0,0 +1,150 @@
+// Ahead-of-time compilation: turns templates into Rust functions that render them from a typed
+// context struct, eg. from a build script. Templates are parsed by the same parser the renderer
+// uses, so both accept exactly the same syntax.
+//
+// A variable `user.name` is read as the field `ctx.user.name`, so a variable the context type
+// lacks is a compile error of the generated code. Field types are turned into template values with
+// runtime::ToValue, which is implemented for strings, numbers, bools, Option, Vec and maps.
+// Variables introduced by `set`, macro parameters and `include ... with` hold Values and behave
+// as in lenient rendering. Extends, blocks, includes and macro calls are resolved at build time,
+// so included templates must be named by a string literal and macros cannot call themselves.
+//
+// In build.rs:
+//     Compiler::new(FileSystemLoader::new("templates"))
+//         .template("page.html", "render_page", "crate::Page")
+//         .write_to(Path::new(&env::var("OUT_DIR")?).join("templates.rs"))?;
+// and in the crate:
+//     mod templates { include!(concat!(env!("OUT_DIR"), "/templates.rs")); }
+mod codegen;
+pub mod runtime;
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::loader::Loader;
+use codegen::Generator;
+
+// A template to compile, the function to generate and the type of its context
+struct Entry {
+    template: String,
+    function: String,
+    context: String,
+}
+
+pub struct Compiler {
+    loader: Box<dyn Loader>,
+    entries: Vec<Entry>,
+}
+
+impl Compiler {
+    pub fn new<L: Loader + 'static>(loader: L) -> Self {
+        Compiler {
+            loader: Box::new(loader),
+            entries: Vec::new(),
+        }
+    }
+
+    // Generates `function(ctx: &context) -> Result<String, TemplateError>` and
+    // `function_to(ctx, out: &mut impl Write)` rendering `template`. `context` is the path of
+    // the context type as seen from where the generated code is included, eg. "crate::Page".
+    pub fn template(mut self, template: &str, function: &str, context: &str) -> Self {
+        self.entries.push(Entry {
+            template: template.to_string(),
+            function: function.to_string(),
+            context: context.to_string(),
+        });
+        self
+    }
+
+    // The Rust source of all the functions. Syntax errors, unknown filters and macros, and
+    // variables that can never be defined are reported here, with their location.
+    pub fn generate(&self) -> Result<String, TemplateError> {
+        let mut generator = Generator::new(self.loader.as_ref());
+        for entry in &self.entries {
+            generator.function(&entry.template, &entry.function, &entry.context)?;
+        }
+        Ok(generator.finish())
+    }
+
+    // Writes the generated source to `path`, leaving the file untouched if it is unchanged
+    // so that cargo does not rebuild the crate for nothing
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), TemplateError> {
+        let code = self.generate()?;
+        let path = path.as_ref();
+        if fs::read_to_string(path).ok().as_deref() == Some(code.as_str()) {
+            return Ok(());
+        }
+        fs::write(path, code)
+            .map_err(|e| ErrorKind::WriteError(format!("Unable to write {:?}: {}", path, e)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::MemoryLoader;
+
+    fn generate(templates: &[(&str, &str)]) -> Result<String, TemplateError> {
+        let mut loader = MemoryLoader::new();
+        for (name, source) in templates {
+            loader.add(name, source);
+        }
+        Compiler::new(loader)
+            .template(templates[0].0, "render_page", "crate::Page")
+            .generate()
+    }
+
+    #[test]
+    fn context_fields_are_typed_test() {
+        let code = generate(&[(
+            "page.html",
+            "<h1>{{ user.name | upper }}</h1>{% for item in items %}{{ loop.index }}{{ item.type }}{% endfor %}",
+        )])
+        .unwrap();
+        assert!(code.contains("pub fn render_page(ctx: &crate::Page)"));
+        assert!(code.contains("pub fn render_page_to<W: std::io::Write>"));
+        assert!(code.contains("rt::filter(\"upper\", &rt::to_value(&ctx.user.name), &[])?"));
+        assert!(code.contains("rt::items_of(&ctx.items)"));
+        assert!(code.contains("rt::to_value(&v_item.r#type)"));
+        assert!(code.contains("rt::attr(&v_loop, &[\"index\"])"));
+    }
+    #[test]
+    fn inheritance_includes_and_macros_are_inlined_test() {
+        let code = generate(&[
+            (
+                "page.html",
+                "{% extends \"base.html\" %}{% from \"ui.html\" import button %}\
+                 {% block body %}{{ super() }}{{ button(label) }}{% endblock %}",
+            ),
+            (
+                "base.html",
+                "{% block body %}<p>base</p>{% endblock %}{% include \"footer.html\" only %}",
+            ),
+            (
+                "ui.html",
+                "{% macro button(text, kind=\"ok\") %}<b>{{ text }}</b>{% endmacro %}",
+            ),
+            ("footer.html", "<footer></footer>"),
+        ])
+        .unwrap();
+        for text in ["<p>base</p>", "<b>", "<footer></footer>"] {
+            assert!(code.contains(&format!("{:?}", text)), "missing {}", text);
+        }
+        assert!(code.contains("let arg_"));
+        assert!(!code.contains("ctx.text"));
+    }
+    #[test]
+    fn build_errors_are_located_test() {
+        let error = |source: &str| generate(&[("t.html", source)]).unwrap_err().to_string();
+        assert_eq!(
+            "t.html:1:4: Unknown filter `shout`\n    | {{ name | shout }}\n    |    ^",
+            error("{{ name | shout }}")
+        );
+        assert!(error("{% macro f() %}{{ nme }}{% endmacro %}{{ f() }}")
+            .starts_with("t.html:1:19: Undefined variable `nme`"));
+        assert!(error("{% if x %}{% set y = 1 %}{% endif %}").contains("`set` inside `if`"));
+        assert!(error("{% macro f() %}{{ f() }}{% endmacro %}{{ f() }}").contains("calls itself"));
+        assert!(error("{% include name %}").contains("string literal"));
+    }
+}
@@ -0,0 +1,235 @@
+// Helpers called by the Rust code the AOT compiler generates. They give compiled templates the
+// same value semantics as the runtime renderer: output formatting, truthiness, filters and loops.
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::OnceLock;
+
+use crate::filters::FilterRegistry;
+
+// Re-exported so that generated code only needs to import this module
+pub use crate::error::{ErrorKind, TemplateError};
+pub use crate::expr::ast::BinOp;
+pub use crate::value::Value;
+
+// Converts context fields to template values. Implement it for your own types to use them
+// whole in a template, eg. `{{ user }}`; reading their fields, eg. `{{ user.name }}`, does not need it.
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Str(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Str(self.clone())
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+macro_rules! number_to_value {
+    ($($t:ty),*) => {
+        $(impl ToValue for $t {
+            fn to_value(&self) -> Value {
+                Value::Number(*self as f64)
+            }
+        })*
+    };
+}
+
+number_to_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for [T] {
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(|v| v.to_value()).collect())
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for HashMap<String, T> {
+    fn to_value(&self) -> Value {
+        Value::Map(
+            self.iter()
+                .map(|(k, v)| (k.clone(), v.to_value()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: ToValue> ToValue for BTreeMap<String, T> {
+    fn to_value(&self) -> Value {
+        Value::Map(
+            self.iter()
+                .map(|(k, v)| (k.clone(), v.to_value()))
+                .collect(),
+        )
+    }
+}
+
+// What `for` iterates over when the iterable is a typed context field, in the order the
+// renderer uses: the items of a list, the sorted keys of a map, nothing for a missing value
+pub trait Items<'a> {
+    type Item;
+    fn items(self) -> Vec<Self::Item>;
+}
+
+impl<'a, T> Items<'a> for &'a [T] {
+    type Item = &'a T;
+    fn items(self) -> Vec<&'a T> {
+        self.iter().collect()
+    }
+}
+
+impl<'a, T> Items<'a> for &'a Vec<T> {
+    type Item = &'a T;
+    fn items(self) -> Vec<&'a T> {
+        self.iter().collect()
+    }
+}
+
+impl<'a, T> Items<'a> for &'a HashMap<String, T> {
+    type Item = &'a String;
+    fn items(self) -> Vec<&'a String> {
+        let mut keys: Vec<&String> = self.keys().collect();
+        keys.sort();
+        keys
+    }
+}
+
+impl<'a, T> Items<'a> for &'a BTreeMap<String, T> {
+    type Item = &'a String;
+    fn items(self) -> Vec<&'a String> {
+        self.keys().collect()
+    }
+}
+
+impl<'a, T> Items<'a> for &'a Option<T>
+where
+    &'a T: Items<'a>,
+{
+    type Item = <&'a T as Items<'a>>::Item;
+    fn items(self) -> Vec<Self::Item> {
+        self.as_ref().map(Items::items).unwrap_or_default()
+    }
+}
+
+pub fn items_of<'a, T: Items<'a>>(iterable: T) -> Vec<T::Item> {
+    iterable.items()
+}
+
+pub fn to_value<T: ToValue + ?Sized>(value: &T) -> Value {
+    value.to_value()
+}
+
+pub fn write_str<W: Write + ?Sized>(out: &mut W, text: &str) -> Result<(), TemplateError> {
+    out.write_all(text.as_bytes())
+        .map_err(|e| ErrorKind::WriteError(e.to_string()).into())
+}
+
+// Flushes the output once the whole template is written
+pub fn finish<W: Write + ?Sized>(out: &mut W) -> Result<(), TemplateError> {
+    out.flush()
+        .map_err(|e| ErrorKind::WriteError(e.to_string()).into())
+}
+
+pub fn write_value<W: Write + ?Sized>(out: &mut W, value: &Value) -> Result<(), TemplateError> {
+    write_str(out, &value.to_string())
+}
+
+// Applies a built-in filter
+pub fn filter(name: &str, value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    static FILTERS: OnceLock<FilterRegistry> = OnceLock::new();
+    let filters = FILTERS.get_or_init(FilterRegistry::new);
+    let filter = filters
+        .get(name)
+        .ok_or_else(|| ErrorKind::UnknownFilter(name.to_string()))?;
+    filter.apply(value, args)
+}
+
+// Reads `value.a.b`; missing attributes are undefined and render as nothing
+pub fn attr(value: &Value, path: &[&str]) -> Value {
+    let mut value = value;
+    for (i, attr) in path.iter().enumerate() {
+        value = match value.get(attr) {
+            Some(v) => v,
+            None => return Value::Undefined(path[..=i].join(".")),
+        };
+    }
+    value.clone()
+}
+
+// The items of a `for` loop over a template value
+pub fn items(value: Value) -> Result<Vec<Value>, TemplateError> {
+    let type_name = value.type_name();
+    value.into_items().ok_or_else(|| {
+        ErrorKind::RenderError(format!("Cannot iterate over a {}", type_name)).into()
+    })
+}
+
+pub fn loop_info(i: usize, length: usize) -> Value {
+    crate::render::loop_info(i, length)
+}
+
+// Evaluates an expression, pointing an error that has no location yet at where the
+// expression is in the template
+pub fn at<T, F>(
+    template: &str,
+    source: &str,
+    line: usize,
+    column: usize,
+    f: F,
+) -> Result<T, TemplateError>
+where
+    F: FnOnce() -> Result<T, TemplateError>,
+{
+    f().map_err(|e| {
+        if e.has_location() {
+            e
+        } else {
+            e.with_location(template, source, line, column)
+        }
+    })
+}
+
+pub fn into_string(buffer: Vec<u8>) -> String {
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+// Output written to a buffer by `super()` or a macro call, as a value
+pub fn captured(buffer: Vec<u8>) -> Value {
+    Value::Str(into_string(buffer))
+}
@@ -0,0 +1,214 @@
+// Loads template context data from JSON, TOML and YAML files
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl DataFormat {
+    // Picks the format from a file extension, eg. "data.yml"
+    pub fn from_path(path: &Path) -> Option<DataFormat> {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| e.parse().ok())
+    }
+}
+
+impl FromStr for DataFormat {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(DataFormat::Json),
+            "toml" => Ok(DataFormat::Toml),
+            "yaml" | "yml" => Ok(DataFormat::Yaml),
+            _ => Err(ErrorKind::DataError(format!(
+                "Unknown data format `{}`, expected json, toml or yaml",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
+// Parses data in the given format. `origin` names the source in error messages, eg. "data.json";
+// errors are reported as "origin:line:column: message".
+pub fn parse_data(
+    source: &str,
+    format: DataFormat,
+    origin: &str,
+) -> Result<HashMap<String, Value>, TemplateError> {
+    let value = match format {
+        DataFormat::Json => {
+            let json: serde_json::Value = serde_json::from_str(source).map_err(|e| {
+                ErrorKind::DataError(format!("{}:{}:{}: {}", origin, e.line(), e.column(), e))
+            })?;
+            from_json(json)
+        }
+        DataFormat::Toml => {
+            let toml: toml::Value = toml::from_str(source).map_err(|e| {
+                let (line, column) = e
+                    .span()
+                    .map(|span| line_and_column(source, span.start))
+                    .unwrap_or((1, 1));
+                ErrorKind::DataError(format!("{}:{}:{}: {}", origin, line, column, e.message()))
+            })?;
+            from_toml(toml)
+        }
+        DataFormat::Yaml => {
+            let yaml: serde_yaml::Value = serde_yaml::from_str(source).map_err(|e| {
+                let (line, column) = e
+                    .location()
+                    .map(|l| (l.line(), l.column()))
+                    .unwrap_or((1, 1));
+                ErrorKind::DataError(format!("{}:{}:{}: {}", origin, line, column, e))
+            })?;
+            from_yaml(yaml)
+        }
+    };
+    match value {
+        Value::Map(map) => Ok(map),
+        Value::Null => Ok(HashMap::new()),
+        other => Err(ErrorKind::DataError(format!(
+            "{}: expected a table of values at the top level, found a {}",
+            origin,
+            other.type_name()
+        ))
+        .into()),
+    }
+}
+
+// Merges `other` into `base`. Nested maps are merged key by key; anything else is replaced.
+pub fn merge(base: &mut HashMap<String, Value>, other: HashMap<String, Value>) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Map(existing)), Value::Map(incoming)) => merge(existing, incoming),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// Sets a value by dotted path, eg. "site.title", creating maps on the way
+pub fn set_path(base: &mut HashMap<String, Value>, path: &str, value: Value) {
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let last = keys.pop().unwrap_or_default();
+    let mut map = base;
+    for key in keys {
+        let entry = map
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Map(HashMap::new()));
+        if !matches!(entry, Value::Map(_)) {
+            *entry = Value::Map(HashMap::new());
+        }
+        map = match entry {
+            Value::Map(m) => m,
+            _ => unreachable!(),
+        };
+    }
+    map.insert(last.to_string(), value);
+}
+
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+fn from_json(json: serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or_default()),
+        serde_json::Value::String(s) => Value::Str(s),
+        serde_json::Value::Array(items) => Value::List(items.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(map) => {
+            Value::Map(map.into_iter().map(|(k, v)| (k, from_json(v))).collect())
+        }
+    }
+}
+
+fn from_toml(toml: toml::Value) -> Value {
+    match toml {
+        toml::Value::String(s) => Value::Str(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::Number(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::Str(d.to_string()),
+        toml::Value::Array(items) => Value::List(items.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => {
+            Value::Map(table.into_iter().map(|(k, v)| (k, from_toml(v))).collect())
+        }
+    }
+}
+
+fn from_yaml(yaml: serde_yaml::Value) -> Value {
+    match yaml {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => Value::Number(n.as_f64().unwrap_or_default()),
+        serde_yaml::Value::String(s) => Value::Str(s),
+        serde_yaml::Value::Sequence(items) => {
+            Value::List(items.into_iter().map(from_yaml).collect())
+        }
+        serde_yaml::Value::Mapping(map) => Value::Map(
+            map.into_iter()
+                .map(|(k, v)| (yaml_key(k), from_yaml(v)))
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+fn yaml_key(key: serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(s) => s,
+        other => from_yaml(other).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_formats_test() {
+        let json = parse_data(r#"{"name": "Bob", "n": 2}"#, DataFormat::Json, "d.json").unwrap();
+        let toml = parse_data("name = \"Bob\"\nn = 2", DataFormat::Toml, "d.toml").unwrap();
+        let yaml = parse_data("name: Bob\nn: 2", DataFormat::Yaml, "d.yaml").unwrap();
+        assert_eq!(json, toml);
+        assert_eq!(json, yaml);
+    }
+    #[test]
+    fn json_error_location_test() {
+        let err = parse_data("{\n  \"a\": }", DataFormat::Json, "d.json").unwrap_err();
+        match err.kind {
+            ErrorKind::DataError(message) => assert!(message.starts_with("d.json:2:8:")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    #[test]
+    fn merge_and_set_path_test() {
+        let mut base = parse_data(
+            r#"{"site": {"title": "A", "lang": "en"}}"#,
+            DataFormat::Json,
+            "a",
+        )
+        .unwrap();
+        let other = parse_data(r#"{"site": {"title": "B"}}"#, DataFormat::Json, "b").unwrap();
+        merge(&mut base, other);
+        set_path(&mut base, "site.lang", Value::from("fr"));
+        let site = &base["site"];
+        assert_eq!(Some(&Value::from("B")), site.get("title"));
+        assert_eq!(Some(&Value::from("fr")), site.get("lang"));
+    }
+}
@@ -0,0 +1,398 @@
+// An Environment loads every template below a directory, compiles each one once and
+// renders them by name. In development mode a background thread watches the directory
+// and recompiles templates when they are added, modified or removed.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::filters::{Filter, FilterRegistry};
+use crate::i18n::Translations;
+use crate::loader::{FileSystemLoader, Loader};
+use crate::render::{Renderer, TemplateSource, UndefinedBehavior};
+use crate::sandbox::Sandbox;
+use crate::template::Template;
+use crate::value::Value;
+
+// How often the development watcher checks the template directory
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// How much of a file is read to tell whether it is a text file
+const SNIFF_LEN: u64 = 8192;
+
+// A compiled template with the file state it was compiled from
+struct CacheEntry {
+    template: Arc<Template>,
+    stamp: FileStamp,
+}
+
+// Modification time and size, used to spot changed files
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+type Cache = Arc<RwLock<HashMap<String, CacheEntry>>>;
+
+pub struct Environment {
+    root: PathBuf,
+    filters: FilterRegistry,
+    undefined: UndefinedBehavior,
+    sandbox: Option<Sandbox>,
+    translations: Option<Translations>,
+    cache: Cache,
+    // Dropped to stop the watcher, which wakes up at once
+    stop: Option<Sender<()>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl Environment {
+    // Loads and compiles all templates under `dir`. Fails on the first template that does not parse.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, TemplateError> {
+        let root = dir.as_ref().to_path_buf();
+        let cache: Cache = Arc::new(RwLock::new(HashMap::new()));
+        for (name, stamp) in scan(&root)? {
+            let template = compile(&root, &name)?;
+            cache
+                .write()
+                .unwrap()
+                .insert(name, CacheEntry { template, stamp });
+        }
+        Ok(Environment {
+            root,
+            filters: FilterRegistry::new(),
+            undefined: UndefinedBehavior::default(),
+            sandbox: None,
+            translations: None,
+            cache,
+            stop: None,
+            watcher: None,
+        })
+    }
+
+    // Like new, but also watches the directory and recompiles templates as they change
+    pub fn development<P: AsRef<Path>>(dir: P) -> Result<Self, TemplateError> {
+        let mut env = Environment::new(dir)?;
+        env.watch(WATCH_INTERVAL);
+        Ok(env)
+    }
+
+    // Starts the watcher thread, checking the directory every `interval`
+    pub fn watch(&mut self, interval: Duration) {
+        if self.watcher.is_some() {
+            return;
+        }
+        let root = self.root.clone();
+        let cache = Arc::clone(&self.cache);
+        let (stop, stopped) = mpsc::channel::<()>();
+        self.stop = Some(stop);
+        self.watcher = Some(thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                reload_changed(&root, &cache);
+            }
+        }));
+    }
+
+    pub fn add_filter<F: Filter + 'static>(&mut self, name: &str, filter: F) {
+        self.filters.register(name, filter);
+    }
+
+    pub fn filters(&self) -> &FilterRegistry {
+        &self.filters
+    }
+
+    pub fn set_undefined(&mut self, behavior: UndefinedBehavior) {
+        self.undefined = behavior;
+    }
+
+    // Renders every template with the sandbox's limits
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.sandbox = Some(sandbox);
+    }
+
+    // Translates `{% trans %}` blocks and `_("...")`, see Renderer::translations
+    pub fn set_translations(&mut self, translations: Translations) {
+        self.translations = Some(translations);
+    }
+
+    // Names of all compiled templates, eg. "partials/nav.html"
+    pub fn template_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.cache.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn render(
+        &self,
+        name: &str,
+        context: &HashMap<String, Value>,
+    ) -> Result<String, TemplateError> {
+        self.renderer().render(name, context)
+    }
+
+    // Streams the rendered template into `out`, see Renderer::render_to
+    pub fn render_to<W: Write>(
+        &self,
+        name: &str,
+        context: &HashMap<String, Value>,
+        out: &mut W,
+    ) -> Result<(), TemplateError> {
+        self.renderer().render_to(name, context, out)
+    }
+
+    fn renderer(&self) -> Renderer<'_> {
+        let mut renderer = Renderer::new(self, &self.filters).undefined(self.undefined);
+        if let Some(sandbox) = &self.sandbox {
+            renderer = renderer.sandbox(sandbox);
+        }
+        if let Some(translations) = &self.translations {
+            renderer = renderer.translations(translations);
+        }
+        renderer
+    }
+}
+
+impl TemplateSource for Environment {
+    fn get_template(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        if let Some(entry) = self.cache.read().unwrap().get(name) {
+            return Ok(Arc::clone(&entry.template));
+        }
+        // Not cached: either a template the watcher has not picked up yet, or one that failed
+        // to compile. Compiling it here reports the real error to the caller.
+        let path = FileSystemLoader::new(&self.root)
+            .path_for(name)
+            .ok_or_else(|| ErrorKind::TemplateNotFound(name.to_string()))?;
+        let template = compile(&self.root, name)?;
+        if let Ok(stamp) = stamp_of(&path) {
+            self.cache.write().unwrap().insert(
+                name.to_string(),
+                CacheEntry {
+                    template: Arc::clone(&template),
+                    stamp,
+                },
+            );
+        }
+        Ok(template)
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
+
+fn compile(root: &Path, name: &str) -> Result<Arc<Template>, TemplateError> {
+    let source = FileSystemLoader::new(root).load(name)?;
+    Ok(Arc::new(Template::parse(name, &source)?))
+}
+
+fn stamp_of(path: &Path) -> Result<FileStamp, TemplateError> {
+    let metadata = fs::metadata(path)
+        .map_err(|e| ErrorKind::RenderError(format!("Unable to read {:?}: {}", path, e)))?;
+    Ok(FileStamp {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+    })
+}
+
+// Lists every template below root as (template name, stamp). Names use '/' as separator.
+// Hidden files and folders, editor backups and files that are not UTF-8 text, such as images
+// next to the templates, are left out. Linked folders are followed, each folder only once so
+// that a link to a parent does not loop.
+fn scan(root: &Path) -> Result<Vec<(String, FileStamp)>, TemplateError> {
+    let mut found = Vec::new();
+    let mut visited = HashSet::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        if !visited.insert(dir.canonicalize().unwrap_or_else(|_| dir.clone())) {
+            continue;
+        }
+        let entries = fs::read_dir(&dir).map_err(|e| {
+            ErrorKind::RenderError(format!("Unable to read directory {:?}: {}", dir, e))
+        })?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name.starts_with('.') || file_name.ends_with('~') {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !is_text(&path) {
+                continue;
+            }
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            found.push((name, stamp_of(&path)?));
+        }
+    }
+    Ok(found)
+}
+
+// Whether the start of the file is UTF-8 without NUL bytes. A character cut at the end of
+// what is read does not count against it.
+fn is_text(path: &Path) -> bool {
+    let mut start = Vec::new();
+    let read = fs::File::open(path).and_then(|f| f.take(SNIFF_LEN).read_to_end(&mut start));
+    if read.is_err() || start.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(&start) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && start.len() as u64 == SNIFF_LEN,
+    }
+}
+
+// One pass of the watcher: recompile new or modified templates and forget deleted ones.
+// Templates that fail to compile are dropped from the cache, so rendering them reports the error.
+fn reload_changed(root: &Path, cache: &Cache) {
+    let files = match scan(root) {
+        Ok(files) => files,
+        Err(_) => return,
+    };
+    let changed: Vec<(String, FileStamp)> = {
+        let cache = cache.read().unwrap();
+        files
+            .iter()
+            .filter(|(name, stamp)| cache.get(name).map(|e| e.stamp) != Some(*stamp))
+            .cloned()
+            .collect()
+    };
+    let mut cache = cache.write().unwrap();
+    cache.retain(|name, _| files.iter().any(|(n, _)| n == name));
+    for (name, stamp) in changed {
+        match compile(root, &name) {
+            Ok(template) => {
+                cache.insert(name, CacheEntry { template, stamp });
+            }
+            Err(_) => {
+                cache.remove(&name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Creates an empty directory under the system temp dir for one test
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("template-engine-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn scan_and_render_test() {
+        let dir = temp_dir("scan");
+        fs::write(dir.join("partials/nav.html"), "<nav>{{ name }}</nav>").unwrap();
+        fs::write(
+            dir.join("page.html"),
+            r#"{% include "partials/nav.html" %}"#,
+        )
+        .unwrap();
+        let env = Environment::new(&dir).unwrap();
+        assert_eq!(
+            vec!["page.html".to_string(), "partials/nav.html".to_string()],
+            env.template_names()
+        );
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), Value::from("Bob"));
+        assert_eq!(
+            Ok("<nav>Bob</nav>".to_string()),
+            env.render("page.html", &context)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skip_non_templates_test() {
+        let dir = temp_dir("skip");
+        fs::write(dir.join("page.html"), "hello").unwrap();
+        fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0, 0xff]).unwrap();
+        fs::write(dir.join("latin1.po"), b"msgstr \"caf\xe9\"").unwrap();
+        fs::write(dir.join(".page.html.swp"), "{% if").unwrap();
+        fs::write(dir.join("page.html~"), "{% if").unwrap();
+        let mut env = Environment::new(&dir).unwrap();
+        assert_eq!(vec!["page.html".to_string()], env.template_names());
+        env.watch(Duration::from_millis(20));
+        fs::write(dir.join("partials/icon.gif"), b"GIF89a\0\0").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(vec!["page.html".to_string()], env.template_names());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hot_reload_test() {
+        let dir = temp_dir("reload");
+        fs::write(dir.join("page.html"), "v1").unwrap();
+        let mut env = Environment::new(&dir).unwrap();
+        env.watch(Duration::from_millis(20));
+        fs::write(dir.join("page.html"), "version 2").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            Ok("version 2".to_string()),
+            env.render("page.html", &HashMap::new())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stop_watching_test() {
+        let dir = temp_dir("stop");
+        let mut env = Environment::new(&dir).unwrap();
+        env.watch(Duration::from_secs(10));
+        let start = std::time::Instant::now();
+        drop(env);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop_test() {
+        let dir = temp_dir("symlink");
+        fs::write(dir.join("partials/nav.html"), "<nav>").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("partials/root")).unwrap();
+        let env = Environment::new(&dir).unwrap();
+        assert_eq!(vec!["partials/nav.html".to_string()], env.template_names());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_error_on_load_test() {
+        let dir = temp_dir("error");
+        fs::write(dir.join("bad.html"), "{% if x %}").unwrap();
+        let err = Environment::new(&dir).err().unwrap();
+        assert_eq!(
+            ErrorKind::SyntaxError("Missing {% endif %}".to_string()),
+            err.kind
+        );
+        assert_eq!(
+            ("bad.html".to_string(), 1),
+            err.location.map(|l| (l.template, l.line)).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
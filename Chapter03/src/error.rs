@@ -0,0 +1,195 @@
+// Errors raised while parsing and rendering templates.
+// A TemplateError carries what went wrong (ErrorKind) and, where known, where it happened:
+// template name, line, column, the offending source line with a caret, and a hint.
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    ParseError(String),        // 表达式语法错误
+    UnknownFilter(String),     // 未注册的过滤器
+    FilterError(String),       // 过滤器执行失败
+    SyntaxError(String),       // 模板语法错误
+    TemplateNotFound(String),  // 找不到模板
+    UndefinedVariable(String), // 严格模式下的未定义变量
+    // A chain of extends/include that loops back on itself, eg. "a.html -> b.html -> a.html"
+    CyclicTemplate(String),
+    RenderError(String), // 渲染失败
+    DataError(String),   // 上下文数据文件错误
+    WriteError(String),  // 写入输出失败
+    // A sandbox limit was hit, eg. too many loop iterations or too much output
+    LimitExceeded(String),
+    NotAllowed(String), // 沙箱不允许的过滤器或属性
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::ParseError(e) => write!(f, "Error parsing expression: {}", e),
+            ErrorKind::UnknownFilter(name) => write!(f, "Unknown filter `{}`", name),
+            ErrorKind::FilterError(e) => write!(f, "Error applying filter: {}", e),
+            ErrorKind::SyntaxError(e) => write!(f, "Syntax error: {}", e),
+            ErrorKind::TemplateNotFound(name) => write!(f, "Template `{}` not found", name),
+            ErrorKind::UndefinedVariable(name) => write!(f, "Undefined variable `{}`", name),
+            ErrorKind::CyclicTemplate(chain) => {
+                write!(f, "Cyclic template inheritance or include: {}", chain)
+            }
+            ErrorKind::RenderError(e) => write!(f, "Error rendering template: {}", e),
+            ErrorKind::DataError(e) => write!(f, "Error reading data: {}", e),
+            ErrorKind::WriteError(e) => write!(f, "Error writing output: {}", e),
+            ErrorKind::LimitExceeded(e) => write!(f, "Limit exceeded: {}", e),
+            ErrorKind::NotAllowed(e) => write!(f, "Not allowed in sandbox: {}", e),
+        }
+    }
+}
+
+// Where in a template an error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub template: String,
+    pub line: usize,
+    pub column: usize,
+    // The source line followed by a line with a caret under the error column
+    pub snippet: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    pub kind: ErrorKind,
+    // Boxed to keep Result<_, TemplateError> small
+    pub location: Option<Box<Location>>,
+    pub hint: Option<String>,
+}
+
+impl TemplateError {
+    pub fn new(kind: ErrorKind) -> Self {
+        TemplateError {
+            kind,
+            location: None,
+            hint: None,
+        }
+    }
+
+    pub fn has_location(&self) -> bool {
+        self.location.is_some()
+    }
+
+    // Records where the error happened. `source` is the template text, used for the snippet.
+    pub fn with_location(
+        mut self,
+        template: &str,
+        source: &str,
+        line: usize,
+        column: usize,
+    ) -> Self {
+        let snippet = source.lines().nth(line - 1).map(|text| {
+            let caret_at: String = text
+                .chars()
+                .take(column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            format!("{}\n{}^", text, caret_at)
+        });
+        self.location = Some(Box::new(Location {
+            template: template.to_string(),
+            line,
+            column,
+            snippet,
+        }));
+        self
+    }
+
+    pub fn with_hint<S: Into<String>>(mut self, hint: S) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+impl From<ErrorKind> for TemplateError {
+    fn from(kind: ErrorKind) -> Self {
+        TemplateError::new(kind)
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(location) => {
+                write!(
+                    f,
+                    "{}:{}:{}: {}",
+                    location.template, location.line, location.column, self.kind
+                )?;
+                for line in location.snippet.iter().flat_map(|s| s.lines()) {
+                    write!(f, "\n    | {}", line)?;
+                }
+            }
+            None => write!(f, "{}", self.kind)?,
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n    = hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for TemplateError {}
+
+// Suggests the candidate closest to `name` by edit distance, if any is close enough
+pub fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(b.1)))
+        .map(|(_, c)| format!("did you mean `{}`?", c))
+}
+
+// Levenshtein distance between two strings
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn edit_distance_test() {
+        assert_eq!(0, edit_distance("name", "name"));
+        assert_eq!(1, edit_distance("nme", "name"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+    }
+    #[test]
+    fn did_you_mean_test() {
+        let candidates = vec!["city".to_string(), "name".to_string()];
+        assert_eq!(
+            Some("did you mean `name`?".to_string()),
+            did_you_mean("nmae", &candidates)
+        );
+        assert_eq!(None, did_you_mean("zzz", &candidates));
+    }
+    #[test]
+    fn display_with_snippet_test() {
+        let err = TemplateError::new(ErrorKind::UndefinedVariable("nme".to_string()))
+            .with_location("page.html", "<h1>\n<p>{{ nme }}</p>", 2, 7)
+            .with_hint("did you mean `name`?");
+        assert_eq!(
+            "page.html:2:7: Undefined variable `nme`\n    | <p>{{ nme }}</p>\n    |       ^\n    = hint: did you mean `name`?",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,245 @@
+/// This module contains the AST nodes built by the expression Parser and evaluates them against a context
+// Standard lib
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Other internal modules
+use crate::error::{ErrorKind, TemplateError};
+use crate::filters::FilterRegistry;
+use crate::value::Value;
+
+// Anything that variables can be looked up in while evaluating an expression.
+// Function calls such as `super()` are dispatched to the scope as well.
+pub trait Scope {
+    fn lookup(&self, name: &str) -> Option<&Value>;
+
+    // Names visible in the scope, used to suggest a fix for an undefined variable
+    fn names(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Called when `name` (eg. "user.nmae") is not defined; `candidates` are the names that are.
+    // The default is lenient: the variable evaluates to Value::Undefined and renders as nothing.
+    fn undefined(&self, name: &str, _candidates: &[String]) -> Result<Value, TemplateError> {
+        Ok(Value::Undefined(name.to_string()))
+    }
+
+    // Lets a sandboxed scope refuse filters and attributes that are not allowed
+    fn check_filter(&self, _name: &str) -> Result<(), TemplateError> {
+        Ok(())
+    }
+
+    fn check_attribute(&self, _variable: &str, _attribute: &str) -> Result<(), TemplateError> {
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, _args: Vec<Value>) -> Result<Value, TemplateError> {
+        Err(ErrorKind::RenderError(format!("Unknown function `{}`", name)).into())
+    }
+}
+
+impl Scope for HashMap<String, Value> {
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.get(name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.keys().cloned().collect()
+    }
+}
+
+// Lets a borrowed context be used as a scope without cloning it
+impl Scope for &HashMap<String, Value> {
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.get(name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.keys().cloned().collect()
+    }
+}
+
+// Binary operators, from comparisons to boolean logic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Eq,
+    NotEq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    In,
+    And,
+    Or,
+}
+
+impl BinOp {
+    // The result of `left op right`. For `and` and `or` this is only reached when the left
+    // side did not decide the result, so it is the truthiness of the right side.
+    pub fn apply(&self, left: &Value, right: &Value) -> bool {
+        match self {
+            BinOp::Eq => left.loose_eq(right),
+            BinOp::NotEq => !left.loose_eq(right),
+            BinOp::Lt => left.compare(right) == Some(Ordering::Less),
+            BinOp::Gt => left.compare(right) == Some(Ordering::Greater),
+            BinOp::LtEq => matches!(left.compare(right), Some(Ordering::Less | Ordering::Equal)),
+            BinOp::GtEq => matches!(
+                left.compare(right),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            BinOp::In => right.contains(left),
+            BinOp::And | BinOp::Or => right.is_truthy(),
+        }
+    }
+}
+
+// List of allowed AST nodes that can be constructed by Parser
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    // A variable with an optional attribute path, eg. `user.address.city`
+    Variable(Vec<String>),
+    // A string, number or boolean literal
+    Literal(Value),
+    // `expr | name(args...)`
+    Filter {
+        expr: Box<Expr>,
+        name: String,
+        args: Vec<Expr>,
+    },
+    // `name(args...)`, eg. `super()`
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Not(Box<Expr>),
+    Binary {
+        op: BinOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+impl Expr {
+    // How many levels the AST has, 1 for a variable or literal
+    pub fn depth(&self) -> usize {
+        let deepest = |args: &[Expr]| args.iter().map(Expr::depth).max().unwrap_or(0);
+        match self {
+            Expr::Variable(_) | Expr::Literal(_) => 1,
+            Expr::Filter { expr, args, .. } => 1 + expr.depth().max(deepest(args)),
+            Expr::Call { args, .. } => 1 + deepest(args),
+            Expr::Not(expr) => 1 + expr.depth(),
+            Expr::Binary { left, right, .. } => 1 + left.depth().max(right.depth()),
+        }
+    }
+
+    // Given an AST, compute its value. Undefined variables evaluate to Value::Undefined(name),
+    // or to an error when the scope is strict
+    pub fn eval(
+        &self,
+        scope: &mut dyn Scope,
+        filters: &FilterRegistry,
+    ) -> Result<Value, TemplateError> {
+        match self {
+            Expr::Variable(path) => {
+                let mut value = match scope.lookup(&path[0]) {
+                    Some(v) => v,
+                    None => return scope.undefined(&path[0], &scope.names()),
+                };
+                for (i, attr) in path.iter().enumerate().skip(1) {
+                    scope.check_attribute(&path[0], attr)?;
+                    value = match value.get(attr) {
+                        Some(v) => v,
+                        None => {
+                            let candidates: Vec<String> = match value {
+                                Value::Map(m) => m.keys().cloned().collect(),
+                                _ => Vec::new(),
+                            };
+                            return scope.undefined(&path[..=i].join("."), &candidates);
+                        }
+                    };
+                }
+                Ok(value.clone())
+            }
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Filter { expr, name, args } => {
+                scope.check_filter(name)?;
+                let filter = filters
+                    .get(name)
+                    .ok_or_else(|| ErrorKind::UnknownFilter(name.to_string()))?;
+                let value = match expr.eval(scope, filters) {
+                    // `default` exists to handle missing values, so it accepts them even in strict mode
+                    Err(TemplateError {
+                        kind: ErrorKind::UndefinedVariable(missing),
+                        ..
+                    }) if name == "default" => Value::Undefined(missing),
+                    result => result?,
+                };
+                let args = eval_args(args, scope, filters)?;
+                filter.apply(&value, &args)
+            }
+            Expr::Call { name, args } => {
+                let args = eval_args(args, scope, filters)?;
+                scope.call(name, args)
+            }
+            Expr::Not(expr) => Ok(Value::Bool(!expr.eval(scope, filters)?.is_truthy())),
+            Expr::Binary { op, left, right } => {
+                let left = left.eval(scope, filters)?;
+                // `and` and `or` short-circuit
+                match op {
+                    BinOp::And if !left.is_truthy() => return Ok(Value::Bool(false)),
+                    BinOp::Or if left.is_truthy() => return Ok(Value::Bool(true)),
+                    _ => {}
+                }
+                let right = right.eval(scope, filters)?;
+                Ok(Value::Bool(op.apply(&left, &right)))
+            }
+        }
+    }
+}
+
+fn eval_args(
+    args: &[Expr],
+    scope: &mut dyn Scope,
+    filters: &FilterRegistry,
+) -> Result<Vec<Value>, TemplateError> {
+    args.iter().map(|a| a.eval(scope, filters)).collect()
+}
+
+//Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::parser::Parser;
+    #[test]
+    fn test_eval_filter_chain() {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), Value::from("bob"));
+        let expr = Parser::new("name | upper | truncate(2)")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let value = expr.eval(&mut context, &FilterRegistry::new()).unwrap();
+        assert_eq!(Value::from("BO..."), value);
+    }
+    #[test]
+    fn test_eval_attribute() {
+        let mut user = HashMap::new();
+        user.insert("city".to_string(), Value::from("Boston"));
+        let mut context = HashMap::new();
+        context.insert("user".to_string(), Value::Map(user));
+        let expr = Parser::new("user.city").unwrap().parse().unwrap();
+        let value = expr.eval(&mut context, &FilterRegistry::new()).unwrap();
+        assert_eq!(Value::from("Boston"), value);
+    }
+    #[test]
+    fn test_eval_comparison() {
+        let mut context = HashMap::new();
+        context.insert("count".to_string(), Value::from("3"));
+        let expr = Parser::new("count > 2 and not missing")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let value = expr.eval(&mut context, &FilterRegistry::new()).unwrap();
+        assert_eq!(Value::Bool(true), value);
+    }
+}
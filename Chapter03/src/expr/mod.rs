@@ -0,0 +1,6 @@
+/// Module expr: the expression grammar used inside `{{ }}`
+/// eg. `name`, `user.city`, `price | round(2)`, `items | join(", ")`
+pub mod ast;
+pub mod parser;
+pub mod token;
+pub mod tokenizer;
@@ -0,0 +1,375 @@
+/// This module parses the tokens of a template expression into an AST
+/// Grammar (lowest to highest precedence):
+///   expression := and ( 'or' and )*
+///   and        := not ( 'and' not )*
+///   not        := 'not' not | comparison
+///   comparison := filtered ( ( '==' | '!=' | '<' | '>' | '<=' | '>=' | 'in' ) filtered )?
+///   filtered   := primary ( '|' ident ( '(' args ')' )? )*
+///   primary    := ident ( '.' ident )* ( '(' args ')' )? | string | number
+///               | 'true' | 'false' | 'none' | '(' expression ')'
+use super::ast::{BinOp, Expr};
+use super::token::Token;
+use super::tokenizer::Tokenizer;
+use crate::error::{ErrorKind, TemplateError};
+use crate::value::Value;
+
+// How deeply an expression may nest, eg. parentheses, `not`s or a chain of filters. Deeper
+// expressions are refused rather than overflowing the stack when parsed or evaluated.
+pub const MAX_NESTING: usize = 100;
+
+// Parser struct
+pub struct Parser<'a> {
+    source: &'a str,
+    tokenizer: Tokenizer<'a>,
+    current_token: Token,
+    // How many parse_expression and parse_not calls are in progress
+    depth: usize,
+}
+
+// Public methods of Parser
+
+impl<'a> Parser<'a> {
+    // Create a new instance of Parser
+    pub fn new(expr: &'a str) -> Result<Self, TemplateError> {
+        let mut lexer = Tokenizer::new(expr);
+        let cur_token = lexer.next().ok_or_else(|| invalid_character(&lexer))?;
+        Ok(Parser {
+            source: expr,
+            tokenizer: lexer,
+            current_token: cur_token,
+            depth: 0,
+        })
+    }
+
+    // Take a template expression as input and return an AST. The whole input must be consumed.
+    pub fn parse(&mut self) -> Result<Expr, TemplateError> {
+        let expr = self.parse_expression()?;
+        self.expect_end()?;
+        Ok(expr)
+    }
+
+    // Position of the current token in characters from the start of the expression,
+    // used to point error messages at the right column
+    pub fn position(&self) -> usize {
+        self.source[..self.tokenizer.offset()].chars().count()
+    }
+
+    // The methods below let tag parsers, eg. `for item in items`, read the tag piece by piece
+
+    pub fn current_token(&self) -> &Token {
+        &self.current_token
+    }
+
+    // Retrieve the next token from the expression and set it to current_token field in Parser struct
+    pub fn next_token(&mut self) -> Result<(), TemplateError> {
+        self.current_token = self
+            .tokenizer
+            .next()
+            .ok_or_else(|| invalid_character(&self.tokenizer))?;
+        Ok(())
+    }
+
+    pub fn expect(&mut self, expected: Token) -> Result<(), TemplateError> {
+        if expected == self.current_token {
+            self.next_token()
+        } else {
+            Err(ErrorKind::ParseError(format!(
+                "Expected {:?}, got {:?}",
+                expected, self.current_token
+            ))
+            .into())
+        }
+    }
+
+    pub fn expect_ident(&mut self) -> Result<String, TemplateError> {
+        match self.current_token.clone() {
+            Token::Ident(name) => {
+                self.next_token()?;
+                Ok(name)
+            }
+            other => Err(ErrorKind::ParseError(format!("Expected a name, got {:?}", other)).into()),
+        }
+    }
+
+    // Consumes the keyword if it is the current token
+    pub fn accept_keyword(&mut self, keyword: &str) -> Result<bool, TemplateError> {
+        if self.is_keyword(keyword) {
+            self.next_token()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<(), TemplateError> {
+        if self.accept_keyword(keyword)? {
+            Ok(())
+        } else {
+            Err(ErrorKind::ParseError(format!(
+                "Expected `{}`, got {:?}",
+                keyword, self.current_token
+            ))
+            .into())
+        }
+    }
+
+    pub fn expect_end(&self) -> Result<(), TemplateError> {
+        if self.current_token != Token::EOF {
+            return Err(ErrorKind::ParseError(format!(
+                "Unexpected {:?} after expression",
+                self.current_token
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expr, TemplateError> {
+        self.enter()?;
+        let mut left = self.parse_and()?;
+        while self.accept_keyword("or")? {
+            let right = self.parse_and()?;
+            left = nested(binary(BinOp::Or, left, right))?;
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+}
+
+// Private methods of Parser
+
+impl<'a> Parser<'a> {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.current_token, Token::Ident(name) if name == keyword)
+    }
+
+    fn enter(&mut self) -> Result<(), TemplateError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(too_deep());
+        }
+        Ok(())
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, TemplateError> {
+        let mut left = self.parse_not()?;
+        while self.accept_keyword("and")? {
+            let right = self.parse_not()?;
+            left = nested(binary(BinOp::And, left, right))?;
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, TemplateError> {
+        if self.accept_keyword("not")? {
+            self.enter()?;
+            let expr = self.parse_not()?;
+            self.depth -= 1;
+            return nested(Expr::Not(Box::new(expr)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, TemplateError> {
+        let left = self.parse_filtered()?;
+        let op = match &self.current_token {
+            Token::Eq => BinOp::Eq,
+            Token::NotEq => BinOp::NotEq,
+            Token::Lt => BinOp::Lt,
+            Token::Gt => BinOp::Gt,
+            Token::LtEq => BinOp::LtEq,
+            Token::GtEq => BinOp::GtEq,
+            Token::Ident(name) if name == "in" => BinOp::In,
+            _ => return Ok(left),
+        };
+        self.next_token()?;
+        let right = self.parse_filtered()?;
+        nested(binary(op, left, right))
+    }
+
+    fn parse_filtered(&mut self) -> Result<Expr, TemplateError> {
+        let mut expr = self.parse_primary()?;
+        while self.current_token == Token::Pipe {
+            self.next_token()?;
+            let name = self.expect_ident().map_err(|_| {
+                ErrorKind::ParseError(format!(
+                    "Expected filter name after '|', got {:?}",
+                    self.current_token
+                ))
+            })?;
+            let args = if self.current_token == Token::LeftParen {
+                self.parse_args()?
+            } else {
+                Vec::new()
+            };
+            expr = nested(Expr::Filter {
+                expr: Box::new(expr),
+                name,
+                args,
+            })?;
+        }
+        Ok(expr)
+    }
+
+    // Parses a parenthesised, comma separated list of expressions
+    fn parse_args(&mut self) -> Result<Vec<Expr>, TemplateError> {
+        self.expect(Token::LeftParen)?;
+        let mut args = Vec::new();
+        while self.current_token != Token::RightParen {
+            args.push(self.parse_expression()?);
+            if self.current_token == Token::Comma {
+                self.next_token()?;
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RightParen)?;
+        Ok(args)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, TemplateError> {
+        match self.current_token.clone() {
+            Token::Ident(name) => {
+                self.next_token()?;
+                match name.as_str() {
+                    "true" => return Ok(Expr::Literal(Value::Bool(true))),
+                    "false" => return Ok(Expr::Literal(Value::Bool(false))),
+                    "none" => return Ok(Expr::Literal(Value::Null)),
+                    _ => {}
+                }
+                if self.current_token == Token::LeftParen {
+                    let args = self.parse_args()?;
+                    return Ok(Expr::Call { name, args });
+                }
+                let mut path = vec![name];
+                while self.current_token == Token::Dot {
+                    self.next_token()?;
+                    let attr = self.expect_ident().map_err(|_| {
+                        ErrorKind::ParseError(format!(
+                            "Expected attribute name after '.', got {:?}",
+                            self.current_token
+                        ))
+                    })?;
+                    path.push(attr);
+                }
+                // A call through an imported namespace, eg. `ui.button("OK")`
+                if self.current_token == Token::LeftParen {
+                    let args = self.parse_args()?;
+                    return Ok(Expr::Call {
+                        name: path.join("."),
+                        args,
+                    });
+                }
+                Ok(Expr::Variable(path))
+            }
+            Token::Str(s) => {
+                self.next_token()?;
+                Ok(Expr::Literal(Value::Str(s)))
+            }
+            Token::Num(n) => {
+                self.next_token()?;
+                let n = n
+                    .parse::<f64>()
+                    .map_err(|_| ErrorKind::ParseError(format!("Invalid number {}", n)))?;
+                Ok(Expr::Literal(Value::Number(n)))
+            }
+            Token::LeftParen => {
+                self.next_token()?;
+                let expr = self.parse_expression()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            other => Err(ErrorKind::ParseError(format!(
+                "Expected variable or literal, got {:?}",
+                other
+            ))
+            .into()),
+        }
+    }
+}
+
+fn binary(op: BinOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+// Refuses an expression built from parts that each nest deeply enough to go over the limit together
+fn nested(expr: Expr) -> Result<Expr, TemplateError> {
+    if expr.depth() > MAX_NESTING {
+        return Err(too_deep());
+    }
+    Ok(expr)
+}
+
+fn too_deep() -> TemplateError {
+    ErrorKind::LimitExceeded(format!("expression nested more than {} deep", MAX_NESTING)).into()
+}
+
+fn invalid_character(tokenizer: &Tokenizer) -> TemplateError {
+    ErrorKind::ParseError(format!(
+        "Invalid character near offset {}",
+        tokenizer.offset()
+    ))
+    .into()
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_filter_with_args() {
+        let mut parser = Parser::new(r#"items | join(", ")"#).unwrap();
+        let expected = Expr::Filter {
+            expr: Box::new(Expr::Variable(vec!["items".to_string()])),
+            name: "join".to_string(),
+            args: vec![Expr::Literal(Value::from(", "))],
+        };
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
+    #[test]
+    fn test_trailing_garbage() {
+        let mut parser = Parser::new("name name").unwrap();
+        assert!(parser.parse().is_err());
+    }
+    #[test]
+    fn test_comparison_binds_looser_than_filter() {
+        let mut parser = Parser::new("name | lower == 'bob'").unwrap();
+        match parser.parse().unwrap() {
+            Expr::Binary { op, left, .. } => {
+                assert_eq!(BinOp::Eq, op);
+                assert!(matches!(*left, Expr::Filter { .. }));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    #[test]
+    fn test_call() {
+        let mut parser = Parser::new("super()").unwrap();
+        let expected = Expr::Call {
+            name: "super".to_string(),
+            args: vec![],
+        };
+        assert_eq!(parser.parse().unwrap(), expected);
+    }
+    #[test]
+    fn test_nesting_limit() {
+        let deep =
+            |open: &str, close: &str, n: usize| format!("{}x{}", open.repeat(n), close.repeat(n));
+        assert!(Parser::new(&deep("(", ")", 50)).unwrap().parse().is_ok());
+        for expr in [
+            deep("(", ")", 100_000),
+            deep("not ", "", 100_000),
+            deep("f(", ")", 100_000),
+            deep("", " | upper", 100_000),
+            deep("", " or y", 100_000),
+        ] {
+            let error = Parser::new(&expr).unwrap().parse().unwrap_err();
+            assert!(matches!(error.kind, ErrorKind::LimitExceeded(_)));
+        }
+    }
+}
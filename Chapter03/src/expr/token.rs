@@ -0,0 +1,21 @@
+/// List of valid tokens that can appear inside a template expression
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Ident(String), // 变量名或过滤器名
+    Str(String),   // 字符串字面量
+    Num(String),   // 数字字面量
+    Pipe,          // |
+    Dot,           // .
+    Comma,         // ,
+    LeftParen,     // (
+    RightParen,    // )
+    Eq,            // ==
+    NotEq,         // !=
+    Lt,            // <
+    Gt,            // >
+    LtEq,          // <=
+    GtEq,          // >=
+    Assign,        // =
+    EOF,
+}
@@ -0,0 +1,167 @@
+/// This module reads characters in a template expression and converts them to tokens.
+/// The allowed tokens are defined in token module.
+// Standard lib
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+//Other internal modules
+use super::token::Token;
+
+// Tokenizer struct contains a Peekable iterator on the expression source
+pub struct Tokenizer<'a> {
+    expr: Peekable<CharIndices<'a>>,
+    offset: usize,
+}
+
+// Constructs a new instance of Tokenizer
+impl<'a> Tokenizer<'a> {
+    pub fn new(new_expr: &'a str) -> Self {
+        Tokenizer {
+            expr: new_expr.char_indices().peekable(),
+            offset: 0,
+        }
+    }
+
+    // Byte offset of the most recently returned token, used for error messages
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some((_, c)) = self.expr.peek() {
+            if c.is_whitespace() {
+                self.expr.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // Consumes the next character if it is the expected one
+    fn next_if_eq(&mut self, expected: char) -> bool {
+        self.expr.next_if(|(_, c)| *c == expected).is_some()
+    }
+
+    fn read_number(&mut self, first: char) -> String {
+        let mut number = first.to_string();
+        while let Some((_, c)) = self.expr.peek() {
+            if c.is_ascii_digit() || *c == '.' {
+                number.push(*c);
+                self.expr.next();
+            } else {
+                break;
+            }
+        }
+        number
+    }
+
+    // Reads a quoted string; returns None if the closing quote is missing
+    fn read_string(&mut self, quote: char) -> Option<String> {
+        let mut text = String::new();
+        while let Some((_, c)) = self.expr.next() {
+            match c {
+                '\\' => match self.expr.next()? {
+                    (_, 'n') => text.push('\n'),
+                    (_, 't') => text.push('\t'),
+                    (_, other) => text.push(other),
+                },
+                c if c == quote => return Some(text),
+                c => text.push(c),
+            }
+        }
+        None
+    }
+}
+
+// Implement Iterator trait for Tokenizer struct.
+// Returns None when an invalid character or an unterminated string is found
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.skip_whitespace();
+        let (offset, char) = match self.expr.next() {
+            Some(next) => next,
+            None => return Some(Token::EOF),
+        };
+        self.offset = offset;
+        match char {
+            '0'..='9' => Some(Token::Num(self.read_number(char))),
+            '-' if matches!(self.expr.peek(), Some((_, c)) if c.is_ascii_digit()) => {
+                Some(Token::Num(self.read_number(char)))
+            }
+            '"' | '\'' => self.read_string(char).map(Token::Str),
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some((_, c)) = self.expr.peek() {
+                    if c.is_alphanumeric() || *c == '_' {
+                        ident.push(*c);
+                        self.expr.next();
+                    } else {
+                        break;
+                    }
+                }
+                Some(Token::Ident(ident))
+            }
+            '|' => Some(Token::Pipe),
+            '.' => Some(Token::Dot),
+            ',' => Some(Token::Comma),
+            '(' => Some(Token::LeftParen),
+            ')' => Some(Token::RightParen),
+            '=' if self.next_if_eq('=') => Some(Token::Eq),
+            '=' => Some(Token::Assign),
+            '!' if self.next_if_eq('=') => Some(Token::NotEq),
+            '<' if self.next_if_eq('=') => Some(Token::LtEq),
+            '<' => Some(Token::Lt),
+            '>' if self.next_if_eq('=') => Some(Token::GtEq),
+            '>' => Some(Token::Gt),
+            _ => None,
+        }
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_filter_tokens() {
+        let tokenizer = Tokenizer::new("price | round(2)");
+        let tokens: Vec<Token> = tokenizer.take(7).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("price".to_string()),
+                Token::Pipe,
+                Token::Ident("round".to_string()),
+                Token::LeftParen,
+                Token::Num("2".to_string()),
+                Token::RightParen,
+                Token::EOF,
+            ]
+        );
+    }
+    #[test]
+    fn test_comparison_tokens() {
+        let tokens: Vec<Token> = Tokenizer::new("a == 1 != <= >= < > =").take(9).collect();
+        assert_eq!(
+            tokens[1..],
+            [
+                Token::Eq,
+                Token::Num("1".to_string()),
+                Token::NotEq,
+                Token::LtEq,
+                Token::GtEq,
+                Token::Lt,
+                Token::Gt,
+                Token::Assign,
+            ]
+        );
+    }
+    #[test]
+    fn test_string_token() {
+        let mut tokenizer = Tokenizer::new(r#"", ""#);
+        assert_eq!(Some(Token::Str(", ".to_string())), tokenizer.next());
+    }
+}
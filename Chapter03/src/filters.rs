@@ -0,0 +1,238 @@
+// Filters transform a value inside `{{ }}`, eg. `{{ name | upper }}` or `{{ price | round(2) }}`.
+// Applications can add their own filters by implementing the Filter trait or by registering a closure.
+use std::collections::HashMap;
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::value::Value;
+
+pub trait Filter: Send + Sync {
+    fn apply(&self, value: &Value, args: &[Value]) -> Result<Value, TemplateError>;
+}
+
+// Any closure with the right signature can be used as a filter
+impl<F> Filter for F
+where
+    F: Fn(&Value, &[Value]) -> Result<Value, TemplateError> + Send + Sync,
+{
+    fn apply(&self, value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+        self(value, args)
+    }
+}
+
+// Registry of named filters available to templates
+pub struct FilterRegistry {
+    filters: HashMap<String, Box<dyn Filter>>,
+}
+
+impl FilterRegistry {
+    // Creates a registry with the built-in filters
+    pub fn new() -> Self {
+        let mut registry = FilterRegistry::empty();
+        registry.register("upper", upper);
+        registry.register("lower", lower);
+        registry.register("trim", trim);
+        registry.register("length", length);
+        registry.register("round", round);
+        registry.register("join", join);
+        registry.register("truncate", truncate);
+        registry.register("default", default);
+        registry
+    }
+
+    // Creates a registry without any filters
+    pub fn empty() -> Self {
+        FilterRegistry {
+            filters: HashMap::new(),
+        }
+    }
+
+    // Adds a filter, replacing any existing filter with the same name
+    pub fn register<F: Filter + 'static>(&mut self, name: &str, filter: F) {
+        self.filters.insert(name.to_string(), Box::new(filter));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Filter> {
+        self.filters.get(name).map(|f| f.as_ref())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.filters.contains_key(name)
+    }
+}
+
+impl Default for FilterRegistry {
+    fn default() -> Self {
+        FilterRegistry::new()
+    }
+}
+
+// Helpers for reading filter arguments
+
+fn arg_count(name: &str, args: &[Value], min: usize, max: usize) -> Result<(), TemplateError> {
+    if args.len() < min || args.len() > max {
+        return Err(ErrorKind::FilterError(format!(
+            "`{}` expects {} to {} arguments, got {}",
+            name,
+            min,
+            max,
+            args.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn number_arg(name: &str, arg: &Value) -> Result<f64, TemplateError> {
+    arg.as_number().ok_or_else(|| {
+        ErrorKind::FilterError(format!(
+            "`{}` expects a number argument, got {}",
+            name,
+            arg.type_name()
+        ))
+        .into()
+    })
+}
+
+// Built-in filters
+
+fn upper(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("upper", args, 0, 0)?;
+    Ok(Value::Str(value.to_string().to_uppercase()))
+}
+
+fn lower(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("lower", args, 0, 0)?;
+    Ok(Value::Str(value.to_string().to_lowercase()))
+}
+
+fn trim(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("trim", args, 0, 0)?;
+    Ok(Value::Str(value.to_string().trim().to_string()))
+}
+
+fn length(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("length", args, 0, 0)?;
+    let len = match value {
+        Value::List(l) => l.len(),
+        Value::Map(m) => m.len(),
+        Value::Null => 0,
+        other => other.to_string().chars().count(),
+    };
+    Ok(Value::Number(len as f64))
+}
+
+// round(precision=0), where a negative precision rounds to tens, hundreds...
+fn round(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("round", args, 0, 1)?;
+    let n = number_arg("round", value)?;
+    let precision = match args.first() {
+        Some(p) => number_arg("round", p)?,
+        None => 0.0,
+    };
+    // An f64 holds no more than about 15 significant digits
+    if precision.fract() != 0.0 || !(-15.0..=15.0).contains(&precision) {
+        return Err(ErrorKind::FilterError(format!(
+            "`round` expects a whole precision from -15 to 15, got {}",
+            precision
+        ))
+        .into());
+    }
+    let precision = precision as i32;
+    let rounded = if precision >= 0 {
+        let factor = 10f64.powi(precision);
+        (n * factor).round() / factor
+    } else {
+        let factor = 10f64.powi(-precision);
+        (n / factor).round() * factor
+    };
+    // Keep trailing zeros, so that `1.5 | round(2)` renders as 1.50
+    if precision > 0 {
+        Ok(Value::Str(format!("{:.*}", precision as usize, rounded)))
+    } else {
+        Ok(Value::Number(rounded))
+    }
+}
+
+// join(separator="")
+fn join(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("join", args, 0, 1)?;
+    let separator = args.first().map(|s| s.to_string()).unwrap_or_default();
+    match value {
+        Value::List(items) => Ok(Value::Str(
+            items
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(&separator),
+        )),
+        other => Ok(Value::Str(other.to_string())),
+    }
+}
+
+// truncate(length, end="...")
+fn truncate(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("truncate", args, 1, 2)?;
+    let max = number_arg("truncate", &args[0])? as usize;
+    let end = args
+        .get(1)
+        .map(|e| e.to_string())
+        .unwrap_or("...".to_string());
+    let text = value.to_string();
+    if text.chars().count() <= max {
+        return Ok(Value::Str(text));
+    }
+    let mut truncated: String = text.chars().take(max).collect();
+    truncated.push_str(&end);
+    Ok(Value::Str(truncated))
+}
+
+// default(fallback) is used when the value is undefined or empty
+fn default(value: &Value, args: &[Value]) -> Result<Value, TemplateError> {
+    arg_count("default", args, 1, 1)?;
+    if value.is_truthy() {
+        Ok(value.clone())
+    } else {
+        Ok(args[0].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn round_test() {
+        let value = round(&Value::Number(1.23456), &[Value::Number(2.0)]).unwrap();
+        assert_eq!(Value::from("1.23"), value);
+        let value = round(&Value::Number(1250.0), &[Value::Number(-2.0)]).unwrap();
+        assert_eq!(Value::Number(1300.0), value);
+        for precision in [2_000_000_000.0, 16.0, -16.0, 1.5] {
+            assert!(matches!(
+                round(&Value::Number(1.0), &[Value::Number(precision)]).map_err(|e| e.kind),
+                Err(ErrorKind::FilterError(_))
+            ));
+        }
+    }
+    #[test]
+    fn default_test() {
+        let value = default(&Value::Null, &[Value::from("n/a")]).unwrap();
+        assert_eq!(Value::from("n/a"), value);
+    }
+    #[test]
+    fn truncate_test() {
+        let value = truncate(&Value::from("abcdef"), &[Value::Number(3.0)]).unwrap();
+        assert_eq!(Value::from("abc..."), value);
+    }
+    #[test]
+    fn register_closure_test() {
+        let mut registry = FilterRegistry::new();
+        registry.register("shout", |v: &Value, _: &[Value]| {
+            Ok(Value::Str(format!("{}!", v)))
+        });
+        let value = registry
+            .get("shout")
+            .unwrap()
+            .apply(&Value::from("hi"), &[])
+            .unwrap();
+        assert_eq!(Value::from("hi!"), value);
+    }
+}
@@ -0,0 +1,819 @@
+// Translations for `{% trans %}` blocks and `_("...")`, read from gettext `.po` catalogs.
+// The locale comes from the `locale` variable of the context, eg. "de_AT"; a message missing from
+// its catalog is looked up in the catalog of the language ("de"), then in the fallback locales,
+// and is finally rendered untranslated. `extract` collects the messages of templates into a
+// `.pot` file that translators start new catalogs from.
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::expr::ast::Expr;
+use crate::template::{Node, Template};
+use crate::value::Value;
+
+// The messages of one `.po` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Catalog {
+    // msgid to msgstr, or to msgstr[0], msgstr[1], ... for plural messages
+    messages: HashMap<String, Vec<String>>,
+    plural: Plural,
+}
+
+impl Catalog {
+    // `origin` names the catalog in error messages, eg. the file path
+    pub fn parse(source: &str, origin: &str) -> Result<Catalog, TemplateError> {
+        let mut entries = Vec::new();
+        let mut entry = Entry::default();
+        let mut field = None;
+        let mut fuzzy = false;
+        for (i, line) in source.lines().enumerate() {
+            let error = |message: &str| -> TemplateError {
+                ErrorKind::DataError(format!("{}:{}: {}", origin, i + 1, message)).into()
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix('#') {
+                // Comments belong to the entry that follows
+                if !entry.strings.is_empty() {
+                    entries.push((mem::take(&mut entry), fuzzy));
+                    fuzzy = false;
+                }
+                // Translations marked fuzzy need review and are not used
+                if let Some(flags) = comment.strip_prefix(',') {
+                    fuzzy |= flags.split(',').any(|f| f.trim() == "fuzzy");
+                }
+                continue;
+            }
+            if line.starts_with('"') {
+                let text = unquote(line).ok_or_else(|| error("invalid string"))?;
+                match field {
+                    Some(field) => entry.field(field).push_str(&text),
+                    None => return Err(error("string outside of an entry")),
+                }
+                continue;
+            }
+            let (keyword, rest) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected a keyword and a string"))?;
+            let next = match keyword {
+                "msgctxt" => Field::Context,
+                "msgid" => Field::Id,
+                "msgid_plural" => Field::Plural,
+                "msgstr" => Field::Str(0),
+                _ => match keyword
+                    .strip_prefix("msgstr[")
+                    .and_then(|k| k.strip_suffix(']'))
+                    .and_then(|k| k.parse().ok())
+                {
+                    Some(index) => Field::Str(index),
+                    None => return Err(error(&format!("unknown keyword `{}`", keyword))),
+                },
+            };
+            // A msgctxt or msgid after the translations starts the next entry
+            if matches!(next, Field::Context | Field::Id) && !entry.strings.is_empty() {
+                entries.push((mem::take(&mut entry), fuzzy));
+                fuzzy = false;
+            }
+            // The forms are numbered from 0 without gaps, as the plural rule picks them by index
+            if let Field::Str(index) = next {
+                if index != entry.strings.len() {
+                    return Err(error(&format!(
+                        "expected msgstr[{}], got `{}`",
+                        entry.strings.len(),
+                        keyword
+                    )));
+                }
+            }
+            let text = unquote(rest.trim()).ok_or_else(|| error("invalid string"))?;
+            entry.field(next).push_str(&text);
+            field = Some(next);
+        }
+        if !entry.strings.is_empty() {
+            entries.push((entry, fuzzy));
+        }
+
+        let mut catalog = Catalog {
+            messages: HashMap::new(),
+            plural: Plural::default(),
+        };
+        for (entry, fuzzy) in entries {
+            if entry.id.is_empty() && entry.context.is_none() {
+                catalog.plural = header_plural(&entry.strings[0].1, origin)?;
+                continue;
+            }
+            if fuzzy {
+                continue;
+            }
+            let strings = entry.strings;
+            let key = match entry.context {
+                // Messages with a context are kept apart; templates do not use them
+                Some(context) => format!("{}\u{4}{}", context, entry.id),
+                None => entry.id,
+            };
+            catalog
+                .messages
+                .insert(key, strings.into_iter().map(|(_, s)| s).collect());
+        }
+        Ok(catalog)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Catalog, TemplateError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|e| {
+            ErrorKind::DataError(format!("Unable to read {}: {}", path.display(), e))
+        })?;
+        Catalog::parse(&source, &path.display().to_string())
+    }
+
+    // The translation of `msgid`; None if the catalog has none
+    pub fn get(&self, msgid: &str) -> Option<&str> {
+        self.messages
+            .get(msgid)
+            .and_then(|strings| strings.first())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.as_str())
+    }
+
+    // The plural form of `msgid` for `n`, chosen by the catalog's Plural-Forms rule
+    pub fn get_plural(&self, msgid: &str, n: u64) -> Option<&str> {
+        let strings = self.messages.get(msgid)?;
+        let form = self.plural.eval(n) as usize;
+        strings
+            .get(form)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.as_str())
+    }
+}
+
+// An entry being read, with the index of each msgstr
+#[derive(Default)]
+struct Entry {
+    context: Option<String>,
+    id: String,
+    plural: String,
+    strings: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Context,
+    Id,
+    Plural,
+    Str(usize),
+}
+
+impl Entry {
+    fn field(&mut self, field: Field) -> &mut String {
+        match field {
+            Field::Context => self.context.get_or_insert_with(String::new),
+            Field::Id => &mut self.id,
+            Field::Plural => &mut self.plural,
+            Field::Str(index) => {
+                let i = match self.strings.iter().position(|(k, _)| *k == index) {
+                    Some(i) => i,
+                    None => {
+                        self.strings.push((index, String::new()));
+                        self.strings.len() - 1
+                    }
+                };
+                &mut self.strings[i].1
+            }
+        }
+    }
+}
+
+// Reads a quoted C string, eg. "Hello\n"
+fn unquote(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        result.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '"' => '"',
+            '\\' => '\\',
+            _ => return None,
+        });
+    }
+    Some(result)
+}
+
+// Writes `text` as a quoted C string; text with line breaks is split after each one, as gettext does
+fn quote(keyword: &str, text: &str) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+    };
+    if !text.trim_end_matches('\n').contains('\n') {
+        return format!("{} \"{}\"\n", keyword, escape(text));
+    }
+    let mut result = format!("{} \"\"\n", keyword);
+    for line in text.split_inclusive('\n') {
+        result.push_str(&format!("\"{}\"\n", escape(line)));
+    }
+    result
+}
+
+// The plural rule from the header, eg. "Plural-Forms: nplurals=2; plural=(n != 1);"
+fn header_plural(header: &str, origin: &str) -> Result<Plural, TemplateError> {
+    let forms = header
+        .lines()
+        .find_map(|line| line.strip_prefix("Plural-Forms:"));
+    let rule = forms.and_then(|forms| {
+        forms
+            .split(';')
+            .find_map(|part| part.trim().strip_prefix("plural="))
+    });
+    match rule {
+        Some(rule) => Plural::parse(rule).ok_or_else(|| {
+            ErrorKind::DataError(format!("{}: invalid Plural-Forms rule `{}`", origin, rule)).into()
+        }),
+        None => Ok(Plural::default()),
+    }
+}
+
+// A Plural-Forms rule: a C expression in `n` giving the index of the plural form to use
+#[derive(Debug, Clone, PartialEq)]
+enum Plural {
+    N,
+    Number(u64),
+    Not(Box<Plural>),
+    Binary(&'static str, Box<Plural>, Box<Plural>),
+    // condition ? then : else
+    Choice(Box<Plural>, Box<Plural>, Box<Plural>),
+}
+
+// Binary operators from the loosest binding to the tightest
+const PLURAL_OPERATORS: [&[&str]; 6] = [
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Default for Plural {
+    // The English rule, `n != 1`
+    fn default() -> Self {
+        Plural::Binary("!=", Box::new(Plural::N), Box::new(Plural::Number(1)))
+    }
+}
+
+impl Plural {
+    fn parse(rule: &str) -> Option<Plural> {
+        let mut tokens = Vec::new();
+        let mut rest = rule.trim();
+        while !rest.is_empty() {
+            let len = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                rest.find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len())
+            } else if ["||", "&&", "==", "!=", "<=", ">="]
+                .iter()
+                .any(|op| rest.starts_with(op))
+            {
+                2
+            } else if rest.starts_with(|c| "n<>+-*/%!?:()".contains(c)) {
+                1
+            } else {
+                return None;
+            };
+            tokens.push(&rest[..len]);
+            rest = rest[len..].trim_start();
+        }
+        let mut parser = PluralParser { tokens, pos: 0 };
+        let plural = parser.choice()?;
+        (parser.pos == parser.tokens.len()).then_some(plural)
+    }
+
+    fn eval(&self, n: u64) -> u64 {
+        match self {
+            Plural::N => n,
+            Plural::Number(v) => *v,
+            Plural::Not(e) => (e.eval(n) == 0) as u64,
+            Plural::Choice(c, a, b) => {
+                if c.eval(n) != 0 {
+                    a.eval(n)
+                } else {
+                    b.eval(n)
+                }
+            }
+            Plural::Binary(op, a, b) => {
+                let (a, b) = (a.eval(n), b.eval(n));
+                match *op {
+                    "||" => (a != 0 || b != 0) as u64,
+                    "&&" => (a != 0 && b != 0) as u64,
+                    "==" => (a == b) as u64,
+                    "!=" => (a != b) as u64,
+                    "<=" => (a <= b) as u64,
+                    ">=" => (a >= b) as u64,
+                    "<" => (a < b) as u64,
+                    ">" => (a > b) as u64,
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" => a.checked_div(b).unwrap_or(0),
+                    _ => a.checked_rem(b).unwrap_or(0),
+                }
+            }
+        }
+    }
+}
+
+struct PluralParser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> PluralParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn accept(&mut self, token: &str) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn choice(&mut self) -> Option<Plural> {
+        let condition = self.binary(0)?;
+        if !self.accept("?") {
+            return Some(condition);
+        }
+        let then = self.choice()?;
+        if !self.accept(":") {
+            return None;
+        }
+        let otherwise = self.choice()?;
+        Some(Plural::Choice(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, level: usize) -> Option<Plural> {
+        if level == PLURAL_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = PLURAL_OPERATORS[level]
+            .iter()
+            .find(|op| self.peek() == Some(**op))
+        {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Plural::Binary(op, Box::new(left), Box::new(right));
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<Plural> {
+        let token = self.peek()?;
+        self.pos += 1;
+        match token {
+            "n" => Some(Plural::N),
+            "!" => Some(Plural::Not(Box::new(self.unary()?))),
+            "(" => {
+                let inner = self.choice()?;
+                self.accept(")").then_some(inner)
+            }
+            _ => token.parse().ok().map(Plural::Number),
+        }
+    }
+}
+
+// Catalogs by locale, with the locales to fall back to
+#[derive(Debug, Clone, Default)]
+pub struct Translations {
+    catalogs: HashMap<String, Catalog>,
+    fallbacks: Vec<String>,
+}
+
+impl Translations {
+    pub fn new() -> Self {
+        Translations::default()
+    }
+
+    // Loads every `<locale>.po` file in `dir`, eg. "de.po" and "pt_BR.po"
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Translations, TemplateError> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir).map_err(|e| {
+            ErrorKind::DataError(format!("Unable to read {}: {}", dir.display(), e))
+        })?;
+        let mut translations = Translations::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "po") {
+                if let Some(locale) = path.file_stem().and_then(|s| s.to_str()) {
+                    translations.add(locale, Catalog::load(&path)?);
+                }
+            }
+        }
+        Ok(translations)
+    }
+
+    pub fn add(&mut self, locale: &str, catalog: Catalog) {
+        self.catalogs.insert(normalize(locale), catalog);
+    }
+
+    // Adds a locale to try when the context's locale has no translation; the first added is tried first
+    pub fn fallback(mut self, locale: &str) -> Self {
+        self.fallbacks.push(normalize(locale));
+        self
+    }
+
+    pub fn locales(&self) -> Vec<String> {
+        let mut locales: Vec<String> = self.catalogs.keys().cloned().collect();
+        locales.sort();
+        locales
+    }
+
+    // The translation of `msgid`, or of the plural message for `n` when `plural` is given
+    pub fn translate<'s>(
+        &'s self,
+        locale: Option<&str>,
+        msgid: &'s str,
+        plural: Option<(&'s str, u64)>,
+    ) -> &'s str {
+        for locale in self.candidates(locale) {
+            let catalog = match self.catalogs.get(&locale) {
+                Some(catalog) => catalog,
+                None => continue,
+            };
+            let found = match plural {
+                Some((_, n)) => catalog.get_plural(msgid, n),
+                None => catalog.get(msgid),
+            };
+            if let Some(found) = found {
+                return found;
+            }
+        }
+        untranslated(msgid, plural)
+    }
+
+    // The locale, then its language, then the fallbacks
+    fn candidates(&self, locale: Option<&str>) -> Vec<String> {
+        let mut candidates = Vec::new();
+        if let Some(locale) = locale.map(normalize) {
+            if let Some((language, _)) = locale.split_once('_') {
+                candidates.push(locale.clone());
+                candidates.push(language.to_string());
+            } else {
+                candidates.push(locale);
+            }
+        }
+        for fallback in &self.fallbacks {
+            if !candidates.contains(fallback) {
+                candidates.push(fallback.clone());
+            }
+        }
+        candidates
+    }
+}
+
+// "de-AT" and "de_AT.UTF-8" both name the locale "de_AT"
+fn normalize(locale: &str) -> String {
+    let locale = locale.split('.').next().unwrap_or(locale);
+    locale.replace('-', "_")
+}
+
+// A message as written in the template, using the English plural rule
+pub(crate) fn untranslated<'s>(msgid: &'s str, plural: Option<(&'s str, u64)>) -> &'s str {
+    match plural {
+        Some((plural, n)) if n != 1 => plural,
+        _ => msgid,
+    }
+}
+
+// Replaces `%(name)s` with the value of `name` and `%%` with `%`. Placeholders without a value,
+// eg. from a mistyped translation, are left as they are.
+pub(crate) fn format(message: &str, values: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = message;
+    while let Some(i) = rest.find('%') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("%%") {
+            result.push('%');
+            rest = after;
+            continue;
+        }
+        let placeholder = rest
+            .strip_prefix("%(")
+            .and_then(|r| r.split_once(")s"))
+            .and_then(|(name, after)| values.get(name).map(|v| (v, after)));
+        match placeholder {
+            Some((value, after)) => {
+                result.push_str(value);
+                rest = after;
+            }
+            None => {
+                result.push('%');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// A message found in templates, with where it was found, eg. "pages/home.html:12"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub msgid: String,
+    pub plural: Option<String>,
+    pub references: Vec<String>,
+}
+
+// Collects the messages of `{% trans %}` blocks and `_("...")` calls, in the order they appear;
+// a message used several times is listed once with every reference
+pub fn extract(templates: &[Template]) -> Vec<Message> {
+    let mut found = Vec::new();
+    for template in templates {
+        let mut collector = Collector {
+            template,
+            found: Vec::new(),
+        };
+        collector.nodes(&template.nodes);
+        let mut macros: Vec<_> = template.macros.values().collect();
+        macros.sort_by(|a, b| a.name.cmp(&b.name));
+        for m in macros {
+            for (_, default) in &m.params {
+                if let Some(default) = default {
+                    collector.expr(default, None);
+                }
+            }
+            collector.nodes(&m.body);
+        }
+        // Macros have no location of their own, so everything is ordered by line
+        collector.found.sort_by_key(|(_, line)| *line);
+        found.extend(collector.found);
+    }
+
+    let mut messages: Vec<Message> = Vec::new();
+    for (message, _) in found {
+        match messages
+            .iter_mut()
+            .find(|m| m.msgid == message.msgid && m.plural == message.plural)
+        {
+            Some(existing) => {
+                for reference in message.references {
+                    if !existing.references.contains(&reference) {
+                        existing.references.push(reference);
+                    }
+                }
+            }
+            None => messages.push(message),
+        }
+    }
+    messages
+}
+
+// The `.pot` file for the messages, with an empty translation for each
+pub fn write_pot(messages: &[Message]) -> String {
+    let mut pot = String::from(
+        "msgid \"\"\n\
+         msgstr \"\"\n\
+         \"Content-Type: text/plain; charset=UTF-8\\n\"\n\
+         \"Plural-Forms: nplurals=INTEGER; plural=EXPRESSION;\\n\"\n",
+    );
+    for message in messages {
+        pot.push('\n');
+        if !message.references.is_empty() {
+            pot.push_str(&format!("#: {}\n", message.references.join(" ")));
+        }
+        if message.msgid.contains('%') {
+            pot.push_str("#, python-format\n");
+        }
+        pot.push_str(&quote("msgid", &message.msgid));
+        match &message.plural {
+            Some(plural) => {
+                pot.push_str(&quote("msgid_plural", plural));
+                pot.push_str("msgstr[0] \"\"\nmsgstr[1] \"\"\n");
+            }
+            None => pot.push_str("msgstr \"\"\n"),
+        }
+    }
+    pot
+}
+
+// Walks the nodes of one template, noting each message with its line
+struct Collector<'a> {
+    template: &'a Template,
+    found: Vec<(Message, usize)>,
+}
+
+impl<'a> Collector<'a> {
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Expr(expr, span) => self.expr(expr, Some(span.line)),
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    for branch in branches {
+                        self.expr(&branch.condition, Some(branch.span.line));
+                        self.nodes(&branch.body);
+                    }
+                    self.nodes(otherwise);
+                }
+                Node::For {
+                    iterable,
+                    span,
+                    body,
+                    otherwise,
+                    ..
+                } => {
+                    self.expr(iterable, Some(span.line));
+                    self.nodes(body);
+                    self.nodes(otherwise);
+                }
+                Node::Block(name) => {
+                    if let Some(body) = self.template.blocks.get(name) {
+                        self.nodes(body);
+                    }
+                }
+                Node::Include { with, span, .. } => {
+                    for (_, value) in with {
+                        self.expr(value, Some(span.line));
+                    }
+                }
+                Node::Set { value, span, .. } => self.expr(value, Some(span.line)),
+                Node::Trans {
+                    singular,
+                    plural,
+                    span,
+                    ..
+                } => {
+                    self.add(singular, plural.as_ref().map(|(p, _)| p), Some(span.line));
+                    if let Some((_, count)) = plural {
+                        self.expr(count, Some(span.line));
+                    }
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, line: Option<usize>) {
+        match expr {
+            Expr::Call { name, args } if name == "_" => {
+                if let [Expr::Literal(Value::Str(msgid))] = args.as_slice() {
+                    // `_()` messages have no variables, so a `%` is literal
+                    self.add(&msgid.replace('%', "%%"), None, line);
+                }
+            }
+            Expr::Call { args, .. } => args.iter().for_each(|a| self.expr(a, line)),
+            Expr::Filter { expr, args, .. } => {
+                self.expr(expr, line);
+                args.iter().for_each(|a| self.expr(a, line));
+            }
+            Expr::Not(expr) => self.expr(expr, line),
+            Expr::Binary { left, right, .. } => {
+                self.expr(left, line);
+                self.expr(right, line);
+            }
+            Expr::Variable(_) | Expr::Literal(_) => {}
+        }
+    }
+
+    fn add(&mut self, msgid: &str, plural: Option<&String>, line: Option<usize>) {
+        let reference = match line {
+            Some(line) => format!("{}:{}", self.template.name, line),
+            None => self.template.name.clone(),
+        };
+        self.found.push((
+            Message {
+                msgid: msgid.to_string(),
+                plural: plural.cloned(),
+                references: vec![reference],
+            },
+            line.unwrap_or(0),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterRegistry;
+    use crate::loader::MemoryLoader;
+    use crate::render::Renderer;
+
+    const GERMAN: &str = r#"
+msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+"Plural-Forms: nplurals=2; plural=(n != 1);\n"
+
+#: page.html:1
+msgid "Hello %(name)s"
+msgstr "Hallo %(name)s"
+
+msgid "One apple"
+msgid_plural "%(n)s apples"
+msgstr[0] "Ein Apfel"
+msgstr[1] "%(n)s "
+"Äpfel"
+
+#, fuzzy
+msgid "Save"
+msgstr "Sichern"
+"#;
+
+    fn render(source: &str, locale: &str, translations: &Translations) -> String {
+        let mut loader = MemoryLoader::new();
+        loader.add("t.html", source);
+        let mut context = HashMap::new();
+        context.insert("locale".to_string(), Value::from(locale));
+        context.insert("name".to_string(), Value::from("Ana"));
+        context.insert("n".to_string(), Value::from(3));
+        let filters = FilterRegistry::new();
+        Renderer::new(&loader, &filters)
+            .translations(translations)
+            .render("t.html", &context)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_catalog_test() {
+        let catalog = Catalog::parse(GERMAN, "de.po").unwrap();
+        assert_eq!(Some("Hallo %(name)s"), catalog.get("Hello %(name)s"));
+        assert_eq!(Some("Ein Apfel"), catalog.get_plural("One apple", 1));
+        assert_eq!(Some("%(n)s Äpfel"), catalog.get_plural("One apple", 5));
+        assert_eq!(None, catalog.get("Save"));
+        let err = Catalog::parse("msgid \"a\"\nmsgstr \"b\nc\"", "x.po").unwrap_err();
+        assert_eq!(
+            ErrorKind::DataError("x.po:2: invalid string".to_string()),
+            err.kind
+        );
+        let gap = "msgid \"a\"\nmsgid_plural \"as\"\nmsgstr[0] \"b\"\nmsgstr[2] \"c\"";
+        assert_eq!(
+            ErrorKind::DataError("x.po:4: expected msgstr[1], got `msgstr[2]`".to_string()),
+            Catalog::parse(gap, "x.po").unwrap_err().kind
+        );
+    }
+    #[test]
+    fn plural_rule_test() {
+        // Polish: 1 plik, 2 pliki, 5 plików, 22 pliki
+        let rule =
+            Plural::parse("(n==1 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2)")
+                .unwrap();
+        let forms: Vec<u64> = [1, 2, 5, 12, 22, 25]
+            .iter()
+            .map(|n| rule.eval(*n))
+            .collect();
+        assert_eq!(vec![0, 1, 2, 2, 1, 2], forms);
+        assert_eq!(None, Plural::parse("n ==="));
+    }
+    #[test]
+    fn render_with_fallbacks_test() {
+        let mut translations = Translations::new().fallback("de");
+        translations.add("de", Catalog::parse(GERMAN, "de.po").unwrap());
+        let template = "{% trans %}Hello {{ name }}{% endtrans %}, \
+                        {% trans %}One apple{% plural n %}{{ n }} apples{% endtrans %}, {{ _(\"Save\") }}";
+        assert_eq!(
+            "Hallo Ana, 3 Äpfel, Save",
+            render(template, "de-AT", &translations)
+        );
+        assert_eq!(
+            "Hallo Ana, 3 Äpfel, Save",
+            render(template, "fr", &translations)
+        );
+        assert_eq!(
+            "Hello Ana, 3 apples, Save",
+            render(template, "fr", &Translations::new())
+        );
+    }
+    #[test]
+    fn extract_test() {
+        let template = Template::parse(
+            "page.html",
+            "{% trans %}Hello {{ name }}{% endtrans %}\n{{ _(\"Save\") }}{% block b %}{{ _(\"Save\") }}{% endblock %}\n\
+             {% trans %}One file{% plural n %}{{ n }} files{% endtrans %}",
+        )
+        .unwrap();
+        let pot = write_pot(&extract(&[template]));
+        assert!(pot
+            .contains("#: page.html:1\n#, python-format\nmsgid \"Hello %(name)s\"\nmsgstr \"\"\n"));
+        assert!(pot.contains("#: page.html:2\nmsgid \"Save\"\nmsgstr \"\"\n"));
+        assert!(pot.contains("msgid \"One file\"\nmsgid_plural \"%(n)s files\"\nmsgstr[0] \"\"\n"));
+    }
+}
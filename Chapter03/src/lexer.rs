@@ -0,0 +1,207 @@
+// Splits a whole template into literal text, `{{ expression }}` and `{% tag %}` chunks.
+// Unlike get_content_type, which classifies one line at a time, chunks may span lines.
+// `{# comments #}` are dropped, `{% raw %}...{% endraw %}` becomes literal text, and a `-`
+// inside a delimiter, as in `{%- tag -%}`, trims the whitespace before or after it.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ChunkKind {
+    Text,       // 字面量
+    Expression, // {{ }}
+    Tag,        // {% %}
+}
+
+// One piece of the template with the line it starts on and the column its content starts at (1-based)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Chunk {
+    pub kind: ChunkKind,
+    pub content: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+// A delimiter that is never closed, or a `{% raw %}` without `{% endraw %}`
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexError {
+    pub message: &'static str,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Chunk>, LexError> {
+    let mut chunks = Vec::new();
+    let mut rest = source;
+    // The line `rest` starts on
+    let mut line = 1;
+    // Set by `-}}`, `-%}` and `-#}`: strip leading whitespace from the next text
+    let mut trim_next = false;
+
+    while !rest.is_empty() {
+        let start = find_open(rest).unwrap_or(rest.len());
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start();
+        }
+        let inner = &rest[(start + 2).min(rest.len())..];
+        let (kind, close) = match rest[start..].get(..2) {
+            Some("{{") => (Some(ChunkKind::Expression), "}}"),
+            Some("{%") => (Some(ChunkKind::Tag), "%}"),
+            Some(_) => (None, "#}"),
+            None => {
+                push_text(&mut chunks, text, position(source, rest, line, text));
+                break;
+            }
+        };
+        if inner.starts_with('-') {
+            text = text.trim_end();
+        }
+        push_text(&mut chunks, text, position(source, rest, line, text));
+
+        let end = inner.find(close).ok_or_else(|| {
+            let (line, column) = position(source, rest, line, &rest[start..]);
+            LexError {
+                message: "Unclosed `{{`, `{%` or `{#`",
+                line,
+                column,
+            }
+        })?;
+        let raw_content = &inner[..end];
+        let content = trim_markers(raw_content);
+        let (content_line, column) = position(source, rest, line, content);
+        trim_next = raw_content.ends_with('-');
+        let after = &inner[end + 2..];
+        line += rest[..rest.len() - after.len()].matches('\n').count();
+        rest = after;
+
+        let kind = match kind {
+            Some(kind) => kind,
+            None => continue, // 注释
+        };
+        if kind == ChunkKind::Tag && content == "raw" {
+            let (text, after, trim_after) = split_raw(rest).ok_or(LexError {
+                message: "Missing {% endraw %}",
+                line: content_line,
+                column,
+            })?;
+            let text = if trim_next { text.trim_start() } else { text };
+            push_text(&mut chunks, text, position(source, rest, line, text));
+            line += rest[..rest.len() - after.len()].matches('\n').count();
+            rest = after;
+            trim_next = trim_after;
+            continue;
+        }
+        chunks.push(Chunk {
+            kind,
+            content: content.to_string(),
+            line: content_line,
+            column,
+        });
+    }
+    Ok(chunks)
+}
+
+// The content of a delimiter without the `-` trim markers and surrounding whitespace
+fn trim_markers(content: &str) -> &str {
+    let content = content.strip_prefix('-').unwrap_or(content);
+    let content = content.strip_suffix('-').unwrap_or(content);
+    content.trim()
+}
+
+// Splits the input after `{% raw %}` into the raw text, the input after `{% endraw %}`,
+// and whether the endraw tag trims the whitespace that follows it
+fn split_raw(input: &str) -> Option<(&str, &str, bool)> {
+    let mut from = 0;
+    while let Some(i) = input[from..].find("{%") {
+        let start = from + i;
+        let inner = &input[start + 2..];
+        let end = inner.find("%}")?;
+        if trim_markers(&inner[..end]) == "endraw" {
+            let text = &input[..start];
+            let text = if inner.starts_with('-') {
+                text.trim_end()
+            } else {
+                text
+            };
+            return Some((text, &inner[end + 2..], inner[..end].ends_with('-')));
+        }
+        from = start + 2;
+    }
+    None
+}
+
+// Line and column (1-based) where `part` starts. `part` is a slice of `rest`, the unread
+// input starting on `line`, which is itself a slice of `source`.
+fn position(source: &str, rest: &str, line: usize, part: &str) -> (usize, usize) {
+    let offset = part.as_ptr() as usize - source.as_ptr() as usize;
+    let rest_offset = rest.as_ptr() as usize - source.as_ptr() as usize;
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (
+        line + source[rest_offset..offset].matches('\n').count(),
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+// Index of the next `{{`, `{%` or `{#`
+fn find_open(input: &str) -> Option<usize> {
+    ["{{", "{%", "{#"]
+        .iter()
+        .filter_map(|open| input.find(open))
+        .min()
+}
+
+fn push_text(chunks: &mut Vec<Chunk>, text: &str, (line, column): (usize, usize)) {
+    if !text.is_empty() {
+        chunks.push(Chunk {
+            kind: ChunkKind::Text,
+            content: text.to_string(),
+            line,
+            column,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn tokenize_test() {
+        let chunks = tokenize("<p>\n{{ name }}{% if x %}\n</p>").unwrap();
+        let kinds: Vec<(ChunkKind, &str, usize)> = chunks
+            .iter()
+            .map(|c| (c.kind.clone(), c.content.as_str(), c.line))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (ChunkKind::Text, "<p>\n", 1),
+                (ChunkKind::Expression, "name", 2),
+                (ChunkKind::Tag, "if x", 2),
+                (ChunkKind::Text, "\n</p>", 2),
+            ]
+        );
+    }
+    #[test]
+    fn column_test() {
+        let chunks = tokenize("<p>\n  {{  name }}</p>").unwrap();
+        assert_eq!((2, 7), (chunks[1].line, chunks[1].column));
+    }
+    #[test]
+    fn comment_raw_and_trim_test() {
+        let chunks =
+            tokenize("a {# note #}b\n  {%- raw -%}\n {{ x }} {%- endraw %}\n{{- y -}}  !").unwrap();
+        let contents: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(vec!["a ", "b", "{{ x }}", "y", "!"], contents);
+        assert_eq!(4, chunks[3].line);
+    }
+    #[test]
+    fn unclosed_tag_test() {
+        assert_eq!(
+            Err(LexError {
+                message: "Unclosed `{{`, `{%` or `{#`",
+                line: 2,
+                column: 1
+            }),
+            tokenize("a\n{% if x")
+        );
+    }
+}
@@ -1,6 +1,26 @@
 // Standard library imports
 use std::collections::HashMap;
 
+pub mod aot;
+pub mod data;
+pub mod environment;
+pub mod error;
+pub mod expr;
+pub mod filters;
+pub mod i18n;
+pub mod lexer;
+pub mod loader;
+pub mod parser;
+pub mod render;
+pub mod sandbox;
+pub mod template;
+pub mod value;
+
+use error::TemplateError;
+use expr::parser::Parser;
+use filters::FilterRegistry;
+use value::Value;
+
 // Each line in template file can be of one of following types
 #[derive(PartialEq, Debug)]
 pub enum ContentType {
@@ -13,6 +33,7 @@ pub enum ContentType {
 //IF ContentType is TemplateVariable, the contents of the line are parsed and stored in the ExpressionData struct.alloc
 // If template file contains this line: <p> Hello {{name}} ,welcome </p> then
 // Head = "Hello" , variable = "name" and tail = ",welcome"
+// The variable may be a full expression with filters, eg. {{ name | upper }}
 #[derive(PartialEq, Debug)]
 pub struct ExpressionData {
     pub head: Option<String>,
@@ -38,10 +59,9 @@ pub fn check_matching_pair(input: &str, symbol1: &str, symbol2: &str) -> bool {
 
 // this returns index of given char symbol, if symbol is present.
 pub fn get_index_for_symbol(input: &str, symbol: char) -> (bool, usize) {
-    let mut characters = input.char_indices();
     let mut does_exist = false;
     let mut index = 0;
-    while let Some((c, d)) = characters.next() {
+    for (c, d) in input.char_indices() {
         if d == symbol {
             does_exist = true;
             index = c;
@@ -57,17 +77,17 @@ pub fn get_index_for_symbol(input: &str, symbol: char) -> (bool, usize) {
 
 pub fn get_content_type(input_line: &str) -> ContentType {
     // Tag expressions are enclosed within {% and %}
-    let is_tag_expression = check_matching_pair(&input_line, "{%", "%}");
+    let is_tag_expression = check_matching_pair(input_line, "{%", "%}");
 
     // ForTag expressions begin with  keywords 'for' and 'in' enclosed within {% and %}
     // ForTag expressions end with keyword 'endfor' enclosed within {% and %}
-    let is_for_tag = (check_symbol_string(&input_line, "for")
-        && check_symbol_string(&input_line, "in"))
-        || check_symbol_string(&input_line, "endfor");
+    let is_for_tag = (check_symbol_string(input_line, "for")
+        && check_symbol_string(input_line, "in"))
+        || check_symbol_string(input_line, "endfor");
     // IfTag expressions begin with  keyword 'if' enclosed within {% and %}
     // IfTag expressions end with keyword 'endif' enclosed within {% and %}
     let is_if_tag =
-        check_symbol_string(&input_line, "if") || check_symbol_string(&input_line, "endif");
+        check_symbol_string(input_line, "if") || check_symbol_string(input_line, "endif");
 
     // Template variables have
     // 1) an optional head,
@@ -76,7 +96,7 @@ pub fn get_content_type(input_line: &str) -> ContentType {
     // eg the expression <p> Hello {{name}} ,welcome </p> is parsed as follows:
     // head = 'Hello', variable = 'name' and tail = ',welcome'
 
-    let is_template_variable = check_matching_pair(&input_line, "{{", "}}");
+    let is_template_variable = check_matching_pair(input_line, "{{", "}}");
     let return_val;
     // case: For Tag
     if is_tag_expression && is_for_tag {
@@ -86,7 +106,7 @@ pub fn get_content_type(input_line: &str) -> ContentType {
         return_val = ContentType::Tag(TagType::IfTag);
     // case: Template variable
     } else if is_template_variable {
-        let content = get_expression_data(&input_line);
+        let content = get_expression_data(input_line);
         return_val = ContentType::TemplateVariable(content);
     // case: Literal
     } else if !is_tag_expression && !is_template_variable {
@@ -98,47 +118,83 @@ pub fn get_content_type(input_line: &str) -> ContentType {
     return_val
 }
 
-// Function to generate HTML for line containing template variable
+// Function to generate HTML for line containing template variable.
+// Expressions that fail to evaluate render as an empty string; use render_template_var to see the error.
 pub fn generate_html_template_var(
     content: ExpressionData,
     context: HashMap<String, String>,
 ) -> String {
+    let context: HashMap<String, Value> = context
+        .into_iter()
+        .map(|(k, v)| (k, Value::Str(v)))
+        .collect();
     let mut html = String::new();
-    println!("expression data is:{:?}", content);
-    if let Some(h) = content.head {
-        html.push_str(&h);
+    if let Some(h) = &content.head {
+        html.push_str(h);
     }
 
-    if let Some(val) = context.get(&content.variable) {
+    if let Ok(val) = evaluate_expression(&content.variable, &context, &FilterRegistry::new()) {
         html.push_str(&val);
     }
 
-    if let Some(t) = content.tail {
-        html.push_str(&t);
+    if let Some(t) = &content.tail {
+        html.push_str(t);
     }
 
     html
 }
 
+// Function to generate HTML for line containing template variable, using the given filters
+pub fn render_template_var(
+    content: &ExpressionData,
+    context: &HashMap<String, Value>,
+    filters: &FilterRegistry,
+) -> Result<String, TemplateError> {
+    let mut html = String::new();
+    if let Some(h) = &content.head {
+        html.push_str(h);
+    }
+    html.push_str(&evaluate_expression(&content.variable, context, filters)?);
+    if let Some(t) = &content.tail {
+        html.push_str(t);
+    }
+    Ok(html)
+}
+
+// Parses an expression such as `price | round(2)` and renders its value
+pub fn evaluate_expression(
+    expression: &str,
+    mut context: &HashMap<String, Value>,
+    filters: &FilterRegistry,
+) -> Result<String, TemplateError> {
+    let expr = Parser::new(expression)?.parse()?;
+    Ok(expr.eval(&mut context, filters)?.to_string())
+}
+
 // Helper function to parse template variable
 pub fn get_expression_data(input_line: &str) -> ExpressionData {
-    let (_h, i) = get_index_for_symbol(input_line, '{');
+    let i = input_line.find("{{").unwrap_or(0);
     let head = input_line[0..i].to_string();
-    let (_j, k) = get_index_for_symbol(input_line, '}');
-    let variable = input_line[i + 1 + 1..k].to_string();
-    let tail = input_line[k + 1 + 1..].to_string();
+    let k = input_line[i..]
+        .find("}}")
+        .map(|k| k + i)
+        .unwrap_or(input_line.len());
+    let variable = input_line[i + 2..k].trim().to_string();
+    let tail = input_line[(k + 2).min(input_line.len())..].to_string();
 
     ExpressionData {
         head: Some(head),
-        variable: variable,
+        variable,
         tail: Some(tail),
     }
 }
 
 //Unit tests
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::error::ErrorKind;
     #[test]
     fn check_get_index_for_symbol_test() {
         assert_eq!((true, 3), get_index_for_symbol("Hi {name} bye", '{'));
@@ -186,6 +242,36 @@ mod tests {
         assert_eq!(expression_data, get_expression_data("Hi {{name}},welcome"));
     }
     #[test]
+    fn check_get_expression_data_filter_test() {
+        let expression_data = ExpressionData {
+            head: Some("<p>".to_string()),
+            variable: r#"items | join(", ")"#.to_string(),
+            tail: Some("</p>".to_string()),
+        };
+
+        assert_eq!(
+            expression_data,
+            get_expression_data(r#"<p>{{ items | join(", ") }}</p>"#)
+        );
+    }
+    #[test]
+    fn render_template_var_test() {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), Value::from("Bob"));
+        let content = get_expression_data("Hi {{ name | upper }}, {{");
+        let html = render_template_var(&content, &context, &FilterRegistry::new());
+        assert_eq!(Ok("Hi BOB, {{".to_string()), html);
+    }
+    #[test]
+    fn unknown_filter_test() {
+        let content = get_expression_data("{{ name | shout }}");
+        let html = render_template_var(&content, &HashMap::new(), &FilterRegistry::new());
+        assert_eq!(
+            Err(ErrorKind::UnknownFilter("shout".to_string())),
+            html.map_err(|e| e.kind)
+        );
+    }
+    #[test]
     fn check_symbol_string_test() {
         assert_eq!(true, check_symbol_string("{{Hello}}", "{{"));
     }
@@ -0,0 +1,100 @@
+// Loaders find template source by name, for `extends` and `include` as well as the template being rendered
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{ErrorKind, TemplateError};
+
+pub trait Loader {
+    fn load(&self, name: &str) -> Result<String, TemplateError>;
+}
+
+// Keeps templates in memory, eg. for tests or templates embedded in the binary
+#[derive(Debug, Default)]
+pub struct MemoryLoader {
+    templates: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+
+    pub fn add(&mut self, name: &str, source: &str) {
+        self.templates.insert(name.to_string(), source.to_string());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<String, TemplateError> {
+        self.templates
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ErrorKind::TemplateNotFound(name.to_string()).into())
+    }
+}
+
+// Reads templates from files below a root directory. Names are relative paths such as "partials/nav.html".
+#[derive(Debug)]
+pub struct FileSystemLoader {
+    root: PathBuf,
+}
+
+impl FileSystemLoader {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        FileSystemLoader {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    // Resolves a template name to a path, refusing names that would escape the root directory
+    pub fn path_for(&self, name: &str) -> Option<PathBuf> {
+        let relative = Path::new(name);
+        let escapes = relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            None
+        } else {
+            Some(self.root.join(relative))
+        }
+    }
+}
+
+impl Loader for FileSystemLoader {
+    fn load(&self, name: &str) -> Result<String, TemplateError> {
+        let path = self
+            .path_for(name)
+            .ok_or_else(|| ErrorKind::TemplateNotFound(name.to_string()))?;
+        fs::read_to_string(&path).map_err(|e| {
+            match e.kind() {
+                io::ErrorKind::NotFound => ErrorKind::TemplateNotFound(name.to_string()),
+                _ => ErrorKind::RenderError(format!("Unable to read {:?}: {}", path, e)),
+            }
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn memory_loader_missing_test() {
+        let loader = MemoryLoader::new();
+        assert_eq!(
+            Err(ErrorKind::TemplateNotFound("a.html".to_string())),
+            loader.load("a.html").map_err(|e| e.kind)
+        );
+    }
+    #[test]
+    fn file_loader_rejects_parent_dir_test() {
+        let loader = FileSystemLoader::new("/srv/templates");
+        assert_eq!(None, loader.path_for("../etc/passwd"));
+        assert_eq!(
+            Some(PathBuf::from("/srv/templates/partials/nav.html")),
+            loader.path_for("partials/nav.html")
+        );
+    }
+}
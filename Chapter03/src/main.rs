@@ -1,23 +1,465 @@
 use std::collections::HashMap;
-use std::io;
-use std::io::BufRead;
-use template_engine::*;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use structopt::StructOpt;
+use template_engine::data::{self, DataFormat};
+use template_engine::error::{ErrorKind, TemplateError};
+use template_engine::filters::FilterRegistry;
+use template_engine::i18n::{self, Translations};
+use template_engine::loader::FileSystemLoader;
+use template_engine::render::{Renderer, UndefinedBehavior};
+use template_engine::sandbox::Sandbox;
+use template_engine::template::Template;
+use template_engine::value::Value;
+
+// Exit codes
+const EXIT_TEMPLATE_ERROR: i32 = 1;
+const EXIT_DATA_ERROR: i32 = 2;
+const EXIT_IO_ERROR: i32 = 3;
+
+// Define commandline arguments in a struct
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "template-engine",
+    about = "Render templates with data from JSON, TOML or YAML files"
+)]
+enum Commandline {
+    #[structopt(
+        help = "Render a template file, or every template in a directory, eg. template-engine render page.html --data data.json --out page.html"
+    )]
+    Render {
+        #[structopt(
+            parse(from_os_str),
+            help = "Template file, or a directory whose templates are rendered into --out. Names starting with '_' are only used by extends/include"
+        )]
+        template: PathBuf,
+        #[structopt(
+            long,
+            number_of_values = 1,
+            parse(from_os_str),
+            help = "JSON, TOML or YAML data file; repeat to merge several in order. Use '-' for stdin"
+        )]
+        data: Vec<PathBuf>,
+        #[structopt(
+            long = "var",
+            number_of_values = 1,
+            help = "Override a value after the data files are merged, eg. --var site.title=Home"
+        )]
+        vars: Vec<String>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Output file, or output directory when rendering a directory. Defaults to stdout"
+        )]
+        out: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Directory that extends/include names are resolved against. Defaults to the template's directory"
+        )]
+        templates: Option<PathBuf>,
+        #[structopt(
+            long,
+            default_value = "json",
+            help = "Format of data read from stdin: json, toml or yaml"
+        )]
+        stdin_format: DataFormat,
+        #[structopt(
+            long,
+            default_value = "lenient",
+            help = "What to do with undefined variables: lenient (render nothing), strict (fail) or debug (render them as {{ name }})"
+        )]
+        undefined: UndefinedBehavior,
+        #[structopt(
+            long,
+            help = "Render untrusted templates with limits on loop iterations, output size, include depth and time"
+        )]
+        sandbox: bool,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Directory of <locale>.po catalogs for {% trans %} and _(\"...\"); the locale is read from the `locale` variable"
+        )]
+        translations: Option<PathBuf>,
+        #[structopt(
+            long = "fallback-locale",
+            number_of_values = 1,
+            help = "Locale used for messages the context's locale has no translation for; repeat to try several in order"
+        )]
+        fallback_locales: Vec<String>,
+    },
+    #[structopt(
+        help = "Collect the messages to translate from templates into a .pot file, eg. template-engine extract templates --out messages.pot"
+    )]
+    Extract {
+        #[structopt(parse(from_os_str), help = "Template file or directory of templates")]
+        templates: PathBuf,
+        #[structopt(long, parse(from_os_str), help = "Output file. Defaults to stdout")]
+        out: Option<PathBuf>,
+    },
+}
+
+// How templates are rendered, from the command line flags
+struct RenderOptions {
+    undefined: UndefinedBehavior,
+    sandbox: Option<Sandbox>,
+    translations: Option<Translations>,
+}
+
+// Errors reported by the command line, each with its own exit code
+enum CliError {
+    Template(PathBuf, TemplateError),
+    Data(TemplateError),
+    Io(String),
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Template(..) => EXIT_TEMPLATE_ERROR,
+            CliError::Data(_) => EXIT_DATA_ERROR,
+            CliError::Io(_) => EXIT_IO_ERROR,
+        }
+    }
+
+    // Prints the error as a file:line:column diagnostic where the location is known
+    fn report(&self) {
+        match self {
+            CliError::Template(root, e) => {
+                let mut e = e.clone();
+                if let Some(location) = e.location.as_mut() {
+                    location.template = root.join(&location.template).display().to_string();
+                }
+                eprintln!("error: {}", e)
+            }
+            CliError::Data(e) => eprintln!("error: {}", e),
+            CliError::Io(e) => eprintln!("error: {}", e),
+        }
+    }
+}
 
 fn main() {
-    let mut context: HashMap<String, String> = HashMap::new();
-    context.insert("name".to_string(), "Bob".to_string());
-    context.insert("city".to_string(), "Boston".to_string());
-
-    for line in io::stdin().lock().lines() {
-        match get_content_type(&line.unwrap().clone()) {
-            ContentType::TemplateVariable(content) => {
-                let html = generate_html_template_var(content, context.clone());
-                println!("{}", html);
+    let args: Commandline = Commandline::from_args();
+    let result = match args {
+        Commandline::Render {
+            template,
+            data,
+            vars,
+            out,
+            templates,
+            stdin_format,
+            undefined,
+            sandbox,
+            translations,
+            fallback_locales,
+        } => load_context(&data, &vars, stdin_format).and_then(|context| {
+            let options = RenderOptions {
+                undefined,
+                sandbox: sandbox.then(Sandbox::new),
+                translations: load_translations(translations, &fallback_locales)?,
+            };
+            if template.is_dir() {
+                let out = out.ok_or_else(|| {
+                    CliError::Io("--out is required when rendering a directory".to_string())
+                })?;
+                render_tree(&template, &out, &context, &options)
+            } else {
+                render_file(&template, templates, out, &context, &options)
             }
-            ContentType::Literal(text) => println!("{}", text),
-            ContentType::Tag(TagType::ForTag) => println!("For Tag not implemented"),
-            ContentType::Tag(TagType::IfTag) => println!("If Tag not implemented"),
-            ContentType::Unrecognized => println!("Unrecognized input"),
+        }),
+        Commandline::Extract { templates, out } => extract(&templates, out),
+    };
+    if let Err(e) = result {
+        e.report();
+        process::exit(e.exit_code());
+    }
+}
+
+// Builds the context from data files, then applies --var overrides
+fn load_context(
+    files: &[PathBuf],
+    vars: &[String],
+    stdin_format: DataFormat,
+) -> Result<HashMap<String, Value>, CliError> {
+    let mut context = HashMap::new();
+    for file in files {
+        let (source, format) = if file == Path::new("-") {
+            let mut source = String::new();
+            io::stdin()
+                .read_to_string(&mut source)
+                .map_err(|e| CliError::Io(format!("Unable to read stdin: {}", e)))?;
+            (source, stdin_format)
+        } else {
+            let format = DataFormat::from_path(file).ok_or_else(|| {
+                CliError::Data(TemplateError::new(ErrorKind::DataError(format!(
+                    "{}: unknown data file extension, expected .json, .toml, .yaml or .yml",
+                    file.display()
+                ))))
+            })?;
+            (read_file(file)?, format)
+        };
+        let origin = file.display().to_string();
+        let values = data::parse_data(&source, format, &origin).map_err(CliError::Data)?;
+        data::merge(&mut context, values);
+    }
+    for var in vars {
+        let (key, value) = var.split_once('=').ok_or_else(|| {
+            CliError::Data(TemplateError::new(ErrorKind::DataError(format!(
+                "--var {}: expected key=value",
+                var
+            ))))
+        })?;
+        data::set_path(&mut context, key, Value::from(value));
+    }
+    Ok(context)
+}
+
+fn load_translations(
+    dir: Option<PathBuf>,
+    fallbacks: &[String],
+) -> Result<Option<Translations>, CliError> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let translations = Translations::load_dir(dir).map_err(CliError::Data)?;
+    Ok(Some(
+        fallbacks.iter().fold(translations, |translations, locale| {
+            translations.fallback(locale)
+        }),
+    ))
+}
+
+// Writes the messages of every template under `src` as a .pot file
+fn extract(src: &Path, out: Option<PathBuf>) -> Result<(), CliError> {
+    let (root, files) = if src.is_dir() {
+        (src.to_path_buf(), list_files(src)?)
+    } else {
+        let root = src.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        (root, vec![src.to_path_buf()])
+    };
+    let mut templates = Vec::new();
+    for path in files {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            // Not a text file, eg. an image next to the templates
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+            Err(e) => {
+                return Err(CliError::Io(format!(
+                    "Unable to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        let name = template_name(&root, &path).unwrap_or_else(|| path.display().to_string());
+        let template =
+            Template::parse(&name, &source).map_err(|e| CliError::Template(root.clone(), e))?;
+        templates.push(template);
+    }
+    let pot = i18n::write_pot(&i18n::extract(&templates));
+    match out {
+        Some(out) => write_file(&out, pot.as_bytes()),
+        None => io::stdout()
+            .write_all(pot.as_bytes())
+            .map_err(|e| CliError::Io(format!("Unable to write output: {}", e))),
+    }
+}
+
+fn render_file(
+    template: &Path,
+    templates: Option<PathBuf>,
+    out: Option<PathBuf>,
+    context: &HashMap<String, Value>,
+    options: &RenderOptions,
+) -> Result<(), CliError> {
+    if !template.is_file() {
+        return Err(CliError::Io(format!(
+            "{}: no such template file",
+            template.display()
+        )));
+    }
+    let root = templates.unwrap_or_else(|| match template.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    });
+    let name = template_name(&root, template).ok_or_else(|| {
+        CliError::Io(format!(
+            "{} is not inside the templates directory {}",
+            template.display(),
+            root.display()
+        ))
+    })?;
+    match out {
+        Some(out) => render_into_file(&root, &name, context, options, &out),
+        None => render(&root, &name, context, options, &mut io::stdout().lock()),
+    }
+}
+
+// Renders every template under `src` into the same relative path under `out`.
+// Files that are not UTF-8 text, such as images, are copied unchanged.
+fn render_tree(
+    src: &Path,
+    out: &Path,
+    context: &HashMap<String, Value>,
+    options: &RenderOptions,
+) -> Result<(), CliError> {
+    // Outputs written among the templates would replace them, or be rendered again next time
+    if let (Some(src_abs), Some(out_abs)) = (absolute(src), absolute(out)) {
+        if out_abs.starts_with(&src_abs) {
+            return Err(CliError::Io(format!(
+                "The output directory {} must be outside the templates directory {}",
+                out.display(),
+                src.display()
+            )));
+        }
+    }
+    for path in list_files(src)? {
+        let name = match template_name(src, &path) {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.split('/').any(|part| part.starts_with('_')) {
+            continue;
+        }
+        let bytes = fs::read(&path)
+            .map_err(|e| CliError::Io(format!("Unable to read {}: {}", path.display(), e)))?;
+        if std::str::from_utf8(&bytes).is_ok() {
+            render_into_file(src, &name, context, options, &out.join(&name))?;
+        } else {
+            write_file(&out.join(&name), &bytes)?;
+        }
+    }
+    Ok(())
+}
+
+// Streams the rendered template into `writer` as it is rendered
+fn render<W: Write>(
+    root: &Path,
+    name: &str,
+    context: &HashMap<String, Value>,
+    options: &RenderOptions,
+    writer: &mut W,
+) -> Result<(), CliError> {
+    let loader = FileSystemLoader::new(root);
+    let filters = FilterRegistry::new();
+    let mut renderer = Renderer::new(&loader, &filters).undefined(options.undefined);
+    if let Some(sandbox) = &options.sandbox {
+        renderer = renderer.sandbox(sandbox);
+    }
+    if let Some(translations) = &options.translations {
+        renderer = renderer.translations(translations);
+    }
+    renderer
+        .render_to(name, context, writer)
+        .map_err(|e| match e.kind {
+            ErrorKind::WriteError(message) => {
+                CliError::Io(format!("Unable to write output: {}", message))
+            }
+            _ => CliError::Template(root.to_path_buf(), e),
+        })
+}
+
+// Renders into a temporary file next to `path`, then renames it over `path`. The file at
+// `path`, which may be the template itself, is left as it was if rendering fails.
+fn render_into_file(
+    root: &Path,
+    name: &str,
+    context: &HashMap<String, Value>,
+    options: &RenderOptions,
+    path: &Path,
+) -> Result<(), CliError> {
+    create_parent(path)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+    let mut file = fs::File::create(&temporary)
+        .map_err(|e| CliError::Io(format!("Unable to write {}: {}", temporary.display(), e)))?;
+    let rendered = render(root, name, context, options, &mut file).and_then(|_| {
+        drop(file);
+        fs::rename(&temporary, path)
+            .map_err(|e| CliError::Io(format!("Unable to write {}: {}", path.display(), e)))
+    });
+    if rendered.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    rendered
+}
+
+// The canonical form of a path that may not exist yet: its nearest existing ancestor is
+// canonicalized and the rest appended
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = fs::canonicalize(existing) {
+            return Some(
+                rest.iter()
+                    .rev()
+                    .fold(canonical, |path, part| path.join(part)),
+            );
         }
+        rest.push(existing.file_name()?);
+        existing = existing.parent()?;
+        if existing.as_os_str().is_empty() {
+            existing = Path::new(".");
+        }
+    }
+}
+
+// The loader name of a template file, eg. "partials/nav.html"
+fn template_name(root: &Path, template: &Path) -> Option<String> {
+    let relative = match template.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => {
+            let root = fs::canonicalize(root).ok()?;
+            let template = fs::canonicalize(template).ok()?;
+            template.strip_prefix(root).ok()?.to_path_buf()
+        }
+    };
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some(parts.join("/"))
+}
+
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>, CliError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .map_err(|e| CliError::Io(format!("Unable to read {}: {}", dir.display(), e)))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_file(path: &Path) -> Result<String, CliError> {
+    fs::read_to_string(path)
+        .map_err(|e| CliError::Io(format!("Unable to read {}: {}", path.display(), e)))
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), CliError> {
+    create_parent(path)?;
+    fs::write(path, contents)
+        .map_err(|e| CliError::Io(format!("Unable to write {}: {}", path.display(), e)))
+}
+
+fn create_parent(path: &Path) -> Result<(), CliError> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent)
+            .map_err(|e| CliError::Io(format!("Unable to create {}: {}", parent.display(), e))),
+        _ => Ok(()),
     }
 }
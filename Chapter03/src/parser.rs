@@ -0,0 +1,591 @@
+// Builds a Template from the chunks produced by the lexer.
+// Tags open and close nested sections, eg. {% if %} ... {% endif %}; expressions inside
+// tags and {{ }} are handled by the expression parser in the expr module.
+use std::collections::HashMap;
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::expr::ast::Expr;
+use crate::expr::parser::Parser;
+use crate::expr::token::Token;
+use crate::lexer::{self, Chunk, ChunkKind};
+use crate::template::{Branch, Import, Macro, Node, Span, Template};
+
+// How deeply tags may nest, counting the template itself. Each level takes a few recursive
+// calls, here and when rendering, so deeper templates are refused before they overflow the stack.
+pub const MAX_NESTING: usize = 32;
+
+pub fn parse_template(name: &str, source: &str) -> Result<Template, TemplateError> {
+    let chunks = lexer::tokenize(source).map_err(|e| {
+        TemplateError::new(ErrorKind::SyntaxError(e.message.to_string()))
+            .with_location(name, source, e.line, e.column)
+    })?;
+    let mut parser = TemplateParser {
+        name: name.to_string(),
+        source: source.to_string(),
+        chunks,
+        pos: 0,
+        parent: None,
+        blocks: HashMap::new(),
+        macros: HashMap::new(),
+        imports: Vec::new(),
+        depth: 0,
+    };
+    let (nodes, _) = parser.parse_nodes(&[], Span { line: 1, column: 1 })?;
+    let mut template = Template {
+        name: name.to_string(),
+        source: parser.source,
+        parent: parser.parent,
+        nodes,
+        blocks: parser.blocks,
+        macros: parser.macros,
+        imports: parser.imports,
+        nesting: 0,
+    };
+    template.nesting = template.measure_nesting();
+    Ok(template)
+}
+
+struct TemplateParser {
+    name: String,
+    source: String,
+    chunks: Vec<Chunk>,
+    pos: usize,
+    parent: Option<String>,
+    blocks: HashMap<String, Vec<Node>>,
+    macros: HashMap<String, Macro>,
+    imports: Vec<Import>,
+    // How many tags are open around the nodes being parsed
+    depth: usize,
+}
+
+// The closing tag that ended a section: its keyword, the rest of the tag and where the rest starts
+struct EndTag {
+    keyword: String,
+    rest: String,
+    span: Span,
+}
+
+impl TemplateParser {
+    // Parses nodes until one of the end tags is reached. With no end tags, parses to the end of input.
+    // `opened_at` is the tag that opened this section, reported when the end tag is missing.
+    fn parse_nodes(
+        &mut self,
+        end_tags: &[&str],
+        opened_at: Span,
+    ) -> Result<(Vec<Node>, Option<EndTag>), TemplateError> {
+        // Sections are parsed recursively, so refuse to go deeper than the stack allows
+        if self.depth > MAX_NESTING {
+            let message = format!("tags nested more than {} deep", MAX_NESTING);
+            return Err(
+                TemplateError::new(ErrorKind::LimitExceeded(message)).with_location(
+                    &self.name,
+                    &self.source,
+                    opened_at.line,
+                    opened_at.column,
+                ),
+            );
+        }
+        self.depth += 1;
+        let result = self.parse_section(end_tags, opened_at);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_section(
+        &mut self,
+        end_tags: &[&str],
+        opened_at: Span,
+    ) -> Result<(Vec<Node>, Option<EndTag>), TemplateError> {
+        let mut nodes = Vec::new();
+        while self.pos < self.chunks.len() {
+            let chunk = self.chunks[self.pos].clone();
+            self.pos += 1;
+            let span = Span {
+                line: chunk.line,
+                column: chunk.column,
+            };
+            match chunk.kind {
+                ChunkKind::Text => nodes.push(Node::Text(chunk.content)),
+                ChunkKind::Expression => {
+                    let expr = self.parse_expression(&chunk.content, span)?;
+                    nodes.push(Node::Expr(expr, span));
+                }
+                ChunkKind::Tag => {
+                    let (keyword, rest) = split_keyword(&chunk.content);
+                    let rest_span = span_of(&chunk, rest);
+                    if end_tags.contains(&keyword) {
+                        return Ok((
+                            nodes,
+                            Some(EndTag {
+                                keyword: keyword.to_string(),
+                                rest: rest.to_string(),
+                                span: rest_span,
+                            }),
+                        ));
+                    }
+                    let node =
+                        self.parse_tag(keyword, rest, span, rest_span, end_tags.is_empty())?;
+                    if let Some(node) = node {
+                        nodes.push(node);
+                    }
+                }
+            }
+        }
+        if end_tags.is_empty() {
+            Ok((nodes, None))
+        } else {
+            Err(self.error(
+                opened_at,
+                format!("Missing {{% {} %}}", end_tags[end_tags.len() - 1]),
+            ))
+        }
+    }
+
+    // `tag` is where the tag's keyword starts and `at` where the rest of the tag starts
+    fn parse_tag(
+        &mut self,
+        keyword: &str,
+        rest: &str,
+        tag: Span,
+        at: Span,
+        top_level: bool,
+    ) -> Result<Option<Node>, TemplateError> {
+        match keyword {
+            "extends" => {
+                if !top_level || self.parent.is_some() {
+                    return Err(self.error(tag, "`extends` must appear once, at the top level"));
+                }
+                let mut parser = self.tag_parser(rest, at)?;
+                let parent = self.template_name(&mut parser, at)?;
+                self.wrap(parser.expect_end(), &parser, at)?;
+                self.parent = Some(parent);
+                Ok(None)
+            }
+            "import" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let template = self.template_name(&mut parser, at)?;
+                self.wrap(parser.expect_keyword("as"), &parser, at)?;
+                let alias = self.wrap(parser.expect_ident(), &parser, at)?;
+                self.wrap(parser.expect_end(), &parser, at)?;
+                self.imports.push(Import::Module { template, alias });
+                Ok(None)
+            }
+            "from" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let template = self.template_name(&mut parser, at)?;
+                self.wrap(parser.expect_keyword("import"), &parser, at)?;
+                let mut names = Vec::new();
+                loop {
+                    let name = self.wrap(parser.expect_ident(), &parser, at)?;
+                    let local = if self.wrap(parser.accept_keyword("as"), &parser, at)? {
+                        self.wrap(parser.expect_ident(), &parser, at)?
+                    } else {
+                        name.clone()
+                    };
+                    names.push((name, local));
+                    if *parser.current_token() != Token::Comma {
+                        break;
+                    }
+                    self.wrap(parser.next_token(), &parser, at)?;
+                }
+                self.wrap(parser.expect_end(), &parser, at)?;
+                self.imports.push(Import::Names { template, names });
+                Ok(None)
+            }
+            "macro" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let name = self.wrap(parser.expect_ident(), &parser, at)?;
+                self.wrap(parser.expect(Token::LeftParen), &parser, at)?;
+                let mut params = Vec::new();
+                while *parser.current_token() != Token::RightParen {
+                    let param = self.wrap(parser.expect_ident(), &parser, at)?;
+                    let default = if *parser.current_token() == Token::Assign {
+                        self.wrap(parser.next_token(), &parser, at)?;
+                        Some(self.wrap(parser.parse_expression(), &parser, at)?)
+                    } else {
+                        None
+                    };
+                    params.push((param, default));
+                    if *parser.current_token() != Token::Comma {
+                        break;
+                    }
+                    self.wrap(parser.next_token(), &parser, at)?;
+                }
+                self.wrap(parser.expect(Token::RightParen), &parser, at)?;
+                self.wrap(parser.expect_end(), &parser, at)?;
+                let (body, _) = self.parse_nodes(&["endmacro"], tag)?;
+                let duplicate = self.macros.insert(
+                    name.clone(),
+                    Macro {
+                        name: name.clone(),
+                        params,
+                        body,
+                    },
+                );
+                if duplicate.is_some() {
+                    return Err(self.error(at, format!("Macro `{}` defined twice", name)));
+                }
+                Ok(None)
+            }
+            "set" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let name = self.wrap(parser.expect_ident(), &parser, at)?;
+                self.wrap(parser.expect(Token::Assign), &parser, at)?;
+                let value_at = Span {
+                    line: at.line,
+                    column: at.column + parser.position(),
+                };
+                let value = self.wrap(parser.parse(), &parser, at)?;
+                Ok(Some(Node::Set {
+                    name,
+                    value,
+                    span: value_at,
+                }))
+            }
+            "block" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let name = self.wrap(parser.expect_ident(), &parser, at)?;
+                self.wrap(parser.expect_end(), &parser, at)?;
+                let (body, end) = self.parse_nodes(&["endblock"], tag)?;
+                let end = end.unwrap();
+                if !end.rest.is_empty() && end.rest != name {
+                    return Err(self.error(
+                        end.span,
+                        format!("`endblock {}` does not match `block {}`", end.rest, name),
+                    ));
+                }
+                if self.blocks.insert(name.clone(), body).is_some() {
+                    return Err(self.error(at, format!("Block `{}` defined twice", name)));
+                }
+                Ok(Some(Node::Block(name)))
+            }
+            "include" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let name = self.wrap(parser.parse_expression(), &parser, at)?;
+                let mut with = Vec::new();
+                if self.wrap(parser.accept_keyword("with"), &parser, at)? {
+                    loop {
+                        let key = self.wrap(parser.expect_ident(), &parser, at)?;
+                        self.wrap(parser.expect(Token::Assign), &parser, at)?;
+                        let value = self.wrap(parser.parse_expression(), &parser, at)?;
+                        with.push((key, value));
+                        if *parser.current_token() != Token::Comma {
+                            break;
+                        }
+                        self.wrap(parser.next_token(), &parser, at)?;
+                    }
+                }
+                let only = self.wrap(parser.accept_keyword("only"), &parser, at)?;
+                self.wrap(parser.expect_end(), &parser, at)?;
+                Ok(Some(Node::Include {
+                    name,
+                    with,
+                    only,
+                    span: at,
+                }))
+            }
+            "if" => {
+                let mut branches = Vec::new();
+                let mut condition = self.parse_expression(rest, at)?;
+                let mut span = at;
+                loop {
+                    let (body, end) = self.parse_nodes(&["elif", "else", "endif"], tag)?;
+                    branches.push(Branch {
+                        condition,
+                        span,
+                        body,
+                    });
+                    let end = end.unwrap();
+                    match end.keyword.as_str() {
+                        "elif" => {
+                            condition = self.parse_expression(&end.rest, end.span)?;
+                            span = end.span;
+                        }
+                        "else" => {
+                            let (otherwise, _) = self.parse_nodes(&["endif"], tag)?;
+                            return Ok(Some(Node::If {
+                                branches,
+                                otherwise,
+                            }));
+                        }
+                        _ => {
+                            return Ok(Some(Node::If {
+                                branches,
+                                otherwise: Vec::new(),
+                            }))
+                        }
+                    }
+                }
+            }
+            "for" => {
+                let mut parser = self.tag_parser(rest, at)?;
+                let var = self.wrap(parser.expect_ident(), &parser, at)?;
+                self.wrap(parser.expect_keyword("in"), &parser, at)?;
+                let iterable_at = Span {
+                    line: at.line,
+                    column: at.column + parser.position(),
+                };
+                let iterable = self.wrap(parser.parse_expression(), &parser, at)?;
+                self.wrap(parser.expect_end(), &parser, at)?;
+                let (body, end) = self.parse_nodes(&["else", "endfor"], tag)?;
+                let otherwise = if end.unwrap().keyword == "else" {
+                    self.parse_nodes(&["endfor"], tag)?.0
+                } else {
+                    Vec::new()
+                };
+                Ok(Some(Node::For {
+                    var,
+                    iterable,
+                    span: iterable_at,
+                    body,
+                    otherwise,
+                }))
+            }
+            "trans" => {
+                if !rest.is_empty() {
+                    return Err(self.error(
+                        at,
+                        "`trans` takes no arguments, use {% plural count %} for plurals",
+                    ));
+                }
+                let mut variables = Vec::new();
+                let (body, end) = self.parse_nodes(&["plural", "endtrans"], tag)?;
+                let singular = self.message(body, tag, &mut variables)?;
+                let end = end.unwrap();
+                let plural = if end.keyword == "plural" {
+                    let count = self.parse_expression(&end.rest, end.span)?;
+                    let (body, _) = self.parse_nodes(&["endtrans"], tag)?;
+                    Some((self.message(body, tag, &mut variables)?, count))
+                } else {
+                    None
+                };
+                Ok(Some(Node::Trans {
+                    singular,
+                    plural,
+                    variables,
+                    span: tag,
+                }))
+            }
+            "elif" | "else" | "endif" | "endfor" | "endblock" | "endmacro" | "endraw"
+            | "plural" | "endtrans" => Err(self.error(
+                tag,
+                format!(
+                    "Unexpected {{% {} %}} without a matching opening tag",
+                    keyword
+                ),
+            )),
+            _ => Err(self.error(tag, format!("Unknown tag `{}`", keyword))),
+        }
+    }
+
+    // The catalog id for the body of a `trans` block: text with `%` escaped as `%%`, and
+    // variables written as `%(name)s` and added to `variables`
+    fn message(
+        &self,
+        body: Vec<Node>,
+        tag: Span,
+        variables: &mut Vec<(String, Expr)>,
+    ) -> Result<String, TemplateError> {
+        let mut message = String::new();
+        for node in body {
+            match node {
+                Node::Text(text) => message.push_str(&text.replace('%', "%%")),
+                Node::Expr(Expr::Variable(path), _) => {
+                    let name = path.join(".");
+                    message.push_str(&format!("%({})s", name));
+                    if !variables.iter().any(|(n, _)| *n == name) {
+                        variables.push((name, Expr::Variable(path)));
+                    }
+                }
+                Node::Expr(_, span) => {
+                    return Err(self.error(
+                        span,
+                        "Only variables can be used inside {% trans %}; assign the value with {% set %} first",
+                    ))
+                }
+                _ => return Err(self.error(tag, "Only text and variables are allowed inside {% trans %}")),
+            }
+        }
+        Ok(message)
+    }
+
+    // Reads a template name given as a string literal, eg. in `extends "base.html"`
+    fn template_name(&self, parser: &mut Parser, at: Span) -> Result<String, TemplateError> {
+        match parser.current_token().clone() {
+            Token::Str(name) => {
+                self.wrap(parser.next_token(), parser, at)?;
+                Ok(name)
+            }
+            other => Err(self.error(
+                Span {
+                    line: at.line,
+                    column: at.column + parser.position(),
+                },
+                format!("Expected a template name string, got {:?}", other),
+            )),
+        }
+    }
+
+    fn parse_expression(&self, source: &str, at: Span) -> Result<Expr, TemplateError> {
+        let mut parser = self.tag_parser(source, at)?;
+        self.wrap(parser.parse(), &parser, at)
+    }
+
+    fn tag_parser<'a>(&self, source: &'a str, at: Span) -> Result<Parser<'a>, TemplateError> {
+        Parser::new(source).map_err(|e| self.relocate(e, at))
+    }
+
+    // Turns errors from the expression parser into syntax errors located at the parser's current token.
+    // `at` is where the parsed text starts in the template.
+    fn wrap<T>(
+        &self,
+        result: Result<T, TemplateError>,
+        parser: &Parser,
+        at: Span,
+    ) -> Result<T, TemplateError> {
+        result.map_err(|e| {
+            let at = Span {
+                line: at.line,
+                column: at.column + parser.position(),
+            };
+            self.relocate(e, at)
+        })
+    }
+
+    fn relocate(&self, e: TemplateError, at: Span) -> TemplateError {
+        match e.kind {
+            ErrorKind::ParseError(message) => self.error(at, message),
+            ErrorKind::LimitExceeded(_) if !e.has_location() => TemplateError::new(e.kind)
+                .with_location(&self.name, &self.source, at.line, at.column),
+            _ => e,
+        }
+    }
+
+    fn error<S: Into<String>>(&self, at: Span, message: S) -> TemplateError {
+        TemplateError::new(ErrorKind::SyntaxError(message.into())).with_location(
+            &self.name,
+            &self.source,
+            at.line,
+            at.column,
+        )
+    }
+}
+
+// Where `part`, a suffix of the chunk's content, starts in the template
+fn span_of(chunk: &Chunk, part: &str) -> Span {
+    let skipped = &chunk.content[..chunk.content.len() - part.len()];
+    Span {
+        line: chunk.line,
+        column: chunk.column + skipped.chars().count(),
+    }
+}
+
+// Splits `for item in items` into ("for", "item in items")
+fn split_keyword(content: &str) -> (&str, &str) {
+    match content.find(char::is_whitespace) {
+        Some(i) => (&content[..i], content[i..].trim()),
+        None => (content, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_blocks_test() {
+        let template = parse_template(
+            "child.html",
+            r#"{% extends "base.html" %}{% block title %}Home{% endblock title %}"#,
+        )
+        .unwrap();
+        assert_eq!(Some("base.html".to_string()), template.parent);
+        assert_eq!(vec![Node::Block("title".to_string())], template.nodes);
+        assert_eq!(
+            Some(&vec![Node::Text("Home".to_string())]),
+            template.blocks.get("title")
+        );
+    }
+    #[test]
+    fn parse_for_else_test() {
+        let template =
+            parse_template("t", "{% for x in xs %}{{ x }}{% else %}none{% endfor %}").unwrap();
+        match &template.nodes[0] {
+            Node::For { var, otherwise, .. } => {
+                assert_eq!("x", var);
+                assert_eq!(&vec![Node::Text("none".to_string())], otherwise);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    #[test]
+    fn missing_end_tag_test() {
+        let err = parse_template("t.html", "a\n{% if x %}b").unwrap_err();
+        assert_eq!(
+            ErrorKind::SyntaxError("Missing {% endif %}".to_string()),
+            err.kind
+        );
+        let location = err.location.unwrap();
+        assert_eq!((2, 4), (location.line, location.column));
+    }
+    #[test]
+    fn expression_error_column_test() {
+        let err = parse_template("t.html", "<p>\n  {{ name | }}</p>").unwrap_err();
+        assert_eq!(
+            "t.html:2:11: Syntax error: Expected filter name after '|', got EOF\n    |   {{ name | }}</p>\n    |           ^",
+            err.to_string()
+        );
+    }
+    #[test]
+    fn trans_test() {
+        let template = parse_template(
+            "t",
+            "{% trans %}{{ user.name }} has 100% of one{% plural n %}{{ user.name }} has {{ n }}{% endtrans %}",
+        )
+        .unwrap();
+        match &template.nodes[0] {
+            Node::Trans {
+                singular,
+                plural,
+                variables,
+                ..
+            } => {
+                assert_eq!("%(user.name)s has 100%% of one", singular);
+                assert_eq!("%(user.name)s has %(n)s", plural.as_ref().unwrap().0);
+                let names: Vec<&str> = variables.iter().map(|(n, _)| n.as_str()).collect();
+                assert_eq!(vec!["user.name", "n"], names);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let err = parse_template("t", "{% trans %}{{ name | upper }}{% endtrans %}").unwrap_err();
+        assert_eq!((1, 15), {
+            let location = err.location.unwrap();
+            (location.line, location.column)
+        });
+    }
+    #[test]
+    fn include_with_test() {
+        let template =
+            parse_template("t", r#"{% include "p.html" with user = me only %}"#).unwrap();
+        match &template.nodes[0] {
+            Node::Include { with, only, .. } => {
+                assert_eq!("user", with[0].0);
+                assert!(*only);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    #[test]
+    fn nesting_limit_test() {
+        let nested = |n: usize| "{% if x %}".repeat(n) + &"{% endif %}".repeat(n);
+        assert_eq!(12, parse_template("t", &nested(10)).unwrap().nesting);
+        let err = parse_template("t", &nested(1000)).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::LimitExceeded(_)));
+        let err = parse_template(
+            "t",
+            &format!("{{{{ {}x{} }}}}", "(".repeat(1000), ")".repeat(1000)),
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::LimitExceeded(_)));
+        assert_eq!(1, err.location.unwrap().line);
+    }
+}
@@ -0,0 +1,974 @@
+// Renders parsed templates, resolving `extends`, `block`, `super()`, `include` and macro calls
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::{did_you_mean, ErrorKind, TemplateError};
+use crate::expr::ast::Scope;
+use crate::filters::FilterRegistry;
+use crate::i18n::{self, Translations};
+use crate::loader::Loader;
+use crate::parser::MAX_NESTING;
+use crate::sandbox::{Meter, Sandbox};
+use crate::template::{Import, Macro, Node, Span, Template};
+use crate::value::Value;
+
+// How deeply templates, blocks and macro calls may stack up, adding up the nesting of each
+// (see Template::nesting); enough for a template nested as deeply as the parser allows
+pub const MAX_RENDER_NESTING: usize = 200;
+
+// What happens when a template uses a variable or attribute that is not defined
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UndefinedBehavior {
+    #[default]
+    Lenient, // 输出为空
+    Strict, // 报错, 并提示相近的变量名
+    Debug,  // 原样输出 {{ name }}, 方便找出遗漏的变量
+}
+
+impl FromStr for UndefinedBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lenient" => Ok(UndefinedBehavior::Lenient),
+            "strict" => Ok(UndefinedBehavior::Strict),
+            "debug" => Ok(UndefinedBehavior::Debug),
+            _ => Err(format!(
+                "Unknown undefined behavior `{}`, expected lenient, strict or debug",
+                s
+            )),
+        }
+    }
+}
+
+// Anything that can hand out parsed templates by name
+pub trait TemplateSource {
+    fn get_template(&self, name: &str) -> Result<Arc<Template>, TemplateError>;
+}
+
+// A plain loader parses the template every time it is requested
+impl<L: Loader + ?Sized> TemplateSource for L {
+    fn get_template(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let source = self.load(name)?;
+        Ok(Arc::new(Template::parse(name, &source)?))
+    }
+}
+
+pub struct Renderer<'a> {
+    source: &'a dyn TemplateSource,
+    filters: &'a FilterRegistry,
+    undefined: UndefinedBehavior,
+    sandbox: Option<&'a Sandbox>,
+    translations: Option<&'a Translations>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(source: &'a dyn TemplateSource, filters: &'a FilterRegistry) -> Self {
+        Renderer {
+            source,
+            filters,
+            undefined: UndefinedBehavior::default(),
+            sandbox: None,
+            translations: None,
+        }
+    }
+
+    pub fn undefined(mut self, behavior: UndefinedBehavior) -> Self {
+        self.undefined = behavior;
+        self
+    }
+
+    // Renders with the sandbox's limits, for templates that cannot be trusted
+    pub fn sandbox(mut self, sandbox: &'a Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    // Translates `{% trans %}` blocks and `_("...")` into the locale named by the context's
+    // `locale` variable. Without translations they render as written.
+    pub fn translations(mut self, translations: &'a Translations) -> Self {
+        self.translations = Some(translations);
+        self
+    }
+
+    // Renders the named template with the given context
+    pub fn render(
+        &self,
+        name: &str,
+        context: &HashMap<String, Value>,
+    ) -> Result<String, TemplateError> {
+        let mut state = self.state(context, None);
+        state.render_template(name)?;
+        Ok(state.out)
+    }
+
+    // Renders the named template straight into `out` as the template is walked, so the output
+    // is never held in memory as a whole. Writes are buffered; rendering stops at the first
+    // error, leaving whatever was written before it in `out`.
+    pub fn render_to<W: Write>(
+        &self,
+        name: &str,
+        context: &HashMap<String, Value>,
+        out: &mut W,
+    ) -> Result<(), TemplateError> {
+        let mut writer = BufWriter::new(out);
+        let mut state = self.state(context, Some(&mut writer));
+        state.render_template(name)?;
+        writer.flush().map_err(write_error)
+    }
+
+    fn state<'s>(
+        &'s self,
+        context: &'s HashMap<String, Value>,
+        sink: Option<&'s mut dyn Write>,
+    ) -> RenderState<'s> {
+        RenderState {
+            source: self.source,
+            filters: self.filters,
+            undefined: self.undefined,
+            globals: context,
+            // Holds variables assigned with `set` at the top level
+            frames: vec![Frame {
+                vars: HashMap::new(),
+                isolated: false,
+                loop_info: false,
+            }],
+            chain: Vec::new(),
+            block_stack: Vec::new(),
+            include_stack: Vec::new(),
+            current: Vec::new(),
+            out: String::new(),
+            sink,
+            capturing: 0,
+            meter: self.sandbox.map(Meter::new),
+            macro_depth: 0,
+            nesting: 0,
+            translations: self.translations,
+        }
+    }
+}
+
+// Variables introduced by `for`, `set`, `include ... with` and macro parameters.
+// An isolated frame hides everything below it, for `include ... only` and macro bodies.
+struct Frame {
+    vars: HashMap<String, Value>,
+    isolated: bool,
+    // `loop` holds the loop information of a `for`, rather than a value set by the template.
+    // Only then are its attributes allowed by a sandbox whatever its allow-list.
+    loop_info: bool,
+}
+
+struct RenderState<'a> {
+    source: &'a dyn TemplateSource,
+    filters: &'a FilterRegistry,
+    undefined: UndefinedBehavior,
+    globals: &'a HashMap<String, Value>,
+    frames: Vec<Frame>,
+    // The template being rendered followed by the templates it extends, most derived first
+    chain: Vec<Arc<Template>>,
+    // Blocks currently being rendered, with the position in `chain` of the definition in use
+    block_stack: Vec<(String, usize)>,
+    // Templates currently being rendered, to detect include cycles
+    include_stack: Vec<String>,
+    // Templates whose nodes are being rendered, innermost last; errors are located in the last one
+    current: Vec<Arc<Template>>,
+    // The rendered text when rendering to a String; when streaming, only text being captured
+    out: String,
+    // Set by render_to: text is written here unless it is being captured
+    sink: Option<&'a mut dyn Write>,
+    // Depth of nested captures, for `super()` and macro calls whose output becomes a value
+    capturing: usize,
+    // Set when rendering in a sandbox
+    meter: Option<Meter<'a>>,
+    macro_depth: usize,
+    // The nesting of the templates, blocks and macros being rendered, added up
+    nesting: usize,
+    translations: Option<&'a Translations>,
+}
+
+impl<'a> RenderState<'a> {
+    fn render_template(&mut self, name: &str) -> Result<(), TemplateError> {
+        if self.include_stack.iter().any(|n| n == name) {
+            let mut cycle = self.include_stack.clone();
+            cycle.push(name.to_string());
+            return Err(ErrorKind::CyclicTemplate(cycle.join(" -> ")).into());
+        }
+        self.check_depth()?;
+        let chain = self.load_chain(name)?;
+        let root = chain[chain.len() - 1].clone();
+
+        self.include_stack.push(name.to_string());
+        let saved_chain = mem::replace(&mut self.chain, chain);
+        let saved_blocks = mem::take(&mut self.block_stack);
+        self.current.push(root.clone());
+        let result = self.render_nested(&root, &root.nodes);
+        self.current.pop();
+        self.chain = saved_chain;
+        self.block_stack = saved_blocks;
+        self.include_stack.pop();
+        result
+    }
+
+    // Loads a template, refusing it if it nests deeper than the sandbox allows
+    fn template(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let template = self.source.get_template(name)?;
+        if let Some(meter) = &self.meter {
+            meter.nesting(&template)?;
+        }
+        Ok(template)
+    }
+
+    // Follows `extends` from the named template up to the root layout
+    fn load_chain(&self, name: &str) -> Result<Vec<Arc<Template>>, TemplateError> {
+        let mut chain: Vec<Arc<Template>> = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            if chain.iter().any(|t| t.name == name) {
+                let mut cycle: Vec<&str> = chain.iter().map(|t| t.name.as_str()).collect();
+                cycle.push(&name);
+                return Err(ErrorKind::CyclicTemplate(cycle.join(" -> ")).into());
+            }
+            let template = self.template(&name)?;
+            next = template.parent.clone();
+            chain.push(template);
+        }
+        Ok(chain)
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node]) -> Result<(), TemplateError> {
+        for node in nodes {
+            self.render_node(node)?;
+        }
+        Ok(())
+    }
+
+    // Renders `nodes` of `template`, whose tags and expressions stack up on those of the
+    // templates, blocks and macros that led to it
+    fn render_nested(&mut self, template: &Template, nodes: &[Node]) -> Result<(), TemplateError> {
+        if self.nesting + template.nesting > MAX_RENDER_NESTING {
+            return Err(ErrorKind::LimitExceeded(format!(
+                "templates, blocks and macro calls nested more than {} levels deep",
+                MAX_RENDER_NESTING
+            ))
+            .into());
+        }
+        self.nesting += template.nesting;
+        let result = self.render_nodes(nodes);
+        self.nesting -= template.nesting;
+        result
+    }
+
+    fn render_node(&mut self, node: &Node) -> Result<(), TemplateError> {
+        if let Some(meter) = &mut self.meter {
+            meter.tick()?;
+        }
+        let filters = self.filters;
+        match node {
+            Node::Text(text) => self.write(text)?,
+            Node::Expr(expr, span) => {
+                let value = expr
+                    .eval(self, filters)
+                    .map_err(|e| self.locate(e, *span))?;
+                match value {
+                    Value::Undefined(name) if self.undefined == UndefinedBehavior::Debug => {
+                        self.write(&format!("{{{{ {} }}}}", name))?
+                    }
+                    value => self.write(&value.to_string())?,
+                }
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                for branch in branches {
+                    let condition = branch
+                        .condition
+                        .eval(self, filters)
+                        .map_err(|e| self.locate(e, branch.span))?;
+                    if condition.is_truthy() {
+                        return self.render_nodes(&branch.body);
+                    }
+                }
+                self.render_nodes(otherwise)?;
+            }
+            Node::For {
+                var,
+                iterable,
+                span,
+                body,
+                otherwise,
+            } => {
+                let iterable = iterable
+                    .eval(self, filters)
+                    .map_err(|e| self.locate(e, *span))?;
+                let type_name = iterable.type_name();
+                let items = match iterable.into_items() {
+                    Some(items) => items,
+                    None => {
+                        let e =
+                            ErrorKind::RenderError(format!("Cannot iterate over a {}", type_name));
+                        return Err(self.locate(e.into(), *span));
+                    }
+                };
+                if items.is_empty() {
+                    return self.render_nodes(otherwise);
+                }
+                let length = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    if let Some(meter) = &mut self.meter {
+                        meter.iteration().map_err(|e| self.locate(e, *span))?;
+                    }
+                    let mut vars = HashMap::new();
+                    vars.insert(var.clone(), item);
+                    vars.insert("loop".to_string(), loop_info(i, length));
+                    self.frames.push(Frame {
+                        vars,
+                        isolated: false,
+                        loop_info: true,
+                    });
+                    let result = self.render_nodes(body);
+                    self.frames.pop();
+                    result?;
+                }
+            }
+            Node::Block(name) => {
+                self.render_block(name, 0)?;
+            }
+            Node::Include {
+                name,
+                with,
+                only,
+                span,
+            } => {
+                let name = name
+                    .eval(self, filters)
+                    .map_err(|e| self.locate(e, *span))?
+                    .to_string();
+                let mut vars = HashMap::new();
+                for (key, value) in with {
+                    let value = value
+                        .eval(self, filters)
+                        .map_err(|e| self.locate(e, *span))?;
+                    vars.insert(key.clone(), value);
+                }
+                self.frames.push(Frame {
+                    vars,
+                    isolated: *only,
+                    loop_info: false,
+                });
+                let result = self
+                    .render_template(&name)
+                    .map_err(|e| self.locate(e, *span));
+                self.frames.pop();
+                result?;
+            }
+            Node::Set { name, value, span } => {
+                let value = value
+                    .eval(self, filters)
+                    .map_err(|e| self.locate(e, *span))?;
+                if let Some(frame) = self.frames.last_mut() {
+                    if name == "loop" {
+                        frame.loop_info = false;
+                    }
+                    frame.vars.insert(name.clone(), value);
+                }
+            }
+            Node::Trans {
+                singular,
+                plural,
+                variables,
+                span,
+            } => {
+                let mut values = HashMap::new();
+                for (name, expr) in variables {
+                    let value = expr
+                        .eval(self, filters)
+                        .map_err(|e| self.locate(e, *span))?;
+                    values.insert(name.clone(), value.to_string());
+                }
+                let plural = match plural {
+                    Some((message, count)) => {
+                        let count = count
+                            .eval(self, filters)
+                            .map_err(|e| self.locate(e, *span))?;
+                        let n = count.as_number().ok_or_else(|| {
+                            let e = ErrorKind::RenderError(format!(
+                                "The `plural` count must be a number, got a {}",
+                                count.type_name()
+                            ));
+                            self.locate(e.into(), *span)
+                        })?;
+                        Some((message.as_str(), n.abs() as u64))
+                    }
+                    None => None,
+                };
+                let message = i18n::format(self.translate(singular, plural), &values);
+                self.write(&message)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The message in the context's locale
+    fn translate<'m>(&'m self, msgid: &'m str, plural: Option<(&'m str, u64)>) -> &'m str {
+        let locale = match self.lookup("locale") {
+            Some(Value::Str(locale)) => Some(locale.as_str()),
+            _ => None,
+        };
+        match self.translations {
+            Some(translations) => translations.translate(locale, msgid, plural),
+            None => i18n::untranslated(msgid, plural),
+        }
+    }
+
+    // Renders the first definition of a block found in the chain at or after `level`
+    fn render_block(&mut self, name: &str, level: usize) -> Result<bool, TemplateError> {
+        let found = self.chain[level..]
+            .iter()
+            .position(|t| t.blocks.contains_key(name));
+        let level = match found {
+            Some(i) => level + i,
+            None => return Ok(false),
+        };
+        let template = self.chain[level].clone();
+        self.block_stack.push((name.to_string(), level));
+        self.current.push(template.clone());
+        let result = self.render_nested(&template, &template.blocks[name]);
+        self.current.pop();
+        self.block_stack.pop();
+        result.map(|_| true)
+    }
+
+    // Finds the macro called `name`, or `alias.name` for a namespace import, returning the
+    // template that defines it. Looks in the template being rendered, including its imports,
+    // then in the templates it extends.
+    fn find_macro(&self, name: &str) -> Result<Option<(Arc<Template>, String)>, TemplateError> {
+        let templates = self.current.last().into_iter().chain(self.chain.iter());
+        for template in templates {
+            if let Some((alias, macro_name)) = name.split_once('.') {
+                for import in &template.imports {
+                    if let Import::Module {
+                        template: module,
+                        alias: a,
+                    } = import
+                    {
+                        if a == alias {
+                            return self.imported_macro(module, macro_name).map(Some);
+                        }
+                    }
+                }
+                continue;
+            }
+            if template.macros.contains_key(name) {
+                return Ok(Some((template.clone(), name.to_string())));
+            }
+            for import in &template.imports {
+                if let Import::Names {
+                    template: module,
+                    names,
+                } = import
+                {
+                    if let Some((macro_name, _)) = names.iter().find(|(_, local)| local == name) {
+                        return self.imported_macro(module, macro_name).map(Some);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn imported_macro(
+        &self,
+        module: &str,
+        name: &str,
+    ) -> Result<(Arc<Template>, String), TemplateError> {
+        let template = self.template(module)?;
+        if !template.macros.contains_key(name) {
+            let e = ErrorKind::RenderError(format!("`{}` has no macro `{}`", module, name));
+            let hint = did_you_mean(name, template.macros.keys());
+            return Err(match hint {
+                Some(hint) => TemplateError::new(e).with_hint(hint),
+                None => e.into(),
+            });
+        }
+        Ok((template, name.to_string()))
+    }
+
+    // Renders a macro body with its parameters bound to `args`, returning the output
+    fn call_macro(
+        &mut self,
+        template: Arc<Template>,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, TemplateError> {
+        let m = &template.macros[name];
+        if args.len() > m.params.len() {
+            return Err(ErrorKind::RenderError(format!(
+                "Macro `{}` takes {} arguments, got {}",
+                name,
+                m.params.len(),
+                args.len()
+            ))
+            .into());
+        }
+        self.check_depth()?;
+        self.frames.push(Frame {
+            vars: HashMap::new(),
+            isolated: true,
+            loop_info: false,
+        });
+        self.macro_depth += 1;
+        self.current.push(template.clone());
+        let saved = self.begin_capture();
+        let result = self.render_macro_body(&template, m, args);
+        let rendered = self.end_capture(saved);
+        self.current.pop();
+        self.macro_depth -= 1;
+        self.frames.pop();
+        result.map(|_| Value::Str(rendered))
+    }
+
+    // Binds the parameters in the macro's frame; missing ones take their default or stay undefined
+    fn render_macro_body(
+        &mut self,
+        template: &Template,
+        m: &Macro,
+        args: Vec<Value>,
+    ) -> Result<(), TemplateError> {
+        let filters = self.filters;
+        let mut args = args.into_iter();
+        for (param, default) in &m.params {
+            let value = match (args.next(), default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.eval(self, filters)?,
+                (None, None) => Value::Undefined(param.clone()),
+            };
+            if let Some(frame) = self.frames.last_mut() {
+                frame.vars.insert(param.clone(), value);
+            }
+        }
+        self.render_nested(template, &m.body)
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), TemplateError> {
+        if let Some(meter) = &mut self.meter {
+            let captured = (self.capturing > 0).then_some(self.out.len());
+            meter.output(text.len(), captured)?;
+        }
+        match &mut self.sink {
+            Some(sink) if self.capturing == 0 => {
+                sink.write_all(text.as_bytes()).map_err(write_error)
+            }
+            _ => {
+                self.out.push_str(text);
+                Ok(())
+            }
+        }
+    }
+
+    // Called before rendering a template or a macro call, which will sit this many levels
+    // below the top-level template
+    fn check_depth(&self) -> Result<(), TemplateError> {
+        let depth = self.include_stack.len() + self.macro_depth;
+        // Whatever the sandbox allows, deeper recursion would overflow the stack
+        if depth > MAX_NESTING {
+            return Err(ErrorKind::LimitExceeded(format!(
+                "includes and macro calls nested more than {} deep",
+                MAX_NESTING
+            ))
+            .into());
+        }
+        match &self.meter {
+            Some(meter) => meter.depth(depth),
+            None => Ok(()),
+        }
+    }
+
+    // Starts collecting output in `out` instead of writing it; returns what `out` held before
+    fn begin_capture(&mut self) -> String {
+        self.capturing += 1;
+        mem::take(&mut self.out)
+    }
+
+    // Returns the captured output and restores `out`
+    fn end_capture(&mut self, saved: String) -> String {
+        self.capturing -= 1;
+        mem::replace(&mut self.out, saved)
+    }
+
+    // Points an error that has no location yet at `span` in the template being rendered
+    fn locate(&self, e: TemplateError, span: Span) -> TemplateError {
+        match self.current.last() {
+            Some(template) if !e.has_location() => {
+                e.with_location(&template.name, &template.source, span.line, span.column)
+            }
+            _ => e,
+        }
+    }
+
+    // `{{ super() }}` renders the parent template's version of the current block
+    fn render_super(&mut self) -> Result<Value, TemplateError> {
+        let (name, level) = self.block_stack.last().cloned().ok_or_else(|| {
+            ErrorKind::RenderError("`super()` used outside of a block".to_string())
+        })?;
+        let saved = self.begin_capture();
+        let result = self.render_block(&name, level + 1);
+        let rendered = self.end_capture(saved);
+        if !result? {
+            return Err(ErrorKind::RenderError(format!(
+                "`super()` used in block `{}`, which has no parent block",
+                name
+            ))
+            .into());
+        }
+        Ok(Value::Str(rendered))
+    }
+}
+
+impl<'a> Scope for RenderState<'a> {
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        for frame in self.frames.iter().rev() {
+            if let Some(value) = frame.vars.get(name) {
+                return Some(value);
+            }
+            if frame.isolated {
+                return None;
+            }
+        }
+        self.globals.get(name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for frame in self.frames.iter().rev() {
+            names.extend(frame.vars.keys().cloned());
+            if frame.isolated {
+                return names;
+            }
+        }
+        names.extend(self.globals.keys().cloned());
+        names
+    }
+
+    fn check_filter(&self, name: &str) -> Result<(), TemplateError> {
+        match &self.meter {
+            Some(meter) => meter.filter(name),
+            None => Ok(()),
+        }
+    }
+
+    fn check_attribute(&self, variable: &str, attribute: &str) -> Result<(), TemplateError> {
+        let meter = match &self.meter {
+            Some(meter) => meter,
+            None => return Ok(()),
+        };
+        // The frame `loop` is found in, as in lookup
+        if variable == "loop" {
+            for frame in self.frames.iter().rev() {
+                if frame.vars.contains_key(variable) {
+                    if frame.loop_info {
+                        return Ok(());
+                    }
+                    break;
+                }
+                if frame.isolated {
+                    break;
+                }
+            }
+        }
+        meter.attribute(attribute)
+    }
+
+    fn undefined(&self, name: &str, candidates: &[String]) -> Result<Value, TemplateError> {
+        if self.undefined != UndefinedBehavior::Strict {
+            return Ok(Value::Undefined(name.to_string()));
+        }
+        let e = TemplateError::new(ErrorKind::UndefinedVariable(name.to_string()));
+        let last = name.rsplit('.').next().unwrap_or(name);
+        Err(match did_you_mean(last, candidates) {
+            Some(hint) => e.with_hint(hint),
+            None => e,
+        })
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, TemplateError> {
+        match name {
+            "super" if args.is_empty() => self.render_super(),
+            "_" => match args.as_slice() {
+                // Catalogs escape `%` as in `{% trans %}` messages
+                [Value::Str(msgid)] => {
+                    let msgid = msgid.replace('%', "%%");
+                    let message = i18n::format(self.translate(&msgid, None), &HashMap::new());
+                    Ok(Value::Str(message))
+                }
+                _ => Err(ErrorKind::RenderError(
+                    "`_()` takes one string, the message to translate".to_string(),
+                )
+                .into()),
+            },
+            _ => match self.find_macro(name)? {
+                Some((template, name)) => self.call_macro(template, &name, args),
+                None => Err(ErrorKind::RenderError(format!("Unknown function `{}`", name)).into()),
+            },
+        }
+    }
+}
+
+fn write_error(e: std::io::Error) -> TemplateError {
+    ErrorKind::WriteError(e.to_string()).into()
+}
+
+// The `loop` variable available inside `for`: index (from 1), index0, first, last and length
+pub(crate) fn loop_info(i: usize, length: usize) -> Value {
+    let mut info = HashMap::new();
+    info.insert("index".to_string(), Value::from((i + 1) as i64));
+    info.insert("index0".to_string(), Value::from(i as i64));
+    info.insert("first".to_string(), Value::Bool(i == 0));
+    info.insert("last".to_string(), Value::Bool(i + 1 == length));
+    info.insert("length".to_string(), Value::from(length as i64));
+    Value::Map(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::MemoryLoader;
+
+    fn render(loader: &MemoryLoader, name: &str) -> Result<String, TemplateError> {
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), Value::from("Bob"));
+        context.insert("items".to_string(), Value::from(vec!["a", "b"]));
+        let filters = FilterRegistry::new();
+        Renderer::new(loader, &filters).render(name, &context)
+    }
+
+    #[test]
+    fn extends_and_super_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "base.html",
+            "<title>{% block title %}Site{% endblock %}</title>{% block body %}{% endblock %}",
+        );
+        loader.add(
+            "page.html",
+            r#"{% extends "base.html" %}{% block title %}Home - {{ super() }}{% endblock %}"#,
+        );
+        assert_eq!(
+            Ok("<title>Home - Site</title>".to_string()),
+            render(&loader, "page.html")
+        );
+    }
+    #[test]
+    fn three_level_inheritance_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("a", "[{% block x %}a{% endblock %}]");
+        loader.add(
+            "b",
+            r#"{% extends "a" %}{% block x %}b{{ super() }}{% endblock %}"#,
+        );
+        loader.add(
+            "c",
+            r#"{% extends "b" %}{% block x %}c{{ super() }}{% endblock %}"#,
+        );
+        assert_eq!(Ok("[cba]".to_string()), render(&loader, "c"));
+    }
+    #[test]
+    fn include_with_scoped_context_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("greet.html", "Hi {{ who }}{{ name }}!");
+        loader.add(
+            "page.html",
+            r#"{% include "greet.html" with who = name | upper %}|{% include "greet.html" with who = "x" only %}"#,
+        );
+        assert_eq!(
+            Ok("Hi BOBBob!|Hi x!".to_string()),
+            render(&loader, "page.html")
+        );
+    }
+    #[test]
+    fn for_and_if_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "list.html",
+            "{% for i in items %}{% if loop.first %}{{ i }}{% else %},{{ i }}{% endif %}{% endfor %}",
+        );
+        assert_eq!(Ok("a,b".to_string()), render(&loader, "list.html"));
+    }
+    #[test]
+    fn deepest_template_test() {
+        // As deep as the parser allows, in a test thread with its smaller stack
+        let mut loader = MemoryLoader::new();
+        let n = crate::parser::MAX_NESTING;
+        let source = "{% if name %}".repeat(n)
+            + "{{ "
+            + &"not ".repeat(crate::expr::parser::MAX_NESTING - 1)
+            + "name }}"
+            + &"{% endif %}".repeat(n);
+        loader.add("deep.html", &source);
+        assert_eq!(Ok("false".to_string()), render(&loader, "deep.html"));
+    }
+    #[test]
+    fn recursive_macro_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "loop.html",
+            "{% macro f(n) %}{{ f(n) }}{% endmacro %}{{ f(1) }}",
+        );
+        assert_eq!(
+            Err(ErrorKind::LimitExceeded(format!(
+                "includes and macro calls nested more than {} deep",
+                MAX_NESTING
+            ))),
+            render(&loader, "loop.html").map_err(|e| e.kind)
+        );
+        // Each call as deep as the parser allows
+        let n = MAX_NESTING - 2;
+        let source = "{% macro f(n) %}".to_string()
+            + &"{% if true %}".repeat(n)
+            + "{{ f(n) }}"
+            + &"{% endif %}".repeat(n)
+            + "{% endmacro %}{{ f(1) }}";
+        loader.add("deep.html", &source);
+        assert!(matches!(
+            render(&loader, "deep.html").map_err(|e| e.kind),
+            Err(ErrorKind::LimitExceeded(_))
+        ));
+    }
+    #[test]
+    fn missing_template_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("page.html", r#"{% extends "missing.html" %}"#);
+        assert_eq!(
+            Err(ErrorKind::TemplateNotFound("missing.html".to_string())),
+            render(&loader, "page.html").map_err(|e| e.kind)
+        );
+    }
+    #[test]
+    fn cycle_detection_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("a.html", r#"{% extends "b.html" %}"#);
+        loader.add("b.html", r#"{% extends "a.html" %}"#);
+        loader.add("c.html", r#"{% include "c.html" %}"#);
+        assert_eq!(
+            Err(ErrorKind::CyclicTemplate(
+                "a.html -> b.html -> a.html".to_string()
+            )),
+            render(&loader, "a.html").map_err(|e| e.kind)
+        );
+        assert_eq!(
+            Err(ErrorKind::CyclicTemplate("c.html -> c.html".to_string())),
+            render(&loader, "c.html").map_err(|e| e.kind)
+        );
+    }
+    #[test]
+    fn strict_undefined_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("page.html", "<h1>\n<p>{{ nmae | upper }}</p>");
+        let filters = FilterRegistry::new();
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), Value::from("Bob"));
+        let err = Renderer::new(&loader, &filters)
+            .undefined(UndefinedBehavior::Strict)
+            .render("page.html", &context)
+            .unwrap_err();
+        assert_eq!(
+            "page.html:2:7: Undefined variable `nmae`\n    | <p>{{ nmae | upper }}</p>\n    |       ^\n    = hint: did you mean `name`?",
+            err.to_string()
+        );
+    }
+    #[test]
+    fn undefined_modes_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "page.html",
+            "[{{ user.city }}][{{ missing | default(\"-\") }}]",
+        );
+        let filters = FilterRegistry::new();
+        let context = HashMap::new();
+        let render = |behavior| {
+            Renderer::new(&loader, &filters)
+                .undefined(behavior)
+                .render("page.html", &context)
+        };
+        assert_eq!(Ok("[][-]".to_string()), render(UndefinedBehavior::Lenient));
+        assert_eq!(
+            Ok("[{{ user }}][-]".to_string()),
+            render(UndefinedBehavior::Debug)
+        );
+        assert_eq!(
+            Err(ErrorKind::UndefinedVariable("user".to_string())),
+            render(UndefinedBehavior::Strict).map_err(|e| e.kind)
+        );
+    }
+    #[test]
+    fn macros_and_imports_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "ui.html",
+            r#"{% macro button(label, href = "/help") %}<a href="{{ href }}">{{ label }}</a>{% endmacro %}"#,
+        );
+        loader.add(
+            "page.html",
+            r#"{% import "ui.html" as ui %}{% from "ui.html" import button as b %}{% macro em(x) %}<em>{{ x }}</em>{% endmacro %}{{ ui.button("Home", "/") }}{{ b(name) }}{{ em(name) }}"#,
+        );
+        assert_eq!(
+            Ok(r#"<a href="/">Home</a><a href="/help">Bob</a><em>Bob</em>"#.to_string()),
+            render(&loader, "page.html")
+        );
+    }
+    #[test]
+    fn set_is_scoped_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "page.html",
+            r#"{% set title = "Home" %}{% for i in items %}{% set title = i %}{{ title }}{% endfor %}{{ title }}"#,
+        );
+        assert_eq!(Ok("abHome".to_string()), render(&loader, "page.html"));
+    }
+    #[test]
+    fn whitespace_control_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add(
+            "list.html",
+            "<ul>\n  {%- for i in items %}\n  <li>{{ i }}</li>\n  {%- endfor %}\n</ul>{# done #}",
+        );
+        assert_eq!(
+            Ok("<ul>\n  <li>a</li>\n  <li>b</li>\n</ul>".to_string()),
+            render(&loader, "list.html")
+        );
+    }
+    #[test]
+    fn render_to_matches_render_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("base.html", "<h1>{% block title %}Site{% endblock %}</h1>");
+        loader.add(
+            "page.html",
+            r#"{% extends "base.html" %}{% macro em(x) %}<em>{{ x }}</em>{% endmacro %}{% block title %}{{ em(name) }} - {{ super() }}{% endblock %}"#,
+        );
+        let filters = FilterRegistry::new();
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), Value::from("Bob"));
+        let renderer = Renderer::new(&loader, &filters);
+        let mut out = Vec::new();
+        renderer.render_to("page.html", &context, &mut out).unwrap();
+        assert_eq!(
+            renderer.render("page.html", &context).unwrap(),
+            String::from_utf8(out).unwrap()
+        );
+    }
+    #[test]
+    fn render_to_stops_at_first_error_test() {
+        let mut loader = MemoryLoader::new();
+        loader.add("page.html", "before {{ name | shout }} after");
+        let filters = FilterRegistry::new();
+        let mut out = Vec::new();
+        let err = Renderer::new(&loader, &filters)
+            .render_to("page.html", &HashMap::new(), &mut out)
+            .unwrap_err();
+        assert_eq!(ErrorKind::UnknownFilter("shout".to_string()), err.kind);
+        assert_eq!(b"before ".to_vec(), out);
+    }
+}
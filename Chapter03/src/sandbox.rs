@@ -0,0 +1,319 @@
+// Limits for rendering untrusted templates. A Sandbox bounds the work a template can make the
+// renderer do and what it can reach; going over a limit stops rendering with LimitExceeded,
+// using a filter or attribute that is not allowed stops it with NotAllowed.
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::error::{ErrorKind, TemplateError};
+use crate::template::Template;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    // Loop iterations over the whole render, counting every `for` loop
+    pub max_loop_iterations: usize,
+    // Bytes of output, including output captured by macro calls and `super()`
+    pub max_output: usize,
+    // How deeply includes and macro calls may nest
+    pub max_include_depth: usize,
+    // How deeply the tags and expressions of a template may nest, counting the template
+    // itself as one level. Parsing already refuses tags nested deeper than parser::MAX_NESTING
+    // and expressions deeper than expr::parser::MAX_NESTING, whatever this says.
+    pub max_nesting: usize,
+    // Units of work: one per node rendered and one per loop iteration
+    pub fuel: u64,
+    pub timeout: Duration,
+    // None allows everything
+    pub allowed_filters: Option<HashSet<String>>,
+    pub allowed_attributes: Option<HashSet<String>>,
+}
+
+impl Sandbox {
+    // Limits suitable for templates written by untrusted users; every filter and attribute is allowed
+    pub fn new() -> Self {
+        Sandbox {
+            max_loop_iterations: 10_000,
+            max_output: 1024 * 1024,
+            max_include_depth: 10,
+            max_nesting: 32,
+            fuel: 1_000_000,
+            timeout: Duration::from_secs(1),
+            allowed_filters: None,
+            allowed_attributes: None,
+        }
+    }
+
+    pub fn max_loop_iterations(mut self, iterations: usize) -> Self {
+        self.max_loop_iterations = iterations;
+        self
+    }
+
+    pub fn max_output(mut self, bytes: usize) -> Self {
+        self.max_output = bytes;
+        self
+    }
+
+    pub fn max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = depth;
+        self
+    }
+
+    pub fn max_nesting(mut self, depth: usize) -> Self {
+        self.max_nesting = depth;
+        self
+    }
+
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Only these filters may be used, eg. allow_filters(&["upper", "default"])
+    pub fn allow_filters(mut self, filters: &[&str]) -> Self {
+        self.allowed_filters = Some(filters.iter().map(|f| f.to_string()).collect());
+        self
+    }
+
+    // Only these attributes may be read with `.`, eg. `user.name`. The attributes of the
+    // `loop` variable inside `for` are always allowed.
+    pub fn allow_attributes(mut self, attributes: &[&str]) -> Self {
+        self.allowed_attributes = Some(attributes.iter().map(|a| a.to_string()).collect());
+        self
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox::new()
+    }
+}
+
+// Tracks what one render has used against the sandbox limits
+pub(crate) struct Meter<'a> {
+    sandbox: &'a Sandbox,
+    started: Instant,
+    fuel_used: u64,
+    iterations: usize,
+    written: usize,
+}
+
+impl<'a> Meter<'a> {
+    pub(crate) fn new(sandbox: &'a Sandbox) -> Self {
+        Meter {
+            sandbox,
+            started: Instant::now(),
+            fuel_used: 0,
+            iterations: 0,
+            written: 0,
+        }
+    }
+
+    // Called once per unit of work; also checks the timeout
+    pub(crate) fn tick(&mut self) -> Result<(), TemplateError> {
+        self.fuel_used += 1;
+        if self.fuel_used > self.sandbox.fuel {
+            return Err(limit(format!(
+                "out of fuel after {} steps",
+                self.sandbox.fuel
+            )));
+        }
+        if self.started.elapsed() > self.sandbox.timeout {
+            return Err(limit(format!(
+                "rendering took longer than {:?}",
+                self.sandbox.timeout
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn iteration(&mut self) -> Result<(), TemplateError> {
+        self.iterations += 1;
+        if self.iterations > self.sandbox.max_loop_iterations {
+            return Err(limit(format!(
+                "more than {} loop iterations",
+                self.sandbox.max_loop_iterations
+            )));
+        }
+        self.tick()
+    }
+
+    // `captured` is the size of the capture buffer the text goes to, if it is being captured
+    pub(crate) fn output(
+        &mut self,
+        len: usize,
+        captured: Option<usize>,
+    ) -> Result<(), TemplateError> {
+        let total = match captured {
+            Some(buffer) => buffer + len,
+            None => {
+                self.written += len;
+                self.written
+            }
+        };
+        if total > self.sandbox.max_output {
+            return Err(limit(format!(
+                "output larger than {} bytes",
+                self.sandbox.max_output
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn depth(&self, depth: usize) -> Result<(), TemplateError> {
+        if depth > self.sandbox.max_include_depth {
+            return Err(limit(format!(
+                "includes and macro calls nested more than {} deep",
+                self.sandbox.max_include_depth
+            )));
+        }
+        Ok(())
+    }
+
+    // Called for every template the render loads
+    pub(crate) fn nesting(&self, template: &Template) -> Result<(), TemplateError> {
+        if template.nesting > self.sandbox.max_nesting {
+            return Err(limit(format!(
+                "`{}` nests tags and expressions more than {} deep",
+                template.name, self.sandbox.max_nesting
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn filter(&self, name: &str) -> Result<(), TemplateError> {
+        match &self.sandbox.allowed_filters {
+            Some(allowed) if !allowed.contains(name) => {
+                Err(ErrorKind::NotAllowed(format!("filter `{}` is not allowed", name)).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn attribute(&self, attribute: &str) -> Result<(), TemplateError> {
+        match &self.sandbox.allowed_attributes {
+            Some(allowed) if !allowed.contains(attribute) => Err(ErrorKind::NotAllowed(format!(
+                "attribute `{}` is not allowed",
+                attribute
+            ))
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn limit(message: String) -> TemplateError {
+    ErrorKind::LimitExceeded(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterRegistry;
+    use crate::loader::MemoryLoader;
+    use crate::render::Renderer;
+    use crate::value::Value;
+    use std::collections::HashMap;
+
+    fn render(source: &str, sandbox: &Sandbox) -> Result<String, TemplateError> {
+        let mut loader = MemoryLoader::new();
+        loader.add("t.html", source);
+        let mut user = HashMap::new();
+        user.insert("name".to_string(), Value::from("Bob"));
+        user.insert("password".to_string(), Value::from("secret"));
+        let mut context = HashMap::new();
+        context.insert("user".to_string(), Value::Map(user));
+        context.insert(
+            "items".to_string(),
+            Value::List((0..100).map(Value::from).collect::<Vec<Value>>()),
+        );
+        let filters = FilterRegistry::new();
+        Renderer::new(&loader, &filters)
+            .sandbox(sandbox)
+            .render("t.html", &context)
+    }
+
+    fn kind(result: Result<String, TemplateError>) -> Result<String, ErrorKind> {
+        result.map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn loop_and_output_limits_test() {
+        let nested = "{% for a in items %}{% for b in items %}x{% endfor %}{% endfor %}";
+        assert!(matches!(
+            kind(render(nested, &Sandbox::new().max_loop_iterations(500))),
+            Err(ErrorKind::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            kind(render(nested, &Sandbox::new().max_output(1000))),
+            Err(ErrorKind::LimitExceeded(_))
+        ));
+        assert!(render("{% for a in items %}x{% endfor %}", &Sandbox::new()).is_ok());
+    }
+    #[test]
+    fn depth_and_fuel_limits_test() {
+        assert_eq!(
+            Err(ErrorKind::LimitExceeded(
+                "includes and macro calls nested more than 10 deep".to_string()
+            )),
+            kind(render(
+                "{% macro f(n) %}{{ f(n) }}{% endmacro %}{{ f(1) }}",
+                &Sandbox::new()
+            ))
+        );
+        let nested = "{% if true %}".repeat(5) + "{{ not not x }}" + &"{% endif %}".repeat(5);
+        assert!(render(&nested, &Sandbox::new()).is_ok());
+        assert_eq!(
+            Err(ErrorKind::LimitExceeded(
+                "`t.html` nests tags and expressions more than 6 deep".to_string()
+            )),
+            kind(render(&nested, &Sandbox::new().max_nesting(6)))
+        );
+        assert!(matches!(
+            kind(render(
+                "{% for a in items %}{{ a }}{% endfor %}",
+                &Sandbox::new().fuel(50)
+            )),
+            Err(ErrorKind::LimitExceeded(_))
+        ));
+    }
+    #[test]
+    fn allow_lists_test() {
+        let sandbox = Sandbox::new()
+            .allow_filters(&["upper"])
+            .allow_attributes(&["name"]);
+        assert_eq!(
+            Ok("BOB1".to_string()),
+            render(
+                "{{ user.name | upper }}{% for i in items %}{% if loop.first %}{{ loop.index }}{% endif %}{% endfor %}",
+                &sandbox
+            )
+        );
+        assert_eq!(
+            Err(ErrorKind::NotAllowed(
+                "attribute `password` is not allowed".to_string()
+            )),
+            kind(render("{{ user.password }}", &sandbox))
+        );
+        // Only the `loop` of a `for` is exempt, not a variable named like it
+        for source in [
+            "{% set loop = user %}{{ loop.password }}",
+            "{% for i in items %}{% set loop = user %}{{ loop.password }}{% endfor %}",
+            "{% macro m(loop) %}{{ loop.password }}{% endmacro %}{{ m(user) }}",
+        ] {
+            assert!(matches!(
+                kind(render(source, &sandbox)),
+                Err(ErrorKind::NotAllowed(_))
+            ));
+        }
+        assert_eq!(
+            Err(ErrorKind::NotAllowed(
+                "filter `lower` is not allowed".to_string()
+            )),
+            kind(render("{{ user.name | lower }}", &sandbox))
+        );
+    }
+}
@@ -0,0 +1,174 @@
+// A parsed template: a tree of nodes plus the blocks and macros it defines, the templates it
+// imports macros from and the template it extends
+use std::collections::HashMap;
+
+use crate::error::TemplateError;
+use crate::expr::ast::Expr;
+use crate::parser;
+
+// Where an expression starts in the template source, 1-based
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+// One `if` or `elif` condition with the nodes rendered when it holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub condition: Expr,
+    pub span: Span,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),     // 字面量
+    Expr(Expr, Span), // {{ expression }}
+    // {% if %} ... {% elif %} ... {% else %} ... {% endif %}
+    If {
+        branches: Vec<Branch>,
+        otherwise: Vec<Node>,
+    },
+    // {% for item in items %} ... {% else %} ... {% endfor %}
+    For {
+        var: String,
+        iterable: Expr,
+        span: Span,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    // {% block name %}; the body is stored in Template::blocks so that child templates can override it
+    Block(String),
+    // {% include "name" [with key = expr, ...] [only] %}
+    Include {
+        name: Expr,
+        with: Vec<(String, Expr)>,
+        only: bool,
+        span: Span,
+    },
+    // {% set name = expr %}, visible until the end of the enclosing for, macro or template
+    Set {
+        name: String,
+        value: Expr,
+        span: Span,
+    },
+    // {% trans %}Hello {{ name }}{% plural count %}...{% endtrans %}. The messages are catalog
+    // ids with variables written as `%(name)s`, as in gettext; `variables` holds their values.
+    Trans {
+        singular: String,
+        // The plural message and the count that chooses between the two
+        plural: Option<(String, Expr)>,
+        variables: Vec<(String, Expr)>,
+        span: Span,
+    },
+}
+
+// {% macro name(param, param = default) %} ... {% endmacro %}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<(String, Option<Expr>)>,
+    pub body: Vec<Node>,
+}
+
+// Macros made available to a template from another one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Import {
+    // {% import "macros.html" as ui %}, called as `ui.button(...)`
+    Module {
+        template: String,
+        alias: String,
+    },
+    // {% from "macros.html" import button, link as l %}, as (macro name, local name) pairs
+    Names {
+        template: String,
+        names: Vec<(String, String)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub name: String,
+    // The template text, kept to quote it in error messages
+    pub source: String,
+    // Set by {% extends "base.html" %}
+    pub parent: Option<String>,
+    pub nodes: Vec<Node>,
+    pub blocks: HashMap<String, Vec<Node>>,
+    pub macros: HashMap<String, Macro>,
+    pub imports: Vec<Import>,
+    // How deeply its tags and expressions nest, checked against Sandbox::max_nesting
+    pub nesting: usize,
+}
+
+impl Template {
+    pub fn parse(name: &str, source: &str) -> Result<Template, TemplateError> {
+        parser::parse_template(name, source)
+    }
+
+    // The deepest nesting of sections plus the expression inside them, counting the
+    // template itself as one level
+    pub(crate) fn measure_nesting(&self) -> usize {
+        let macros = self.macros.values().map(|m| {
+            let defaults = m.params.iter().filter_map(|(_, d)| d.as_ref());
+            let defaults = defaults.map(Expr::depth).max().unwrap_or(0);
+            self.nodes_nesting(&m.body).max(defaults)
+        });
+        // The blocks a child template overrides do not appear in its nodes
+        let blocks = self.blocks.values().map(|body| self.nodes_nesting(body));
+        let deepest = self.nodes_nesting(&self.nodes);
+        1 + deepest
+            .max(macros.max().unwrap_or(0))
+            .max(blocks.max().unwrap_or(0))
+    }
+
+    fn nodes_nesting(&self, nodes: &[Node]) -> usize {
+        nodes
+            .iter()
+            .map(|n| self.node_nesting(n))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn node_nesting(&self, node: &Node) -> usize {
+        let exprs =
+            |exprs: &mut dyn Iterator<Item = &Expr>| exprs.map(Expr::depth).max().unwrap_or(0);
+        match node {
+            Node::Text(_) => 0,
+            // A block is rendered where it appears, so its body counts from there
+            Node::Block(name) => 1 + self.blocks.get(name).map_or(0, |b| self.nodes_nesting(b)),
+            Node::Expr(expr, _) | Node::Set { value: expr, .. } => expr.depth(),
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let branches = branches
+                    .iter()
+                    .map(|b| b.condition.depth().max(self.nodes_nesting(&b.body)));
+                1 + branches
+                    .max()
+                    .unwrap_or(0)
+                    .max(self.nodes_nesting(otherwise))
+            }
+            Node::For {
+                iterable,
+                body,
+                otherwise,
+                ..
+            } => {
+                let body = self.nodes_nesting(body).max(self.nodes_nesting(otherwise));
+                1 + iterable.depth().max(body)
+            }
+            Node::Include { name, with, .. } => {
+                name.depth().max(exprs(&mut with.iter().map(|(_, e)| e)))
+            }
+            Node::Trans {
+                plural, variables, ..
+            } => {
+                let plural = plural.iter().map(|(_, e)| e);
+                exprs(&mut plural.chain(variables.iter().map(|(_, e)| e)))
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Values that can be stored in a template context and produced by expressions and filters
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,                        // 空值
+    Undefined(String),           // 未定义的变量, 保存变量名
+    Bool(bool),                  // 布尔值
+    Number(f64),                 // 数字
+    Str(String),                 // 字符串
+    List(Vec<Value>),            // 列表
+    Map(HashMap<String, Value>), // 映射
+}
+
+impl Value {
+    // Truthiness used by `default` and, later, conditional tags
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null | Value::Undefined(_) => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(l) => !l.is_empty(),
+            Value::Map(m) => !m.is_empty(),
+        }
+    }
+
+    // Returns the numeric value, parsing strings so that context values read as text still work
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Str(s) => s.trim().parse::<f64>().ok(),
+            Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    // Looks up a key in a map value
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(m) => m.get(key),
+            _ => None,
+        }
+    }
+
+    // Equality used by `==`; numbers read from text compare equal to numbers, eg. "3" == 3
+    pub fn loose_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(_), Value::Str(_)) | (Value::Str(_), Value::Number(_)) => {
+                match (self.as_number(), other.as_number()) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => false,
+                }
+            }
+            _ => self == other,
+        }
+    }
+
+    // Ordering used by `<`, `>`, `<=` and `>=`; numeric when both sides are numbers
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self.as_number(), other.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => match (self, other) {
+                (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                _ => None,
+            },
+        }
+    }
+
+    // Membership used by `in`
+    pub fn contains(&self, item: &Value) -> bool {
+        match self {
+            Value::List(items) => items.iter().any(|i| i.loose_eq(item)),
+            Value::Map(m) => m.contains_key(&item.to_string()),
+            Value::Str(s) => s.contains(&item.to_string()),
+            _ => false,
+        }
+    }
+
+    // What `for` iterates over: list items, map keys in sorted order, or nothing for null and
+    // undefined values. None for values that cannot be iterated.
+    pub fn into_items(self) -> Option<Vec<Value>> {
+        match self {
+            Value::List(items) => Some(items),
+            Value::Map(map) => {
+                let mut keys: Vec<String> = map.into_keys().collect();
+                keys.sort();
+                Some(keys.into_iter().map(Value::Str).collect())
+            }
+            Value::Null | Value::Undefined(_) => Some(Vec::new()),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Undefined(_) => "undefined",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null | Value::Undefined(_) => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            // Whole numbers are printed without a trailing ".0"
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Value::Map(_) => write!(f, "[map]"),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<HashMap<String, Value>> for Value {
+    fn from(map: HashMap<String, Value>) -> Self {
+        Value::Map(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn display_whole_number_test() {
+        assert_eq!("3", Value::Number(3.0).to_string());
+        assert_eq!("3.25", Value::Number(3.25).to_string());
+    }
+    #[test]
+    fn display_list_test() {
+        let v = Value::from(vec!["a", "b"]);
+        assert_eq!("a, b", v.to_string());
+    }
+}
@@ -0,0 +1,190 @@
+// Compiles the code the AOT compiler generates for the templates in tests/aot/templates and
+// checks that it renders them as the renderer does. The generated code is checked in as
+// tests/aot/generated.rs so that cargo builds it with the tests; the first test fails when the
+// compiler's output has changed, and UPDATE_AOT=1 regenerates it.
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use template_engine::aot::runtime::{ToValue, Value};
+use template_engine::aot::Compiler;
+use template_engine::filters::FilterRegistry;
+use template_engine::loader::FileSystemLoader;
+use template_engine::render::Renderer;
+
+mod generated {
+    include!("aot/generated.rs");
+}
+
+pub struct User {
+    pub name: Option<String>,
+    pub admin: bool,
+}
+
+pub struct Item {
+    pub name: String,
+    pub price: f64,
+    pub tags: Vec<String>,
+}
+
+pub struct Page {
+    pub title: String,
+    pub user: User,
+    pub items: Vec<Item>,
+    pub stock: HashMap<String, u32>,
+}
+
+// The same context as template values, for the renderer
+fn map(fields: Vec<(&str, Value)>) -> Value {
+    Value::Map(
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+impl ToValue for User {
+    fn to_value(&self) -> Value {
+        map(vec![
+            ("name", self.name.to_value()),
+            ("admin", self.admin.to_value()),
+        ])
+    }
+}
+
+impl ToValue for Item {
+    fn to_value(&self) -> Value {
+        map(vec![
+            ("name", self.name.to_value()),
+            ("price", self.price.to_value()),
+            ("tags", self.tags.to_value()),
+        ])
+    }
+}
+
+impl ToValue for Page {
+    fn to_value(&self) -> Value {
+        map(vec![
+            ("title", self.title.to_value()),
+            ("user", self.user.to_value()),
+            ("items", self.items.to_value()),
+            ("stock", self.stock.to_value()),
+        ])
+    }
+}
+
+fn templates() -> String {
+    format!("{}/tests/aot/templates", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn compiler() -> Compiler {
+    Compiler::new(FileSystemLoader::new(templates())).template(
+        "page.html",
+        "render_page",
+        "crate::Page",
+    )
+}
+
+// Run with UPDATE_AOT=1 to regenerate tests/aot/generated.rs after changing the code generator
+#[test]
+fn generated_code_is_up_to_date_test() {
+    let code = compiler().generate().unwrap();
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/aot/generated.rs");
+    if env::var_os("UPDATE_AOT").is_some() {
+        fs::write(&path, code).unwrap();
+        return;
+    }
+    let current = fs::read_to_string(&path).unwrap_or_default();
+    if current != code {
+        let found: Vec<&str> = current.lines().collect();
+        let expected: Vec<&str> = code.lines().collect();
+        // The first line that differs, or the last one when only the line ends do
+        let line = (0..found.len().max(expected.len()))
+            .find(|&i| found.get(i) != expected.get(i))
+            .unwrap_or(expected.len().saturating_sub(1));
+        panic!(
+            "{:?} is out of date, run the tests with UPDATE_AOT=1 to regenerate it\n\
+             line {}:\n- {}\n+ {}",
+            path,
+            line + 1,
+            found.get(line).unwrap_or(&"<end of file>"),
+            expected.get(line).unwrap_or(&"<end of file>")
+        );
+    }
+}
+
+// Renders the page both ways and checks that they agree
+fn render(page: &Page) -> String {
+    let compiled = generated::render_page(page).unwrap();
+    let context: HashMap<String, Value> = match page.to_value() {
+        Value::Map(fields) => fields,
+        _ => unreachable!(),
+    };
+    let loader = FileSystemLoader::new(templates());
+    let filters = FilterRegistry::new();
+    let rendered = Renderer::new(&loader, &filters)
+        .render("page.html", &context)
+        .unwrap();
+    assert_eq!(rendered, compiled);
+
+    let mut streamed = Vec::new();
+    generated::render_page_to(page, &mut streamed).unwrap();
+    assert_eq!(compiled.as_bytes(), streamed.as_slice());
+    compiled
+}
+
+#[test]
+fn compiled_template_renders_like_the_renderer_test() {
+    let item = |name: &str, price: f64, tags: &[&str]| Item {
+        name: name.to_string(),
+        price,
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+    };
+    let mut page = Page {
+        title: "Shop".to_string(),
+        user: User {
+            name: Some("Ann".to_string()),
+            admin: true,
+        },
+        items: vec![
+            item("Teapot", 12.5, &["kitchen", "gift"]),
+            item("Umbrella stand", 30.0, &[]),
+            item("Mug", 4.256, &[]),
+        ],
+        stock: ["teapot", "mug", "umbrella stand", "kettle", "cup", "saucer"]
+            .iter()
+            .map(|name| (name.to_string(), 3))
+            .collect(),
+    };
+    let html = render(&page);
+    assert!(html.contains("<title>SHOP - Site</title>"), "{}", html);
+    assert!(html.contains("<p>Hello, Ann (admin)</p>"), "{}", html);
+    assert!(
+        html.contains(r#"<li class="first">1. Teapot: 12.50 EUR [kitchen, gift]</li>"#),
+        "{}",
+        html
+    );
+    assert!(
+        html.contains(r#"<li class="last">3. Mug: 4.26 EUR</li>"#),
+        "{}",
+        html
+    );
+    assert!(html.contains("<footer>shop | admin</footer>"), "{}", html);
+    // Maps are iterated by sorted key, whatever their own order
+    assert!(
+        html.contains("<p>In stock: cup, kettle, mug, saucer, teapot, umbrella stand</p>"),
+        "{}",
+        html
+    );
+
+    page.user = User {
+        name: None,
+        admin: false,
+    };
+    page.items.clear();
+    let html = render(&page);
+    assert!(html.contains("<p>Hello, stranger</p>"), "{}", html);
+    assert!(html.contains("<li>Nothing for sale</li>"), "{}", html);
+}
@@ -0,0 +1,114 @@
+// Generated by template_engine::aot::Compiler. Do not edit.
+use ::template_engine::aot::runtime as rt;
+
+const TEMPLATE_0: &str = "page.html";
+const SOURCE_0: &str = "{% extends \"base.html\" %}\n{% from \"macros.html\" import price %}\n{% block title %}{{ title | upper }} - {{ super() }}{% endblock %}\n{% block body %}\n{%- set greeting = \"Hello\" %}\n<p>{{ greeting }}, {{ user.name | default(\"stranger\") }}{% if user.admin and items %} (admin){% endif %}</p>\n<ul>\n{%- for item in items %}\n  <li class=\"{% if loop.first %}first{% elif loop.last %}last{% else %}middle{% endif %}\">{{ loop.index }}. {{ item.name | truncate(8) }}: {{ price(item.price) }}{% if item.tags | length > 0 %} [{{ item.tags | join(\", \") }}]{% endif %}</li>\n{%- else %}\n  <li>Nothing for sale</li>\n{%- endfor %}\n</ul>\n<p>In stock: {% for name in stock %}{{ name }}{% if not loop.last %}, {% endif %}{% endfor %}</p>\n{% endblock %}\n";
+const TEMPLATE_1: &str = "macros.html";
+const SOURCE_1: &str = "{% macro price(amount, currency=\"EUR\") %}{{ amount | round(2) }} {{ currency }}{% endmacro %}\n";
+const TEMPLATE_2: &str = "footer.html";
+const SOURCE_2: &str = "<footer>{{ title | lower }}{% if user.admin %} | admin{% endif %}</footer>\n";
+
+// Rendered from "page.html"
+pub fn render_page(ctx: &crate::Page) -> Result<String, rt::TemplateError> {
+    let mut buf = Vec::new();
+    render_page_to(ctx, &mut buf)?;
+    Ok(rt::into_string(buf))
+}
+
+#[allow(unused_variables, unused_mut, clippy::all)]
+pub fn render_page_to<W: std::io::Write>(ctx: &crate::Page, out: &mut W) -> Result<(), rt::TemplateError> {
+    let mut writer = std::io::BufWriter::new(out);
+    let out = &mut writer;
+    rt::write_str(out, "<title>")?;
+    rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 3, 21, || Ok(rt::filter("upper", &rt::to_value(&ctx.title), &[])?))?)?;
+    rt::write_str(out, " - ")?;
+    rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 3, 43, || Ok({
+        let mut buf = Vec::<u8>::new();
+        {
+            let out = &mut buf;
+            rt::write_str(out, "Site")?;
+        }
+        rt::captured(buf)
+    }))?)?;
+    rt::write_str(out, "</title>\n")?;
+    let set_1 = rt::at(TEMPLATE_0, SOURCE_0, 5, 20, || Ok(rt::Value::Str("Hello".to_string())))?;
+    rt::write_str(out, "\n<p>")?;
+    rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 6, 7, || Ok(set_1.clone()))?)?;
+    rt::write_str(out, ", ")?;
+    rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 6, 23, || Ok(rt::filter("default", &rt::to_value(&ctx.user.name), &[rt::Value::Str("stranger".to_string())])?))?)?;
+    if rt::at(TEMPLATE_0, SOURCE_0, 6, 63, || Ok(rt::Value::Bool(rt::to_value(&ctx.user.admin).is_truthy() && rt::to_value(&ctx.items).is_truthy())))?.is_truthy() {
+        rt::write_str(out, " (admin)")?;
+    }
+    rt::write_str(out, "</p>\n<ul>")?;
+    {
+        let items_2 = rt::items_of(&ctx.items);
+        if items_2.is_empty() {
+            rt::write_str(out, "\n  <li>Nothing for sale</li>")?;
+        } else {
+            let length_2 = items_2.len();
+            for (i_2, v_item) in items_2.into_iter().enumerate() {
+                let v_loop = rt::loop_info(i_2, length_2);
+                rt::write_str(out, "\n  <li class=\"")?;
+                if rt::at(TEMPLATE_0, SOURCE_0, 9, 20, || Ok(rt::attr(&v_loop, &["first"])))?.is_truthy() {
+                    rt::write_str(out, "first")?;
+                } else if rt::at(TEMPLATE_0, SOURCE_0, 9, 46, || Ok(rt::attr(&v_loop, &["last"])))?.is_truthy() {
+                    rt::write_str(out, "last")?;
+                } else {
+                    rt::write_str(out, "middle")?;
+                }
+                rt::write_str(out, "\">")?;
+                rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 9, 94, || Ok(rt::attr(&v_loop, &["index"])))?)?;
+                rt::write_str(out, ". ")?;
+                rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 9, 112, || Ok(rt::filter("truncate", &rt::to_value(&v_item.name), &[rt::Value::Number(8.0)])?))?)?;
+                rt::write_str(out, ": ")?;
+                rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 9, 143, || Ok({
+                    let mut buf = Vec::<u8>::new();
+                    {
+                        let out = &mut buf;
+                        let arg_3_0 = rt::to_value(&v_item.price);
+                        let param_3_0 = arg_3_0;
+                        let param_3_1 = rt::Value::Str("EUR".to_string());
+                        rt::write_value(out, &rt::at(TEMPLATE_1, SOURCE_1, 1, 45, || Ok(rt::filter("round", &param_3_0.clone(), &[rt::Value::Number(2.0)])?))?)?;
+                        rt::write_str(out, " ")?;
+                        rt::write_value(out, &rt::at(TEMPLATE_1, SOURCE_1, 1, 69, || Ok(param_3_1.clone()))?)?;
+                    }
+                    rt::captured(buf)
+                }))?)?;
+                if rt::at(TEMPLATE_0, SOURCE_0, 9, 169, || Ok(rt::Value::Bool(rt::BinOp::Gt.apply(&rt::filter("length", &rt::to_value(&v_item.tags), &[])?, &rt::Value::Number(0.0)))))?.is_truthy() {
+                    rt::write_str(out, " [")?;
+                    rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 9, 199, || Ok(rt::filter("join", &rt::to_value(&v_item.tags), &[rt::Value::Str(", ".to_string())])?))?)?;
+                    rt::write_str(out, "]")?;
+                }
+                rt::write_str(out, "</li>")?;
+            }
+        }
+    }
+    rt::write_str(out, "\n</ul>\n<p>In stock: ")?;
+    {
+        let items_4 = rt::items_of(&ctx.stock);
+        if items_4.is_empty() {
+        } else {
+            let length_4 = items_4.len();
+            for (i_4, v_name) in items_4.into_iter().enumerate() {
+                let v_loop = rt::loop_info(i_4, length_4);
+                rt::write_value(out, &rt::at(TEMPLATE_0, SOURCE_0, 14, 40, || Ok(rt::to_value(&v_name)))?)?;
+                if rt::at(TEMPLATE_0, SOURCE_0, 14, 53, || Ok(rt::Value::Bool(!rt::attr(&v_loop, &["last"]).is_truthy())))?.is_truthy() {
+                    rt::write_str(out, ", ")?;
+                }
+            }
+        }
+    }
+    rt::write_str(out, "</p>\n")?;
+    rt::write_str(out, "\n")?;
+    {
+        rt::write_str(out, "<footer>")?;
+        rt::write_value(out, &rt::at(TEMPLATE_2, SOURCE_2, 1, 12, || Ok(rt::filter("lower", &rt::to_value(&ctx.title), &[])?))?)?;
+        if rt::at(TEMPLATE_2, SOURCE_2, 1, 34, || Ok(rt::to_value(&ctx.user.admin)))?.is_truthy() {
+            rt::write_str(out, " | admin")?;
+        }
+        rt::write_str(out, "</footer>\n")?;
+    }
+    rt::write_str(out, "\n")?;
+    rt::finish(out)
+}
+
@@ -0,0 +1,161 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use super::error::ImagixError;
+use super::resize::Elapsed;
+
+// Number of worker threads, a positive number
+pub fn parse_jobs(s: &str) -> Result<usize, ImagixError> {
+    match s.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => Err(ImagixError::UserInputError(format!(
+            "Wrong value for jobs `{}`, expected a positive number of workers",
+            s
+        ))),
+    }
+}
+
+// One worker per CPU unless --jobs says otherwise
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// An input a dry run would process, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Planned {
+    pub input: PathBuf,
+    // Unknown when the input could not be processed
+    pub output: Option<PathBuf>,
+    // eg. "new" or "source changed", or the error processing it would fail with
+    pub reason: String,
+}
+
+// Outcome of a batch: a file that fails does not stop the others
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub succeeded: usize,
+    pub skipped: usize,
+    // Inputs whose outputs were left as they are, see --force
+    pub up_to_date: usize,
+    pub failed: Vec<(PathBuf, ImagixError)>,
+    // Total size of the input files that were processed
+    pub bytes: u64,
+    pub elapsed: Duration,
+    // Nothing was written; `planned` and `removed` tell what would have been
+    pub dry_run: bool,
+    pub planned: Vec<Planned>,
+    // Outputs whose source is gone, deleted along with their manifest entry
+    pub removed: Vec<PathBuf>,
+}
+
+impl BatchReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, out: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        write!(
+            out,
+            "{} succeeded, {} failed, {} skipped{} in {} ({:.1} files/s, {:.1} MB/s)",
+            self.succeeded,
+            self.failed.len(),
+            self.skipped,
+            match self.up_to_date {
+                0 => String::new(),
+                n => format!(", {} up to date", n),
+            },
+            Elapsed(self.elapsed),
+            self.succeeded as f64 / seconds,
+            self.bytes as f64 / 1_000_000.0 / seconds,
+        )
+    }
+}
+
+// Runs `task` on every file with `jobs` workers, showing a progress bar on the terminal.
+// The message a task returns is printed above the bar; errors are collected in the report.
+pub fn run_batch<F>(
+    files: &[PathBuf],
+    skipped: usize,
+    jobs: usize,
+    task: F,
+) -> Result<BatchReport, ImagixError>
+where
+    F: Fn(&Path) -> Result<String, ImagixError> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| {
+            ImagixError::ImageResizingError("Unable to start workers".into()).caused_by(e)
+        })?;
+    let bar = ProgressBar::new(files.len() as u64);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40} {pos}/{len} ({per_sec}, ETA {eta})",
+        )
+        .unwrap(),
+    );
+    let timer = Instant::now();
+    let report = Mutex::new(BatchReport {
+        skipped,
+        ..BatchReport::default()
+    });
+    pool.install(|| {
+        files.par_iter().for_each(|file| {
+            let result = task(file);
+            let bytes = file.metadata().map_or(0, |m| m.len());
+            let mut report = report.lock().unwrap();
+            match result {
+                Ok(message) => {
+                    report.succeeded += 1;
+                    report.bytes += bytes;
+                    bar.suspend(|| println!("{}", message));
+                }
+                Err(e) => report.failed.push((file.clone(), e)),
+            }
+            bar.inc(1);
+        })
+    });
+    bar.finish_and_clear();
+    let mut report = report.into_inner().unwrap();
+    report.elapsed = timer.elapsed();
+    report.failed.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_continues_past_failures() {
+        let files: Vec<PathBuf> = ["a.jpg", "bad.jpg", "c.jpg", "bad2.jpg"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let report = run_batch(&files, 1, 3, |file| {
+            if file.to_string_lossy().starts_with("bad") {
+                Err(ImagixError::ImageResizingError("broken".into()))
+            } else {
+                Ok(format!("done {:?}", file))
+            }
+        })
+        .unwrap();
+        assert_eq!(2, report.succeeded);
+        assert_eq!(1, report.skipped);
+        assert!(!report.is_success());
+        let failed: Vec<&PathBuf> = report.failed.iter().map(|(f, _)| f).collect();
+        assert_eq!(vec![&files[1], &files[3]], failed);
+        assert!(report
+            .to_string()
+            .starts_with("2 succeeded, 2 failed, 1 skipped in "));
+        assert!(parse_jobs("0").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use super::error::{Context, ImagixError};
+use super::files::resolve;
+
+// Written in the output folder, next to the outputs it describes
+pub const MANIFEST_FILE: &str = ".imagix-manifest.json";
+
+// Version of the manifest format; manifests of another version are ignored
+const MANIFEST_VERSION: u32 = 2;
+
+// Manifests saved so far by this process, to name their temporary files
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+// How a batch deals with outputs that are up to date
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Incremental {
+    // Process every input, up to date or not
+    pub force: bool,
+    // Only list what would be processed and removed
+    pub dry_run: bool,
+}
+
+// What an output was made from. Paths are resolved, see files::resolve, so that runs started
+// from different folders agree on them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub size: u64,
+    // Modification time of the source, in nanoseconds since 1970
+    pub modified: u64,
+    // SHA-256 of the content of the source
+    pub hash: String,
+    // SHA-256 of the operations and encoding options
+    pub parameters: String,
+}
+
+impl Entry {
+    pub fn new(source: &Path, output: &Path, parameters: &str) -> Result<Self, ImagixError> {
+        let (size, modified) = stat(source)?;
+        Ok(Entry {
+            source: resolve(source),
+            output: resolve(output),
+            size,
+            modified,
+            hash: content_hash(source)?,
+            parameters: parameters.to_string(),
+        })
+    }
+}
+
+// Whether an output has to be made again, and why
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Check {
+    UpToDate,
+    Stale(&'static str),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestFile {
+    version: u32,
+    entries: Vec<Entry>,
+}
+
+// The outputs of earlier runs, by output path
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Manifest {
+    // A missing, unreadable or outdated manifest is an empty one: everything is processed again
+    pub fn load(path: &Path) -> Self {
+        let file: Option<ManifestFile> = fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+        match file {
+            Some(file) if file.version == MANIFEST_VERSION => Manifest {
+                entries: file
+                    .entries
+                    .into_iter()
+                    .map(|e| (e.output.clone(), e))
+                    .collect(),
+            },
+            _ => Manifest::default(),
+        }
+    }
+
+    // Written to a temporary file first, so that an interrupted run leaves the old manifest
+    pub fn save(&self, path: &Path) -> Result<(), ImagixError> {
+        let file = ManifestFile {
+            version: MANIFEST_VERSION,
+            entries: self.entries.values().cloned().collect(),
+        };
+        let json = serde_json::to_string_pretty(&file).map_err(|e| {
+            ImagixError::FormatError("Unable to serialize the manifest".into()).caused_by(e)
+        })?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).at("create folder", dir)?;
+        }
+        // Named after the process and a counter, as batches may run side by side
+        let temporary = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temporary, json + "\n").at("write", &temporary)?;
+        fs::rename(&temporary, path).at("replace", path)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn insert(&mut self, entry: Entry) {
+        self.entries.insert(entry.output.clone(), entry);
+    }
+
+    pub fn remove(&mut self, output: &Path) -> Option<Entry> {
+        self.entries.remove(&resolve(output))
+    }
+
+    // Entries whose source has been deleted since
+    pub fn orphans(&self) -> Vec<Entry> {
+        self.entries
+            .values()
+            .filter(|e| !e.source.exists())
+            .cloned()
+            .collect()
+    }
+
+    // The source is only hashed when its size or modification time changed. A file touched
+    // but not changed is up to date, and its entry gets the new time.
+    pub fn check(
+        &mut self,
+        source: &Path,
+        output: &Path,
+        parameters: &str,
+    ) -> Result<Check, ImagixError> {
+        let entry = match self.entries.get_mut(&resolve(output)) {
+            Some(entry) if entry.source == resolve(source) => entry,
+            _ => return Ok(Check::Stale("new")),
+        };
+        if entry.parameters != parameters {
+            return Ok(Check::Stale("changed options"));
+        }
+        if !output.exists() {
+            return Ok(Check::Stale("output missing"));
+        }
+        let (size, modified) = stat(source)?;
+        if (size, modified) == (entry.size, entry.modified) {
+            return Ok(Check::UpToDate);
+        }
+        if size != entry.size || content_hash(source)? != entry.hash {
+            return Ok(Check::Stale("source changed"));
+        }
+        entry.modified = modified;
+        Ok(Check::UpToDate)
+    }
+}
+
+fn stat(path: &Path) -> Result<(u64, u64), ImagixError> {
+    let metadata = path.metadata().at("stat", path)?;
+    let modified = metadata
+        .modified()
+        .at("stat", path)?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    Ok((metadata.len(), modified))
+}
+
+fn content_hash(path: &Path) -> Result<String, ImagixError> {
+    let mut file = fs::File::open(path).at("open", path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).at("read", path)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// A short fingerprint of the options the outputs are made with, eg. the Debug form of the
+// pipeline and encoding options
+pub fn parameters(description: &str) -> String {
+    format!("{:x}", Sha256::digest(description.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_check() {
+        let root = std::env::temp_dir().join("imagix-cache-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let (source, output) = (root.join("a.png"), root.join("tmp/a.png"));
+        fs::write(&source, b"one").unwrap();
+        fs::create_dir_all(root.join("tmp")).unwrap();
+        fs::write(&output, b"out").unwrap();
+
+        let mut manifest = Manifest::default();
+        assert_eq!(
+            Check::Stale("new"),
+            manifest.check(&source, &output, "p").unwrap()
+        );
+        manifest.insert(Entry::new(&source, &output, "p").unwrap());
+        assert_eq!(
+            Check::UpToDate,
+            manifest.check(&source, &output, "p").unwrap()
+        );
+        assert_eq!(
+            Check::Stale("changed options"),
+            manifest.check(&source, &output, "q").unwrap()
+        );
+
+        // Touched without a change, then changed
+        let path = root.join("tmp").join(MANIFEST_FILE);
+        manifest
+            .entries
+            .get_mut(&resolve(&output))
+            .unwrap()
+            .modified -= 1;
+        assert_eq!(
+            Check::UpToDate,
+            manifest.check(&source, &output, "p").unwrap()
+        );
+        manifest.save(&path).unwrap();
+        let mut manifest = Manifest::load(&path);
+        fs::write(&source, b"two").unwrap();
+        manifest
+            .entries
+            .get_mut(&resolve(&output))
+            .unwrap()
+            .modified -= 1;
+        assert_eq!(
+            Check::Stale("source changed"),
+            manifest.check(&source, &output, "p").unwrap()
+        );
+
+        assert!(manifest.orphans().is_empty());
+        fs::remove_file(&source).unwrap();
+        assert_eq!(1, manifest.orphans().len());
+        fs::write(&path, b"{ not json").unwrap();
+        assert!(Manifest::load(&path).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
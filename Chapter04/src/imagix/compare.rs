@@ -0,0 +1,336 @@
+use image::{DynamicImage, GenericImageView, GrayImage, Rgb, RgbImage, RgbaImage};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::error::ImagixError;
+use super::pipeline::open_upright;
+
+// Side of the windows SSIM is averaged over, and the step between them
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STEP: u32 = 4;
+
+// How many pixels may differ, eg. 100 or 0.5%
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelLimit {
+    Count(u64),
+    Percent(f64), // 占全部像素的百分比
+}
+
+impl FromStr for PixelLimit {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wrong = || {
+            ImagixError::UserInputError(format!(
+                "Wrong value for the pixel limit `{}`, expected a number of pixels or a percentage such as 0.5%",
+                s
+            ))
+        };
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(PixelLimit::Percent(percent)),
+                _ => Err(wrong()),
+            },
+            None => s.parse().map(PixelLimit::Count).map_err(|_| wrong()),
+        }
+    }
+}
+
+// When two images are too far apart; every limit given has to hold, and with none given no
+// pixel may differ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub max_different: Option<PixelLimit>,
+    pub min_psnr: Option<f64>,
+    pub min_ssim: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    pub width: u32,
+    pub height: u32,
+    // Pixels where a channel differs by more than the tolerance
+    pub different_pixels: u64,
+    // Largest difference of a channel, from 0 to 255
+    pub max_difference: u8,
+    // In dB, infinite for identical images
+    pub psnr: f64,
+    // From -1 to 1, 1 for identical images
+    pub ssim: f64,
+}
+
+impl Comparison {
+    pub fn different_percent(&self) -> f64 {
+        let total = self.width as u64 * self.height as u64;
+        if total == 0 {
+            0.0
+        } else {
+            self.different_pixels as f64 * 100.0 / total as f64
+        }
+    }
+
+    // One message per limit the comparison does not hold, none if it passes
+    pub fn failures(&self, thresholds: &Thresholds) -> Vec<String> {
+        let mut failures = Vec::new();
+        let max_different = match thresholds {
+            Thresholds {
+                max_different: None,
+                min_psnr: None,
+                min_ssim: None,
+            } => Some(PixelLimit::Count(0)),
+            _ => thresholds.max_different,
+        };
+        match max_different {
+            Some(PixelLimit::Count(max)) if self.different_pixels > max => failures.push(format!(
+                "{} pixels differ, more than {} allowed",
+                self.different_pixels, max
+            )),
+            Some(PixelLimit::Percent(max)) if self.different_percent() > max => {
+                failures.push(format!(
+                    "{:.3}% of the pixels differ, more than {}% allowed",
+                    self.different_percent(),
+                    max
+                ))
+            }
+            _ => {}
+        }
+        if let Some(min) = thresholds.min_psnr {
+            if self.psnr < min {
+                failures.push(format!("PSNR of {:.2} dB, less than {} dB", self.psnr, min));
+            }
+        }
+        if let Some(min) = thresholds.min_ssim {
+            if self.ssim < min {
+                failures.push(format!("SSIM of {:.4}, less than {}", self.ssim, min));
+            }
+        }
+        failures
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, out: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(
+            out,
+            "Different pixels: {} of {} ({:.3}%), largest difference {}",
+            self.different_pixels,
+            self.width as u64 * self.height as u64,
+            self.different_percent(),
+            self.max_difference
+        )?;
+        if self.psnr.is_infinite() {
+            writeln!(out, "PSNR: infinite, the images are identical")?;
+        } else {
+            writeln!(out, "PSNR: {:.2} dB", self.psnr)?;
+        }
+        write!(out, "SSIM: {:.4}", self.ssim)
+    }
+}
+
+// Both images are turned upright before they are compared, as the other subcommands do
+pub fn open_pair(a: &Path, b: &Path) -> Result<(DynamicImage, DynamicImage), ImagixError> {
+    Ok((open_upright(a)?.image, open_upright(b)?.image))
+}
+
+pub fn compare(
+    a: &DynamicImage,
+    b: &DynamicImage,
+    tolerance: u8,
+) -> Result<Comparison, ImagixError> {
+    same_dimensions(a, b)?;
+    let (width, height) = a.dimensions();
+    let (pixels_a, pixels_b) = (a.to_rgba8(), b.to_rgba8());
+    let mut different_pixels = 0;
+    let mut max_difference = 0;
+    let mut squared_error = 0.0;
+    for (pa, pb) in pixels_a.pixels().zip(pixels_b.pixels()) {
+        let difference = (0..4).map(|c| pa[c].abs_diff(pb[c])).max().unwrap_or(0);
+        if difference > tolerance {
+            different_pixels += 1;
+        }
+        max_difference = max_difference.max(difference);
+        // PSNR is computed on the color channels only
+        squared_error += (0..3)
+            .map(|c| (pa[c] as f64 - pb[c] as f64).powi(2))
+            .sum::<f64>();
+    }
+    let samples = (width as u64 * height as u64 * 3).max(1) as f64;
+    let mse = squared_error / samples;
+    let psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    };
+    Ok(Comparison {
+        width,
+        height,
+        different_pixels,
+        max_difference,
+        psnr,
+        ssim: ssim(&a.to_luma8(), &b.to_luma8()),
+    })
+}
+
+// The first image faded to light gray, with the pixels that differ in red, the brighter the
+// larger the difference
+pub fn diff_image(
+    a: &DynamicImage,
+    b: &DynamicImage,
+    tolerance: u8,
+) -> Result<RgbImage, ImagixError> {
+    same_dimensions(a, b)?;
+    let (pixels_a, pixels_b): (RgbaImage, RgbaImage) = (a.to_rgba8(), b.to_rgba8());
+    let gray = a.to_luma8();
+    Ok(RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (pixels_a.get_pixel(x, y), pixels_b.get_pixel(x, y));
+        let difference = (0..4).map(|c| pa[c].abs_diff(pb[c])).max().unwrap_or(0);
+        if difference > tolerance {
+            Rgb([128 + difference / 2, 0, 0])
+        } else {
+            let faded = 191 + gray.get_pixel(x, y)[0] / 4;
+            Rgb([faded, faded, faded])
+        }
+    }))
+}
+
+fn same_dimensions(a: &DynamicImage, b: &DynamicImage) -> Result<(), ImagixError> {
+    if a.dimensions() != b.dimensions() {
+        return Err(ImagixError::UserInputError(format!(
+            "The images have different dimensions, {}x{} and {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        )));
+    }
+    Ok(())
+}
+
+// Mean structural similarity of the brightness over windows of 8x8 pixels
+fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = a.dimensions();
+    // Images smaller than a window are compared as a whole
+    let window_width = SSIM_WINDOW.min(width);
+    let window_height = SSIM_WINDOW.min(height);
+    if window_width == 0 || window_height == 0 {
+        return 1.0;
+    }
+    let starts = |size: u32, window: u32| {
+        let mut starts: Vec<u32> = (0..=size - window).step_by(SSIM_STEP as usize).collect();
+        // The last row and column of windows reach the edges
+        if starts.last() != Some(&(size - window)) {
+            starts.push(size - window);
+        }
+        starts
+    };
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in starts(height, window_height) {
+        for x0 in starts(width, window_width) {
+            let n = (window_width * window_height) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y0 + window_height {
+                for x in x0..x0 + window_width {
+                    let (va, vb) = (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64);
+                    sum_a += va;
+                    sum_b += vb;
+                    sum_aa += va * va;
+                    sum_bb += vb * vb;
+                    sum_ab += va * vb;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let variance_a = sum_aa / n - mean_a * mean_a;
+            let variance_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn checkerboard() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 30, |x, y| {
+            if (x / 5 + y / 5) % 2 == 0 {
+                Rgba([200, 200, 200, 255])
+            } else {
+                Rgba([40, 40, 40, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_compare() {
+        let a = checkerboard();
+        let same = compare(&a, &a, 0).unwrap();
+        assert_eq!(0, same.different_pixels);
+        assert!(same.psnr.is_infinite());
+        assert!((same.ssim - 1.0).abs() < 1e-9);
+
+        // A 10x10 square changed slightly, and 2 pixels changed a lot
+        let mut changed = a.to_rgba8();
+        for y in 0..10 {
+            for x in 0..10 {
+                let p = changed.get_pixel_mut(x, y);
+                p[0] = p[0].saturating_add(3);
+            }
+        }
+        changed.put_pixel(39, 29, Rgba([255, 0, 0, 255]));
+        changed.put_pixel(38, 29, Rgba([255, 0, 0, 255]));
+        let changed = DynamicImage::ImageRgba8(changed);
+        let comparison = compare(&a, &changed, 0).unwrap();
+        assert_eq!(102, comparison.different_pixels);
+        assert_eq!(200, comparison.max_difference);
+        assert!(comparison.psnr > 20.0 && comparison.psnr < 40.0);
+        assert!(comparison.ssim < 1.0 && comparison.ssim > 0.9);
+        // The slight changes are within the tolerance
+        assert_eq!(2, compare(&a, &changed, 5).unwrap().different_pixels);
+
+        let thresholds = Thresholds {
+            max_different: Some("10%".parse().unwrap()),
+            min_psnr: Some(20.0),
+            min_ssim: None,
+        };
+        assert!(comparison.failures(&thresholds).is_empty());
+        let thresholds = Thresholds {
+            max_different: Some("100".parse().unwrap()),
+            min_psnr: None,
+            min_ssim: Some(1.0),
+        };
+        assert_eq!(2, comparison.failures(&thresholds).len());
+        // A PSNR limit alone lets pixels differ
+        let thresholds = Thresholds {
+            max_different: None,
+            min_psnr: Some(20.0),
+            min_ssim: None,
+        };
+        assert!(comparison.failures(&thresholds).is_empty());
+        let thresholds = Thresholds {
+            max_different: None,
+            min_psnr: None,
+            min_ssim: None,
+        };
+        assert_eq!(
+            vec!["102 pixels differ, more than 0 allowed"],
+            comparison.failures(&thresholds)
+        );
+
+        let diff = diff_image(&a, &changed, 5).unwrap();
+        assert_eq!(&Rgb([228, 0, 0]), diff.get_pixel(39, 29));
+        assert_eq!(diff.get_pixel(0, 0)[0], diff.get_pixel(0, 0)[2]);
+
+        assert!(compare(&a, &DynamicImage::new_rgb8(30, 40), 0).is_err());
+        assert!("150%".parse::<PixelLimit>().is_err());
+    }
+}
@@ -0,0 +1,566 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use super::batch::{run_batch, BatchReport};
+use super::error::{Context, ImagixError};
+use super::pipeline::open_upright;
+use super::resize::get_image_files;
+use super::stats::megabytes;
+
+// Perceptual hash used to find near-duplicates, 64 bits each
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashKind {
+    Average,    // aHash: 8x8 灰度, 与平均值比较
+    Difference, // dHash: 相邻像素比较
+    Perceptual, // pHash: 32x32 DCT 的低频部分
+}
+
+impl FromStr for HashKind {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ahash" => Ok(HashKind::Average),
+            "dhash" => Ok(HashKind::Difference),
+            "phash" => Ok(HashKind::Perceptual),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for hash `{}`, expected ahash, dhash or phash",
+                s
+            ))),
+        }
+    }
+}
+
+impl HashKind {
+    pub fn hash(&self, img: &DynamicImage) -> u64 {
+        match self {
+            HashKind::Average => {
+                let pixels = img.resize_exact(8, 8, FilterType::Triangle).to_luma8();
+                let mean = pixels.pixels().map(|p| p[0] as u32).sum::<u32>() / 64;
+                bits(pixels.pixels().map(|p| p[0] as u32 > mean))
+            }
+            HashKind::Difference => {
+                let pixels = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+                bits((0..8).flat_map(|y| {
+                    let pixels = &pixels;
+                    (0..8).map(move |x| pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0])
+                }))
+            }
+            HashKind::Perceptual => {
+                let pixels = img.resize_exact(32, 32, FilterType::Triangle).to_luma8();
+                let coefficients = low_frequencies(&pixels);
+                // The first coefficient is the mean brightness, left out of the median
+                let mut sorted = coefficients[1..].to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let median = sorted[sorted.len() / 2];
+                bits(coefficients.iter().map(|c| *c > median))
+            }
+        }
+    }
+}
+
+// The 8x8 lowest frequencies of the two dimensional DCT-II of a 32x32 image
+fn low_frequencies(pixels: &image::GrayImage) -> Vec<f64> {
+    const N: usize = 32;
+    let cosines: Vec<Vec<f64>> = (0..8)
+        .map(|u| {
+            (0..N)
+                .map(|x| {
+                    ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * N) as f64).cos()
+                })
+                .collect()
+        })
+        .collect();
+    let scale = |u: usize| {
+        if u == 0 {
+            (1.0 / N as f64).sqrt()
+        } else {
+            (2.0 / N as f64).sqrt()
+        }
+    };
+    let mut coefficients = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += pixels.get_pixel(x as u32, y as u32)[0] as f64
+                        * cosines[u][x]
+                        * cosines[v][y];
+                }
+            }
+            coefficients.push(scale(u) * scale(v) * sum);
+        }
+    }
+    coefficients
+}
+
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
+
+// Number of bits two hashes differ in, from 0, the same, to 64
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Largest Hamming distance between near-duplicates, from 0 to 64
+pub fn parse_threshold(s: &str) -> Result<u32, ImagixError> {
+    match s.parse::<u32>() {
+        Ok(threshold) if threshold <= 64 => Ok(threshold),
+        _ => Err(ImagixError::UserInputError(format!(
+            "Wrong value for threshold `{}`, expected a number of bits from 0 to 64",
+            s
+        ))),
+    }
+}
+
+// What is done with the duplicates once found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Report,
+    Move(PathBuf), // 移到该文件夹
+    Hardlink,      // 只替换完全相同的副本
+}
+
+// `move` is parsed without its folder, which Action::with_folder adds
+impl FromStr for Action {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "report" => Ok(Action::Report),
+            "move" => Ok(Action::Move(PathBuf::new())),
+            "hardlink" => Ok(Action::Hardlink),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for action `{}`, expected report, move or hardlink",
+                s
+            ))),
+        }
+    }
+}
+
+impl Action {
+    // Sets the folder duplicates are moved to, which only `move` takes and requires
+    pub fn with_folder(self, move_to: Option<PathBuf>) -> Result<Action, ImagixError> {
+        match (self, move_to) {
+            (Action::Move(_), Some(dir)) => Ok(Action::Move(dir)),
+            (Action::Move(_), None) => Err(ImagixError::UserInputError(
+                "Specify where to move the duplicates with --move-to".to_string(),
+            )),
+            (_, Some(_)) => Err(ImagixError::UserInputError(
+                "--move-to goes with --action move".to_string(),
+            )),
+            (action, None) => Ok(action),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupeOptions {
+    pub hash: HashKind,
+    pub threshold: u32,
+    pub action: Action,
+}
+
+// What is known of an image to compare it with the others
+#[derive(Debug, Clone)]
+struct Fingerprint {
+    path: PathBuf,
+    bytes: u64,
+    width: u32,
+    height: u32,
+    digest: Vec<u8>,
+    hash: u64,
+}
+
+fn fingerprint(path: &Path, kind: HashKind) -> Result<Fingerprint, ImagixError> {
+    let content = fs::read(path).at("read", path)?;
+    let digest = Sha256::digest(&content).to_vec();
+    // Turned upright, so that a copy saved without its EXIF orientation is still found
+    let image = open_upright(path)?.image;
+    let (width, height) = image.dimensions();
+    Ok(Fingerprint {
+        path: path.to_path_buf(),
+        bytes: content.len() as u64,
+        width,
+        height,
+        digest,
+        hash: kind.hash(&image),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    pub bytes: u64,
+    // Bits its hash differs in from the hash of the kept image
+    pub distance: u32,
+    // Same content, byte for byte
+    pub exact: bool,
+}
+
+// Copies of an image; the one with the most pixels, then the largest file, is kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub keep: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+    pub duplicates: Vec<Duplicate>,
+}
+
+#[derive(Debug)]
+pub struct DedupeReport {
+    pub groups: Vec<Group>,
+    pub action: Action,
+    // Duplicates farther than this from the kept image are only reported
+    pub threshold: u32,
+    // Duplicates moved or replaced by hard links
+    pub handled: usize,
+    // Hashing of the images, and the duplicates that could not be moved or linked
+    pub batch: BatchReport,
+}
+
+impl fmt::Display for DedupeReport {
+    fn fmt(&self, out: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for group in &self.groups {
+            writeln!(
+                out,
+                "Kept {:?} ({}x{}, {})",
+                group.keep,
+                group.width,
+                group.height,
+                megabytes(group.bytes)
+            )?;
+            for duplicate in &group.duplicates {
+                if duplicate.exact {
+                    writeln!(out, "  {:?}: exact copy", duplicate.path)?;
+                } else if duplicate.distance > self.threshold {
+                    writeln!(
+                        out,
+                        "  {:?}: distance {}, similar only through the others, left in place",
+                        duplicate.path, duplicate.distance
+                    )?;
+                } else {
+                    writeln!(
+                        out,
+                        "  {:?}: distance {}",
+                        duplicate.path, duplicate.distance
+                    )?;
+                }
+            }
+        }
+        let duplicates: Vec<&Duplicate> = self.groups.iter().flat_map(|g| &g.duplicates).collect();
+        writeln!(
+            out,
+            "{} groups, {} duplicates ({} exact), {} in duplicates",
+            self.groups.len(),
+            duplicates.len(),
+            duplicates.iter().filter(|d| d.exact).count(),
+            megabytes(duplicates.iter().map(|d| d.bytes).sum())
+        )?;
+        match &self.action {
+            Action::Report => Ok(()),
+            Action::Move(dir) => writeln!(out, "Moved {} duplicates to {:?}", self.handled, dir),
+            Action::Hardlink => writeln!(
+                out,
+                "Replaced {} exact copies with hard links, near-duplicates are left as they are",
+                self.handled
+            ),
+        }
+    }
+}
+
+pub fn process_dedupe_request(
+    options: &DedupeOptions,
+    src_folder: &Path,
+    jobs: usize,
+) -> Result<DedupeReport, ImagixError> {
+    let files = get_image_files(src_folder)?;
+    let fingerprints = Mutex::new(Vec::new());
+    let mut batch = run_batch(&files, 0, jobs, |file| {
+        let fingerprint = fingerprint(file, options.hash)?;
+        let message = format!("Hashed {:?}", file);
+        fingerprints.lock().unwrap().push(fingerprint);
+        Ok(message)
+    })?;
+    let mut fingerprints = fingerprints.into_inner().unwrap();
+    fingerprints.sort_by(|a, b| a.path.cmp(&b.path));
+    let groups = group_duplicates(&fingerprints, options.threshold);
+
+    let mut handled = 0;
+    for group in &groups {
+        for duplicate in &group.duplicates {
+            let result = match &options.action {
+                Action::Report => continue,
+                // Chained into the group by the images between them, not a copy of the kept one
+                Action::Move(_) if duplicate.distance > options.threshold => continue,
+                Action::Move(dir) => move_to(&duplicate.path, dir),
+                Action::Hardlink if duplicate.exact => link_to(&duplicate.path, &group.keep),
+                Action::Hardlink => continue,
+            };
+            match result {
+                Ok(()) => handled += 1,
+                Err(e) => batch.failed.push((duplicate.path.clone(), e)),
+            }
+        }
+    }
+    batch.failed.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(DedupeReport {
+        groups,
+        action: options.action.clone(),
+        threshold: options.threshold,
+        handled,
+        batch,
+    })
+}
+
+// Images with the same content, or hashes at most `threshold` bits apart, end up in a group.
+// Near-duplicates are chained: a group may hold images farther apart than the threshold if
+// others sit between them, so a duplicate's distance to the kept image can exceed it.
+fn group_duplicates(fingerprints: &[Fingerprint], threshold: u32) -> Vec<Group> {
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    // Exact copies first, then one image of each content is compared with the others
+    let mut contents: HashMap<&[u8], usize> = HashMap::new();
+    let mut distinct = Vec::new();
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        match contents.get(fingerprint.digest.as_slice()) {
+            Some(&first) => parents[i] = first,
+            None => {
+                contents.insert(&fingerprint.digest, i);
+                distinct.push(i);
+            }
+        }
+    }
+    for (n, &a) in distinct.iter().enumerate() {
+        for &b in &distinct[n + 1..] {
+            if hamming(fingerprints[a].hash, fingerprints[b].hash) <= threshold {
+                let (ra, rb) = (root(&mut parents, a), root(&mut parents, b));
+                if ra != rb {
+                    parents[rb.max(ra)] = rb.min(ra);
+                }
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<&Fingerprint>> = HashMap::new();
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        let r = root(&mut parents, i);
+        members.entry(r).or_default().push(fingerprint);
+    }
+    let mut groups: Vec<Group> = members
+        .into_values()
+        .filter(|m| m.len() > 1)
+        .map(|m| {
+            let keep = m
+                .iter()
+                .copied()
+                .max_by(|a, b| {
+                    (a.width as u64 * a.height as u64, a.bytes)
+                        .cmp(&(b.width as u64 * b.height as u64, b.bytes))
+                        .then_with(|| b.path.cmp(&a.path))
+                })
+                .unwrap();
+            Group {
+                keep: keep.path.clone(),
+                width: keep.width,
+                height: keep.height,
+                bytes: keep.bytes,
+                duplicates: m
+                    .iter()
+                    .filter(|f| f.path != keep.path)
+                    .map(|f| Duplicate {
+                        path: f.path.clone(),
+                        bytes: f.bytes,
+                        distance: hamming(keep.hash, f.hash),
+                        exact: f.digest == keep.digest,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.keep.cmp(&b.keep));
+    groups
+}
+
+// Moves `file` into `dir`, numbering it if a file of that name is already there
+fn move_to(file: &Path, dir: &Path) -> Result<(), ImagixError> {
+    fs::create_dir_all(dir).at("create folder", dir)?;
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut dest = dir.join(format!("{}{}", stem, extension));
+    let mut n = 1;
+    while dest.exists() {
+        dest = dir.join(format!("{}-{}{}", stem, n, extension));
+        n += 1;
+    }
+    // Renaming fails across file systems, where the file is copied instead
+    if fs::rename(file, &dest).is_err() {
+        fs::copy(file, &dest).at("copy", file)?;
+        fs::remove_file(file).at("remove", file)?;
+    }
+    Ok(())
+}
+
+// Replaces `file` by a hard link to `keep`, so that the file is never missing
+fn link_to(file: &Path, keep: &Path) -> Result<(), ImagixError> {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = file.with_file_name(format!(".{}.imagix-link", name));
+    fs::hard_link(keep, &temporary).map_err(|e| {
+        ImagixError::FileIOError(format!("Unable to link {:?} to {:?}", file, keep).into())
+            .caused_by(e)
+    })?;
+    fs::rename(&temporary, file)
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })
+        .at("replace", file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    // A gradient with a dark disc, or the disc on the other side when `mirrored`
+    fn photo(width: u32, height: u32, mirrored: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (fx, fy) = (x as f64 / width as f64, y as f64 / height as f64);
+            let fx = if mirrored { 1.0 - fx } else { fx };
+            let disc = (fx - 0.3).powi(2) + (fy - 0.4).powi(2) < 0.04;
+            let value = if disc {
+                20
+            } else {
+                (255.0 * (fx + fy) / 2.0) as u8
+            };
+            Rgb([value, value, 255 - value])
+        }))
+    }
+
+    #[test]
+    fn test_perceptual_hashes() {
+        let original = photo(400, 300, false);
+        let smaller = original.resize_exact(200, 150, FilterType::Triangle);
+        let other = photo(400, 300, true);
+        for kind in [
+            HashKind::Average,
+            HashKind::Difference,
+            HashKind::Perceptual,
+        ] {
+            let hash = kind.hash(&original);
+            assert!(hamming(hash, kind.hash(&smaller)) <= 5, "{:?}", kind);
+            assert!(hamming(hash, kind.hash(&other)) > 10, "{:?}", kind);
+        }
+        assert!(parse_threshold("65").is_err());
+
+        let action = |s: &str, dir: Option<&str>| {
+            s.parse::<Action>()
+                .and_then(|a| a.with_folder(dir.map(PathBuf::from)))
+        };
+        assert_eq!(
+            Action::Move(PathBuf::from("dupes")),
+            action("move", Some("dupes")).unwrap()
+        );
+        assert_eq!(Action::Hardlink, action("hardlink", None).unwrap());
+        assert!(action("move", None).is_err());
+        assert!(action("report", Some("dupes")).is_err());
+        assert!(action("delete", None).is_err());
+    }
+    #[test]
+    fn test_dedupe_actions() {
+        let root = std::env::temp_dir().join("imagix-dedupe-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        photo(400, 300, false)
+            .save_with_format(root.join("a.png"), ImageFormat::Png)
+            .unwrap();
+        fs::copy(root.join("a.png"), root.join("a copy.png")).unwrap();
+        photo(200, 150, false)
+            .save_with_format(root.join("b.jpg"), ImageFormat::Jpeg)
+            .unwrap();
+        photo(400, 300, true)
+            .save_with_format(root.join("c.png"), ImageFormat::Png)
+            .unwrap();
+
+        let mut options = DedupeOptions {
+            hash: HashKind::Perceptual,
+            threshold: 6,
+            action: Action::Report,
+        };
+        let report = process_dedupe_request(&options, &root, 2).unwrap();
+        assert!(report.batch.is_success());
+        assert_eq!(1, report.groups.len());
+        let group = &report.groups[0];
+        assert_eq!(root.join("a copy.png"), group.keep);
+        let exact: Vec<bool> = group.duplicates.iter().map(|d| d.exact).collect();
+        assert_eq!(vec![true, false], exact);
+        assert!(report
+            .to_string()
+            .contains("1 groups, 2 duplicates (1 exact)"));
+
+        options.action = Action::Hardlink;
+        let report = process_dedupe_request(&options, &root, 2).unwrap();
+        assert_eq!(1, report.handled);
+        assert!(root.join("a.png").exists());
+
+        options.action = Action::Move(root.join("tmp"));
+        let report = process_dedupe_request(&options, &root, 2).unwrap();
+        assert_eq!(2, report.handled);
+        assert!(root.join("tmp/b.jpg").exists());
+        assert!(!root.join("b.jpg").exists());
+        assert!(root.join("c.png").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn test_chained_group() {
+        // a ~ b and b ~ c, but c is 6 bits away from a
+        let fingerprint = |name: &str, width: u32, hash: u64| Fingerprint {
+            path: PathBuf::from(name),
+            bytes: 1000,
+            width,
+            height: 100,
+            digest: name.as_bytes().to_vec(),
+            hash,
+        };
+        let fingerprints = [
+            fingerprint("a.jpg", 400, 0),
+            fingerprint("b.jpg", 200, 0b111),
+            fingerprint("c.jpg", 100, 0b111111),
+        ];
+        let groups = group_duplicates(&fingerprints, 3);
+        assert_eq!(1, groups.len());
+        assert_eq!(PathBuf::from("a.jpg"), groups[0].keep);
+        let distances: Vec<u32> = groups[0].duplicates.iter().map(|d| d.distance).collect();
+        assert_eq!(vec![3, 6], distances);
+        let report = DedupeReport {
+            groups,
+            action: Action::Report,
+            threshold: 3,
+            handled: 0,
+            batch: BatchReport::default(),
+        };
+        assert!(report
+            .to_string()
+            .contains("\"c.jpg\": distance 6, similar only through the others, left in place"));
+    }
+}
@@ -1,39 +1,209 @@
-use image::error;
+use image::error::ImageError;
 use std::convert::From;
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::{fmt, io};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum ImagixError {
-    FileIOError(String),
-    UserInputError(String),
-    ImageResizingError(String),
-    FormatError(String),
+    FileIOError(Failure),        // 读写文件失败
+    UserInputError(String),      // 命令行或请求有误
+    ImageResizingError(Failure), // 解码或处理图片失败
+    FormatError(Failure),        // 编码, 元数据或清单失败
 }
 
-impl From<io::Error> for ImagixError {
-    fn from(_error: io::Error) -> Self {
-        ImagixError::FileIOError("Error in File I/O".to_string())
+// What failed, on which file, and the error that caused it
+#[derive(Debug, Default)]
+pub struct Failure {
+    pub message: String,
+    // eg. read, decode or write
+    pub operation: Option<&'static str>,
+    pub path: Option<PathBuf>,
+    pub source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure {
+            message,
+            ..Failure::default()
+        }
     }
 }
 
-impl From<error::ImageError> for ImagixError {
-    fn from(_error: error::ImageError) -> Self {
-        ImagixError::ImageResizingError("Error in image processing".to_string())
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        Failure::from(message.to_string())
     }
 }
 
-impl From<io::ErrorKind> for ImagixError {
-    fn from(_error: io::ErrorKind) -> Self {
-        ImagixError::UserInputError("Error in user input".to_string())
+impl ImagixError {
+    fn failure_mut(&mut self) -> Option<&mut Failure> {
+        match self {
+            ImagixError::FileIOError(f)
+            | ImagixError::ImageResizingError(f)
+            | ImagixError::FormatError(f) => Some(f),
+            ImagixError::UserInputError(_) => None,
+        }
+    }
+
+    // Tells what was being done to which file, unless the error already does
+    pub fn at(mut self, operation: &'static str, path: &Path) -> Self {
+        if let Some(failure) = self.failure_mut() {
+            if failure.operation.is_none() && failure.path.is_none() {
+                failure.operation = Some(operation);
+                failure.path = Some(path.to_path_buf());
+            }
+        }
+        self
+    }
+
+    pub fn caused_by<E: Error + Send + Sync + 'static>(mut self, source: E) -> Self {
+        if let Some(failure) = self.failure_mut() {
+            failure.source = Some(Box::new(source));
+        }
+        self
+    }
+
+    // An image that cannot be written in the chosen format
+    pub fn encoding(error: ImageError) -> Self {
+        ImagixError::FormatError(Failure::default()).caused_by(error)
+    }
+
+    // The file the error is about, if it is known
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ImagixError::FileIOError(f)
+            | ImagixError::ImageResizingError(f)
+            | ImagixError::FormatError(f) => f.path.as_deref(),
+            ImagixError::UserInputError(_) => None,
+        }
+    }
+
+    // Status the command line tool exits with; 1 is left for batches where some files failed
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ImagixError::UserInputError(_) => 2,
+            ImagixError::FileIOError(_) => 3,
+            ImagixError::ImageResizingError(_) => 4,
+            ImagixError::FormatError(_) => 5,
+        }
+    }
+
+    // This error then the errors that caused it, eg. for `Unable to read "a.jpg": No such file`
+    pub fn chain(&self) -> Vec<String> {
+        let mut messages = vec![self.to_string()];
+        let mut source = self.source();
+        while let Some(error) = source {
+            messages.push(error.to_string());
+            source = error.source();
+        }
+        messages
+    }
+}
+
+// Adds the operation and the file to the error of a result, see `ImagixError::at`
+pub trait Context<T> {
+    fn at(self, operation: &'static str, path: &Path) -> Result<T, ImagixError>;
+}
+
+impl<T, E: Into<ImagixError>> Context<T> for Result<T, E> {
+    fn at(self, operation: &'static str, path: &Path) -> Result<T, ImagixError> {
+        self.map_err(|e| e.into().at(operation, path))
+    }
+}
+
+impl From<io::Error> for ImagixError {
+    fn from(error: io::Error) -> Self {
+        ImagixError::FileIOError(Failure::default()).caused_by(error)
+    }
+}
+
+// An image that cannot be decoded or processed, whatever the image crate says went wrong;
+// encoding errors go through ImagixError::encoding instead
+impl From<ImageError> for ImagixError {
+    fn from(error: ImageError) -> Self {
+        match error {
+            ImageError::IoError(e) => e.into(),
+            _ => ImagixError::ImageResizingError(Failure::default()).caused_by(error),
+        }
     }
 }
 
 impl fmt::Display for ImagixError {
     fn fmt(&self, out: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(
-            out,
-            "{}",
-            ImagixError::FormatError("Error occurred".to_string())
-        )
+        let (failure, kind) = match self {
+            ImagixError::UserInputError(e) => return write!(out, "{}", e),
+            ImagixError::FileIOError(f) => (f, "File I/O error"),
+            ImagixError::ImageResizingError(f) => (f, "Image processing error"),
+            ImagixError::FormatError(f) => (f, "Format error"),
+        };
+        let context = match (failure.operation, &failure.path) {
+            (Some(operation), Some(path)) => format!("Unable to {} {:?}", operation, path),
+            (Some(operation), None) => format!("Unable to {}", operation),
+            (None, Some(path)) => format!("{:?}", path),
+            (None, None) => String::new(),
+        };
+        // The source is not repeated here, it is the next link of the chain
+        match (context.is_empty(), failure.message.is_empty()) {
+            (true, true) => write!(out, "{}", kind),
+            (true, false) => write!(out, "{}", failure.message),
+            (false, true) => write!(out, "{}", context),
+            (false, false) => write!(out, "{}: {}", context, failure.message),
+        }
+    }
+}
+
+impl Error for ImagixError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImagixError::FileIOError(f)
+            | ImagixError::ImageResizingError(f)
+            | ImagixError::FormatError(f) => {
+                f.source.as_deref().map(|e| e as &(dyn Error + 'static))
+            }
+            ImagixError::UserInputError(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_error_chain() {
+        let path = Path::new("/no/such/image.jpg");
+        let error = fs::read(path).at("read", path).unwrap_err();
+        assert!(matches!(error, ImagixError::FileIOError(_)));
+        assert_eq!(Some(path), error.path());
+        assert_eq!(3, error.exit_code());
+        let chain = error.chain();
+        assert_eq!("Unable to read \"/no/such/image.jpg\"", chain[0]);
+        assert!(chain[1].contains("No such file"), "{}", chain[1]);
+
+        // The innermost context is kept
+        let error = error.at("resize", Path::new("other.jpg"));
+        assert_eq!(Some(path), error.path());
+
+        let error = image::load_from_memory(b"not an image").unwrap_err();
+        let error = ImagixError::from(error).at("decode", path);
+        assert_eq!(4, error.exit_code());
+        assert_eq!(2, error.chain().len());
+        let error = ImagixError::encoding(ImageError::IoError(io::Error::other("x")));
+        assert_eq!(5, error.exit_code());
+
+        let error = ImagixError::FormatError("Unable to write EXIF data".into())
+            .caused_by(io::Error::other("disk full"));
+        assert_eq!(
+            vec!["Unable to write EXIF data", "disk full"],
+            error.chain()
+        );
+        assert_eq!(
+            "File I/O error",
+            ImagixError::from(io::Error::other("x")).to_string()
+        );
     }
 }
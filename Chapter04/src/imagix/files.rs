@@ -0,0 +1,363 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use image::ImageFormat;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::error::ImagixError;
+use super::resize::Mode;
+
+// Name of the folder outputs go to, next to their source, when there is no --out-dir
+pub const DEFAULT_OUTPUT_FOLDER: &str = "tmp";
+
+// The path with `.`, `..` and symbolic links resolved, so that it names the same file from any
+// working directory. A path that does not exist yet is resolved up to its last existing folder.
+pub fn resolve(path: &Path) -> PathBuf {
+    if let Ok(resolved) = path.canonicalize() {
+        return resolved;
+    }
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+    };
+    match path.components().next_back() {
+        Some(Component::Normal(name)) => resolve(parent).join(name),
+        Some(Component::ParentDir) => {
+            let parent = resolve(parent);
+            parent.parent().map_or(parent.clone(), Path::to_path_buf)
+        }
+        _ => resolve(parent),
+    }
+}
+
+// Format of a file, told from its first bytes rather than its extension
+pub fn sniff(path: &Path) -> Option<ImageFormat> {
+    let mut header = Vec::with_capacity(64);
+    fs::File::open(path)
+        .and_then(|f| f.take(64).read_to_end(&mut header))
+        .ok()?;
+    image::guess_format(&header).ok()
+}
+
+// Images found under a source folder
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub images: Vec<PathBuf>,
+    // Files that matched the filters but are not images
+    pub skipped: Vec<PathBuf>,
+}
+
+// Which files of a source folder to process
+#[derive(Debug, Default)]
+pub struct Selection {
+    recursive: bool,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    out_dir: Option<PathBuf>,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Selection::default()
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    // Globs are matched against the path relative to the source folder, ignoring case, eg.
+    // `*.jpg` or `products/**`. Only files matching one of the includes are processed, if any.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    // Outputs written there are not picked up again as inputs
+    pub fn out_dir(mut self, out_dir: Option<PathBuf>) -> Self {
+        self.out_dir = out_dir;
+        self
+    }
+
+    // Where the outputs of images found under `root` go
+    pub fn layout(&self, root: &Path) -> OutputLayout {
+        OutputLayout::new(root, self.out_dir.clone())
+    }
+
+    pub fn collect(&self, src_folder: &Path) -> Result<Listing, ImagixError> {
+        if !src_folder.is_dir() {
+            return Err(ImagixError::UserInputError(
+                "Invalid source folder".to_string(),
+            ));
+        }
+        let include = glob_set(&self.include)?;
+        let exclude = glob_set(&self.exclude)?;
+        let out_dir = self.out_dir.as_ref().and_then(|d| d.canonicalize().ok());
+        let walker = WalkDir::new(src_folder)
+            .min_depth(1)
+            .max_depth(if self.recursive { usize::MAX } else { 1 })
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| {
+                !e.file_type().is_dir()
+                    || match &out_dir {
+                        Some(out_dir) => e.path().canonicalize().ok().as_ref() != Some(out_dir),
+                        None => e.file_name() != DEFAULT_OUTPUT_FOLDER,
+                    }
+            });
+        let mut listing = Listing::default();
+        for entry in walker {
+            let entry = entry.map_err(|e| {
+                let path = e.path().unwrap_or(src_folder).to_path_buf();
+                ImagixError::from(io::Error::from(e)).at("list", &path)
+            })?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(src_folder)
+                .unwrap_or(entry.path());
+            if !self.matches(&include, &exclude, relative) {
+                continue;
+            }
+            if sniff(entry.path()).is_some() {
+                listing.images.push(entry.into_path());
+            } else {
+                listing.skipped.push(entry.into_path());
+            }
+        }
+        Ok(listing)
+    }
+
+    // The first of `paths`, which need not exist, that collecting `src_folder` would pick up,
+    // eg. to tell whether outputs written there would be read back as inputs
+    pub fn first_selected<'a>(
+        &self,
+        src_folder: &Path,
+        paths: &'a [PathBuf],
+    ) -> Result<Option<&'a PathBuf>, ImagixError> {
+        let include = glob_set(&self.include)?;
+        let exclude = glob_set(&self.exclude)?;
+        let folder = resolve(src_folder);
+        let out_dir = self.out_dir.as_deref().map(resolve);
+        let selected = |path: &PathBuf| {
+            let path = resolve(path);
+            let relative = match path.strip_prefix(&folder) {
+                Ok(relative) => relative,
+                Err(_) => return false,
+            };
+            if !self.recursive && relative.components().count() > 1 {
+                return false;
+            }
+            // In a folder the walk does not go into
+            let skipped = relative
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .any(|dir| match &out_dir {
+                    Some(out_dir) => folder.join(dir) == *out_dir,
+                    None => dir.file_name() == Some(DEFAULT_OUTPUT_FOLDER.as_ref()),
+                });
+            !skipped && self.matches(&include, &exclude, relative)
+        };
+        Ok(paths.iter().find(|path| selected(path)))
+    }
+
+    fn matches(&self, include: &GlobSet, exclude: &GlobSet, relative: &Path) -> bool {
+        (self.include.is_empty() || include.is_match(relative)) && !exclude.is_match(relative)
+    }
+}
+
+// The images a request works on and where their outputs go: the file itself in single mode, or
+// the images selected in the folder
+pub fn collect_inputs(
+    mode: Mode,
+    src_folder: &Path,
+    selection: &Selection,
+) -> Result<(Listing, OutputLayout), ImagixError> {
+    match mode {
+        Mode::Single => {
+            let root = src_folder.parent().unwrap_or(Path::new(""));
+            let listing = Listing {
+                images: vec![src_folder.to_path_buf()],
+                skipped: Vec::new(),
+            };
+            Ok((listing, selection.layout(root)))
+        }
+        Mode::All => Ok((selection.collect(src_folder)?, selection.layout(src_folder))),
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, ImagixError> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| ImagixError::UserInputError(format!("Wrong glob `{}`: {}", pattern, e)))?;
+        set.add(glob);
+    }
+    set.build()
+        .map_err(|e| ImagixError::UserInputError(e.to_string()))
+}
+
+// Where the output of each input goes: `<out-dir>/<path relative to the source folder>`, or
+// the `tmp` folder next to the input when there is no out-dir
+#[derive(Debug)]
+pub struct OutputLayout {
+    root: PathBuf,
+    out_dir: Option<PathBuf>,
+}
+
+impl OutputLayout {
+    pub fn new(root: &Path, out_dir: Option<PathBuf>) -> Self {
+        OutputLayout {
+            root: root.to_path_buf(),
+            out_dir,
+        }
+    }
+
+    // Where the outputs of the source folder itself go
+    pub fn out_root(&self) -> PathBuf {
+        match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => self.root.join(DEFAULT_OUTPUT_FOLDER),
+        }
+    }
+
+    // Whether `output` is in a folder this layout writes to, so that it may be removed once
+    // its source is gone
+    pub fn owns(&self, output: &Path) -> bool {
+        let output = resolve(output);
+        match &self.out_dir {
+            Some(out_dir) => output.starts_with(resolve(out_dir)),
+            None => {
+                let folder = output.parent().and_then(Path::file_name);
+                output.starts_with(resolve(&self.root))
+                    && folder == Some(DEFAULT_OUTPUT_FOLDER.as_ref())
+            }
+        }
+    }
+
+    pub fn dir_for(&self, file: &Path) -> PathBuf {
+        let parent = file.parent().unwrap_or(Path::new(""));
+        match &self.out_dir {
+            Some(out_dir) => out_dir.join(parent.strip_prefix(&self.root).unwrap_or(Path::new(""))),
+            None => parent.join(DEFAULT_OUTPUT_FOLDER),
+        }
+    }
+
+    // Names the output of every file `<stem>.<extension>`. When two inputs would be written
+    // to the same file, eg. photo.jpg and photo.png as webp, the later one in input order
+    // becomes `<stem>-1.<extension>`, `<stem>-2.<extension>` and so on.
+    pub fn plan(&self, files: &[(PathBuf, &str)]) -> HashMap<PathBuf, PathBuf> {
+        let mut taken = HashSet::new();
+        let mut outputs = HashMap::new();
+        for (file, extension) in files {
+            let dir = self.dir_for(file);
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            let mut output = dir.join(format!("{}.{}", stem, extension));
+            let mut n = 0;
+            // Collisions are checked without case, as on case insensitive file systems
+            while !taken.insert(output.to_string_lossy().to_lowercase()) {
+                n += 1;
+                output = dir.join(format!("{}-{}.{}", stem, n, extension));
+            }
+            outputs.insert(file.clone(), output);
+        }
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recursive_selection() {
+        let root = std::env::temp_dir().join("imagix-files-test");
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["shop/shoes", "tmp", "out"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let png = |path: &str| {
+            image::DynamicImage::new_rgb8(2, 2)
+                .save_with_format(root.join(path), ImageFormat::Png)
+                .unwrap()
+        };
+        // The extension does not matter, the content does
+        png("a.JPEG");
+        png("shop/b.webp.png");
+        png("shop/shoes/c.png");
+        png("tmp/old.png");
+        png("out/old.png");
+        fs::write(root.join("shop/notes.png"), "not an image").unwrap();
+
+        let names = |listing: Listing| -> Vec<String> {
+            listing
+                .images
+                .iter()
+                .map(|p| {
+                    p.strip_prefix(&root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+        let top = Selection::new().collect(&root).unwrap();
+        assert_eq!(vec!["a.JPEG"], names(top));
+        let all = Selection::new()
+            .recursive(true)
+            .out_dir(Some(root.join("out")))
+            .exclude("**/shoes/**")
+            .collect(&root)
+            .unwrap();
+        assert_eq!(1, all.skipped.len());
+        assert_eq!(vec!["a.JPEG", "shop/b.webp.png", "tmp/old.png"], names(all));
+        let shop = Selection::new()
+            .recursive(true)
+            .include("shop/**")
+            .collect(&root)
+            .unwrap();
+        assert_eq!(vec!["shop/b.webp.png", "shop/shoes/c.png"], names(shop));
+        assert!(Selection::new().include("[").collect(&root).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn test_output_layout() {
+        let layout = OutputLayout::new(Path::new("/src"), Some(PathBuf::from("/out")));
+        let files = [
+            (PathBuf::from("/src/a/photo.jpg"), "webp"),
+            (PathBuf::from("/src/a/photo.png"), "webp"),
+            (PathBuf::from("/src/a/PHOTO.gif"), "webp"),
+            (PathBuf::from("/src/b/photo.png"), "webp"),
+        ];
+        let outputs = layout.plan(&files);
+        let output = |i: usize| outputs[&files[i].0].to_string_lossy().into_owned();
+        assert_eq!("/out/a/photo.webp", output(0));
+        assert_eq!("/out/a/photo-1.webp", output(1));
+        assert_eq!("/out/a/PHOTO-2.webp", output(2));
+        assert_eq!("/out/b/photo.webp", output(3));
+        let default = OutputLayout::new(Path::new("/src"), None);
+        assert_eq!(
+            PathBuf::from("/src/a/tmp"),
+            default.dir_for(Path::new("/src/a/photo.jpg"))
+        );
+
+        assert!(layout.owns(Path::new("/out/a/../b/photo.webp")));
+        assert!(!layout.owns(Path::new("/out/../src/a/photo.jpg")));
+        assert!(default.owns(Path::new("/src/./a/tmp/photo.jpg")));
+        assert!(!default.owns(Path::new("/src/a/photo.jpg")));
+        assert!(!default.owns(Path::new("/elsewhere/tmp/photo.jpg")));
+    }
+}
@@ -0,0 +1,282 @@
+use image::codecs::gif::GifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{self, PngEncoder};
+use image::{DynamicImage, ImageEncoder, ImageFormat};
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+use super::error::ImagixError;
+
+// Format the resized images are written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Keep, // 与输入格式相同
+    Png,
+    Jpeg,
+    Webp,
+    Gif,
+    Bmp,
+    Tiff,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(OutputFormat::Keep),
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::Webp),
+            "gif" => Ok(OutputFormat::Gif),
+            "bmp" => Ok(OutputFormat::Bmp),
+            "tiff" | "tif" => Ok(OutputFormat::Tiff),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for format `{}`, expected keep, png, jpeg, webp, gif, bmp or tiff",
+                s
+            ))),
+        }
+    }
+}
+
+impl OutputFormat {
+    fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            OutputFormat::Keep => None,
+            OutputFormat::Png => Some(ImageFormat::Png),
+            OutputFormat::Jpeg => Some(ImageFormat::Jpeg),
+            OutputFormat::Webp => Some(ImageFormat::WebP),
+            OutputFormat::Gif => Some(ImageFormat::Gif),
+            OutputFormat::Bmp => Some(ImageFormat::Bmp),
+            OutputFormat::Tiff => Some(ImageFormat::Tiff),
+        }
+    }
+}
+
+// Encoding quality of lossy formats, from 1 to 100
+pub fn parse_quality(s: &str) -> Result<u8, ImagixError> {
+    match s.parse::<u8>() {
+        Ok(quality) => check_quality(quality),
+        Err(_) => Err(wrong_quality(s)),
+    }
+}
+
+pub fn check_quality(quality: u8) -> Result<u8, ImagixError> {
+    if (1..=100).contains(&quality) {
+        Ok(quality)
+    } else {
+        Err(wrong_quality(quality))
+    }
+}
+
+fn wrong_quality(value: impl fmt::Display) -> ImagixError {
+    ImagixError::UserInputError(format!(
+        "Wrong value for quality `{}`, expected a number from 1 to 100",
+        value
+    ))
+}
+
+// PNG compression: fast, default, best, none or a level from 1 to 9
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PngCompression(pub png::CompressionType);
+
+impl FromStr for PngCompression {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let compression = match s {
+            "fast" => png::CompressionType::Fast,
+            "default" => png::CompressionType::Default,
+            "best" => png::CompressionType::Best,
+            "none" => png::CompressionType::Uncompressed,
+            _ => match s.parse::<u8>() {
+                Ok(level) if (1..=9).contains(&level) => png::CompressionType::Level(level),
+                _ => {
+                    return Err(ImagixError::UserInputError(format!(
+                        "Wrong value for PNG compression `{}`, expected fast, default, best, none or a level from 1 to 9",
+                        s
+                    )))
+                }
+            },
+        };
+        Ok(PngCompression(compression))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodeOptions {
+    pub format: OutputFormat,
+    pub quality: u8,
+    pub png_compression: PngCompression,
+    // Copy camera, date and GPS tags to the output; only JPEG and PNG can hold them
+    pub keep_metadata: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            format: OutputFormat::Keep,
+            quality: 85,
+            png_compression: PngCompression(png::CompressionType::Default),
+            keep_metadata: false,
+        }
+    }
+}
+
+impl EncodeOptions {
+    // The format to write an image in, given the format it was read in
+    pub fn target_format(&self, source: Option<ImageFormat>) -> Result<ImageFormat, ImagixError> {
+        if let Some(format) = self.format.image_format() {
+            return Ok(format);
+        }
+        match source {
+            Some(
+                format @ (ImageFormat::Png
+                | ImageFormat::Jpeg
+                | ImageFormat::WebP
+                | ImageFormat::Gif
+                | ImageFormat::Bmp
+                | ImageFormat::Tiff),
+            ) => Ok(format),
+            Some(format) => Err(ImagixError::FormatError(
+                format!(
+                    "Cannot keep the {:?} format, choose one with --format",
+                    format
+                )
+                .into(),
+            )),
+            None => Err(ImagixError::FormatError(
+                "Unable to tell the format of the image".into(),
+            )),
+        }
+    }
+
+    // Encodes the image with the given raw EXIF block, which is dropped by formats other than
+    // JPEG and PNG
+    pub fn encode(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        exif: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, ImagixError> {
+        let mut bytes = Cursor::new(Vec::new());
+        match format {
+            // JPEG has no alpha channel
+            ImageFormat::Jpeg => {
+                let mut encoder = JpegEncoder::new_with_quality(&mut bytes, self.quality);
+                if let Some(exif) = exif {
+                    encoder.set_exif_metadata(exif).map_err(|e| {
+                        ImagixError::FormatError("Unable to add EXIF data".into()).caused_by(e)
+                    })?;
+                }
+                DynamicImage::ImageRgb8(img.to_rgb8())
+                    .write_with_encoder(encoder)
+                    .map_err(ImagixError::encoding)?;
+            }
+            ImageFormat::Png => {
+                let mut encoder = PngEncoder::new_with_quality(
+                    &mut bytes,
+                    self.png_compression.0,
+                    png::FilterType::Adaptive,
+                );
+                if let Some(exif) = exif {
+                    encoder.set_exif_metadata(exif).map_err(|e| {
+                        ImagixError::FormatError("Unable to add EXIF data".into()).caused_by(e)
+                    })?;
+                }
+                img.write_with_encoder(encoder)
+                    .map_err(ImagixError::encoding)?;
+            }
+            ImageFormat::WebP => {
+                let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+                let encoder = webp::Encoder::from_image(&rgba)
+                    .map_err(|e| ImagixError::FormatError(e.into()))?;
+                // `encode` panics on what libwebp refuses, eg. images over 16383 pixels wide
+                return encoder
+                    .encode_simple(false, self.quality as f32)
+                    .map(|bytes| bytes.to_vec())
+                    .map_err(|e| {
+                        ImagixError::FormatError(format!("Unable to encode WebP: {:?}", e).into())
+                    });
+            }
+            ImageFormat::Gif => {
+                GifEncoder::new(&mut bytes)
+                    .encode(
+                        img.to_rgba8().as_raw(),
+                        img.width(),
+                        img.height(),
+                        image::ExtendedColorType::Rgba8,
+                    )
+                    .map_err(ImagixError::encoding)?;
+            }
+            _ => {
+                DynamicImage::ImageRgba8(img.to_rgba8())
+                    .write_to(&mut bytes, format)
+                    .map_err(ImagixError::encoding)?;
+            }
+        }
+        Ok(bytes.into_inner())
+    }
+}
+
+// Extension of the files written in `format`
+pub fn extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("img")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_encode_formats() {
+        let img = DynamicImage::new_rgba8(16, 8);
+        let keep = EncodeOptions::default();
+        assert_eq!(
+            ImageFormat::Jpeg,
+            keep.target_format(Some(ImageFormat::Jpeg)).unwrap()
+        );
+        assert!(keep.target_format(Some(ImageFormat::Ico)).is_err());
+        assert!(keep.target_format(None).is_err());
+        for name in ["png", "jpeg", "webp", "gif", "bmp", "tiff"] {
+            let options = EncodeOptions {
+                format: name.parse().unwrap(),
+                ..EncodeOptions::default()
+            };
+            let format = options.target_format(Some(ImageFormat::Png)).unwrap();
+            let bytes = options.encode(&img, format, None).unwrap();
+            assert_eq!(format, image::guess_format(&bytes).unwrap());
+            assert_eq!(
+                (16, 8),
+                image::load_from_memory(&bytes).unwrap().dimensions()
+            );
+        }
+        assert_eq!("jpg", extension(ImageFormat::Jpeg));
+        assert_eq!("tiff", extension(ImageFormat::Tiff));
+    }
+    #[test]
+    fn test_webp_too_large() {
+        let options = EncodeOptions {
+            format: "webp".parse().unwrap(),
+            ..EncodeOptions::default()
+        };
+        let error = options
+            .encode(&DynamicImage::new_rgb8(16384, 1), ImageFormat::WebP, None)
+            .unwrap_err();
+        assert!(matches!(error, ImagixError::FormatError(_)));
+        assert!(error.to_string().contains("BAD_DIMENSION"), "{}", error);
+    }
+    #[test]
+    fn test_invalid_encode_input() {
+        assert!("avif".parse::<OutputFormat>().is_err());
+        assert!(parse_quality("0").is_err());
+        assert!(parse_quality("101").is_err());
+        assert!("10".parse::<PngCompression>().is_err());
+        assert_eq!(
+            PngCompression(png::CompressionType::Level(6)),
+            "6".parse().unwrap()
+        );
+    }
+}
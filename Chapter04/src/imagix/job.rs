@@ -0,0 +1,720 @@
+use image::GenericImageView;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::batch::{run_batch, BatchReport, Planned};
+use super::error::{Context, ImagixError};
+use super::files::{resolve, sniff, Selection};
+use super::format::{check_quality, extension, EncodeOptions, PngCompression};
+use super::metadata::kept_tags;
+use super::pipeline::{open_upright, Operation, Pipeline};
+use super::resize::{Background, Elapsed};
+use super::watermark::{check_opacity, check_relative_width, Position, Watermark};
+
+// A job file lists named profiles, which are steps shared by jobs, and the jobs themselves:
+//
+//     [profiles.web]
+//     steps = [{ resize = "1600x" }, { convert = { format = "webp", quality = 80 } }]
+//
+//     [[jobs]]
+//     name = "gallery"
+//     inputs = ["photos/**/*.jpg"]
+//     profile = "web"
+//     steps = [{ watermark = { text = "© Shop", font = "fonts/DejaVuSans.ttf" } }]
+//     output = "public/{dir}/{stem}-{width}w.{ext}"
+//
+// Paths are relative to the folder of the job file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileSpec>,
+    #[serde(default)]
+    jobs: Vec<JobSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileSpec {
+    #[serde(default)]
+    steps: Vec<Step>,
+    output: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobSpec {
+    name: String,
+    // Globs such as `photos/**/*.jpg`, matched without case
+    inputs: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    profile: Option<String>,
+    // Run after the steps of the profile
+    #[serde(default)]
+    steps: Vec<Step>,
+    // Overrides the output of the profile
+    output: Option<String>,
+}
+
+// A step is a table with a single key, eg. `{ resize = "800x" }`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Step {
+    Resize(String), // 与 --ops 的 resize 相同, 如 800x, x600 或 50%
+    Ops(String),    // 任意 --ops 操作, 如 "rotate:90,grayscale"
+    Convert(ConvertSpec),
+    Watermark(WatermarkSpec),
+    Metadata(MetadataSpec), // keep 或 strip
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConvertSpec {
+    format: String,
+    quality: Option<u8>,
+    png_compression: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WatermarkSpec {
+    image: Option<PathBuf>,
+    text: Option<String>,
+    font: Option<PathBuf>,
+    size: Option<f32>,
+    color: Option<String>,
+    position: Option<String>,
+    opacity: Option<f32>,
+    margin: Option<u32>,
+    relative_width: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MetadataSpec {
+    Keep,
+    Strip,
+}
+
+// What the steps of a profile and then of a job add up to
+#[derive(Debug, Clone, Default)]
+struct Plan {
+    pipeline: Pipeline,
+    encoding: EncodeOptions,
+}
+
+impl Plan {
+    fn step(self, step: &Step, dir: &Path) -> Result<Plan, ImagixError> {
+        let Plan {
+            mut pipeline,
+            mut encoding,
+        } = self;
+        match step {
+            Step::Resize(spec) => {
+                let resize: Pipeline = format!("resize:{}", spec).parse()?;
+                pipeline = resize
+                    .operations()
+                    .iter()
+                    .cloned()
+                    .fold(pipeline, Pipeline::then);
+            }
+            Step::Ops(ops) => {
+                let ops: Pipeline = ops.parse()?;
+                pipeline = ops
+                    .operations()
+                    .iter()
+                    .cloned()
+                    .fold(pipeline, Pipeline::then);
+            }
+            Step::Convert(convert) => {
+                encoding.format = convert.format.parse()?;
+                if let Some(quality) = convert.quality {
+                    encoding.quality = check_quality(quality)?;
+                }
+                if let Some(compression) = &convert.png_compression {
+                    encoding.png_compression = PngCompression::from_str(compression)?;
+                }
+            }
+            Step::Watermark(spec) => {
+                let watermark = spec.build(dir)?;
+                pipeline = pipeline.then(Operation::Watermark(Arc::new(watermark)));
+            }
+            Step::Metadata(metadata) => {
+                encoding.keep_metadata = matches!(metadata, MetadataSpec::Keep);
+            }
+        }
+        Ok(Plan { pipeline, encoding })
+    }
+}
+
+impl WatermarkSpec {
+    // The logo or font is loaded here, so that a missing one is found before any processing
+    fn build(&self, dir: &Path) -> Result<Watermark, ImagixError> {
+        let color = match &self.color {
+            Some(color) => color.parse()?,
+            None => Background::from_str("white")?,
+        };
+        let watermark = match (&self.image, &self.text, &self.font) {
+            (Some(image), None, None) => Watermark::from_image(&dir.join(image))?,
+            (None, Some(text), Some(font)) => {
+                Watermark::from_text(text, &dir.join(font), self.size.unwrap_or(24.0), color.0)?
+            }
+            (None, Some(_), None) => {
+                return Err(ImagixError::UserInputError(
+                    "Specify the font of the text".to_string(),
+                ))
+            }
+            _ => {
+                return Err(ImagixError::UserInputError(
+                    "Specify either an image or a text with a font".to_string(),
+                ))
+            }
+        };
+        let position = match &self.position {
+            Some(position) => position.parse()?,
+            None => Position::from_str("bottom-right")?,
+        };
+        let opacity = check_opacity(self.opacity.unwrap_or(1.0))?;
+        let relative_width = self.relative_width.map(check_relative_width).transpose()?;
+        Ok(watermark
+            .position(position)
+            .opacity(opacity)
+            .margin(self.margin.unwrap_or(0))
+            .relative_width(relative_width))
+    }
+}
+
+// A part of an output template
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Stem,   // 输入文件名, 不含扩展名
+    Ext,    // 输出格式的扩展名
+    Width,  // 处理后的宽度
+    Height, // 处理后的高度
+    Dir,    // 输入相对于 glob 起始文件夹的路径
+    Job,    // 任务名
+}
+
+// Names outputs, eg. `{stem}-{width}w.{ext}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl FromStr for Template {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or_else(|| {
+                ImagixError::UserInputError(format!("Unclosed `{{` in output `{}`", s))
+            })?;
+            parts.push(match &rest[start + 1..start + end] {
+                "stem" => Part::Stem,
+                "ext" => Part::Ext,
+                "width" => Part::Width,
+                "height" => Part::Height,
+                "dir" => Part::Dir,
+                "job" => Part::Job,
+                name => {
+                    return Err(ImagixError::UserInputError(format!(
+                        "Wrong placeholder `{{{}}}` in output `{}`, expected stem, ext, width, height, dir or job",
+                        name, s
+                    )))
+                }
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err(ImagixError::UserInputError(
+                "The output cannot be empty".to_string(),
+            ));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    // Placeholders whose value is not known yet are left as they are, eg. the size in a dry run
+    fn render(
+        &self,
+        job: &str,
+        input: &Input,
+        file: &Path,
+        ext: &str,
+        size: Option<(u32, u32)>,
+    ) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Stem => out.push_str(&file.file_stem().unwrap_or_default().to_string_lossy()),
+                Part::Ext => out.push_str(ext),
+                Part::Width => match size {
+                    Some((width, _)) => out.push_str(&width.to_string()),
+                    None => out.push_str("{width}"),
+                },
+                Part::Height => match size {
+                    Some((_, height)) => out.push_str(&height.to_string()),
+                    None => out.push_str("{height}"),
+                },
+                Part::Dir => {
+                    let parent = file.parent().unwrap_or(Path::new(""));
+                    let dir = parent.strip_prefix(&input.base).unwrap_or(Path::new(""));
+                    out.push_str(&dir.to_string_lossy());
+                }
+                Part::Job => out.push_str(job),
+            }
+        }
+        out
+    }
+
+    // The folders before the first placeholder, which hold every output
+    fn root(&self) -> PathBuf {
+        match self.parts.first() {
+            Some(Part::Text(text)) => {
+                PathBuf::from(text.rsplit_once('/').map_or("", |(dir, _)| dir))
+            }
+            _ => PathBuf::new(),
+        }
+    }
+}
+
+// The folder a glob starts from and the rest of the glob, eg. `photos` and `**/*.jpg`
+#[derive(Debug)]
+struct Input {
+    base: PathBuf,
+    pattern: String,
+}
+
+impl Input {
+    fn new(glob: &str, dir: &Path) -> Input {
+        let literal = |c: &str| !c.contains(['*', '?', '[', '{']);
+        let components: Vec<&str> = glob.split('/').collect();
+        let split = components
+            .iter()
+            .position(|c| !literal(c))
+            .unwrap_or(components.len() - 1);
+        let base = components[..split].join("/");
+        let base = match base.as_str() {
+            "" if glob.starts_with('/') => PathBuf::from("/"),
+            _ => dir.join(base),
+        };
+        Input {
+            base,
+            pattern: components[split..].join("/"),
+        }
+    }
+}
+
+// Images of a job, each with the input that matched it
+type Matches<'a> = Vec<(PathBuf, &'a Input)>;
+
+// A job ready to run: every step parsed and every logo or font loaded
+#[derive(Debug)]
+pub struct Job {
+    pub name: String,
+    inputs: Vec<Input>,
+    exclude: Vec<String>,
+    pipeline: Pipeline,
+    encoding: EncodeOptions,
+    output: Template,
+    // Folder of the job file
+    dir: PathBuf,
+}
+
+// The whole file is checked before any job runs; all the mistakes found are reported together
+pub fn load_jobs(path: &Path) -> Result<Vec<Job>, ImagixError> {
+    let text = fs::read_to_string(path).at("read", path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse_jobs(&text, dir).map_err(|e| match e {
+        ImagixError::UserInputError(message) => {
+            ImagixError::UserInputError(format!("Invalid job file {:?}: {}", path, message))
+        }
+        e => e,
+    })
+}
+
+fn parse_jobs(text: &str, dir: &Path) -> Result<Vec<Job>, ImagixError> {
+    let file: JobFile = toml::from_str(text)
+        .map_err(|e| ImagixError::UserInputError(e.to_string().trim_end().to_string()))?;
+    let mut errors = Vec::new();
+    let mut profiles = HashMap::new();
+    for (name, profile) in &file.profiles {
+        let owner = format!("profile `{}`", name);
+        let plan = plan(&profile.steps, Plan::default(), dir, &owner, &mut errors);
+        profiles.insert(name.as_str(), (plan, profile.output.as_deref()));
+    }
+    if file.jobs.is_empty() {
+        errors.push("no [[jobs]]".to_string());
+    }
+    let mut names = HashSet::new();
+    let mut jobs = Vec::new();
+    for spec in &file.jobs {
+        if !names.insert(spec.name.as_str()) {
+            errors.push(format!("job `{}`: the name is used twice", spec.name));
+        }
+        jobs.extend(job(spec, &profiles, dir, &mut errors));
+    }
+    match errors.len() {
+        0 => Ok(jobs),
+        1 => Err(ImagixError::UserInputError(errors.remove(0))),
+        n => Err(ImagixError::UserInputError(format!(
+            "{} errors\n  {}",
+            n,
+            errors.join("\n  ")
+        ))),
+    }
+}
+
+// A wrong step is reported and left out, so that the steps after it are checked too
+fn plan(steps: &[Step], plan: Plan, dir: &Path, owner: &str, errors: &mut Vec<String>) -> Plan {
+    steps
+        .iter()
+        .enumerate()
+        .fold(plan, |plan, (i, step)| match plan.clone().step(step, dir) {
+            Ok(plan) => plan,
+            Err(e) => {
+                errors.push(format!("{}: step {}: {}", owner, i + 1, e));
+                plan
+            }
+        })
+}
+
+fn job(
+    spec: &JobSpec,
+    profiles: &HashMap<&str, (Plan, Option<&str>)>,
+    dir: &Path,
+    errors: &mut Vec<String>,
+) -> Option<Job> {
+    let owner = format!("job `{}`", spec.name);
+    let count = errors.len();
+    let (start, profile_output) = match &spec.profile {
+        Some(name) => match profiles.get(name.as_str()) {
+            Some((plan, output)) => (plan.clone(), *output),
+            None => {
+                errors.push(format!("{}: no profile named `{}`", owner, name));
+                (Plan::default(), None)
+            }
+        },
+        None => (Plan::default(), None),
+    };
+    let Plan { pipeline, encoding } = plan(&spec.steps, start, dir, &owner, errors);
+    let mut error = |message: String| errors.push(format!("{}: {}", owner, message));
+    let output = match spec.output.as_deref().or(profile_output) {
+        Some(output) => output
+            .parse::<Template>()
+            .map_err(|e| error(e.to_string()))
+            .ok(),
+        None => {
+            error("specify the output, eg. \"{stem}.{ext}\"".to_string());
+            None
+        }
+    };
+    if spec.inputs.is_empty() {
+        error("specify at least one input".to_string());
+    }
+    let inputs: Vec<Input> = spec
+        .inputs
+        .iter()
+        .map(|glob| Input::new(glob, dir))
+        .collect();
+    for (glob, input) in spec.inputs.iter().zip(&inputs) {
+        if !input.base.is_dir() {
+            error(format!("input `{}`: no folder {:?}", glob, input.base));
+        }
+    }
+    let job = Job {
+        name: spec.name.clone(),
+        inputs,
+        exclude: spec.exclude.clone(),
+        pipeline,
+        encoding,
+        output: output?,
+        dir: dir.to_path_buf(),
+    };
+    if errors.len() > count {
+        return None;
+    }
+    // A wrong glob is only found when it is built
+    match job.read_back() {
+        Ok(None) => Some(job),
+        Ok(Some((output, input))) => {
+            errors.push(format!(
+                "{}: outputs such as {:?} would be read back by input `{}`, write them to another folder or name them so that it cannot match",
+                owner, output, spec.inputs[input]
+            ));
+            None
+        }
+        Err(e) => {
+            errors.push(format!("{}: {}", owner, e));
+            None
+        }
+    }
+}
+
+impl Job {
+    fn selection(&self, input: &Input) -> Selection {
+        // Outputs already written under the folder of an input are not inputs again
+        let selection = Selection::new()
+            .recursive(input.pattern.contains('/'))
+            .out_dir(Some(self.dir.join(self.output.root())));
+        self.exclude
+            .iter()
+            .fold(selection, |selection, pattern| selection.exclude(pattern))
+    }
+
+    // Every image matched by an input, once, with the input that matched it first
+    fn collect(&self) -> Result<(Matches<'_>, usize), ImagixError> {
+        let mut seen = HashSet::new();
+        let mut files = Vec::new();
+        let mut skipped = 0;
+        for input in &self.inputs {
+            let listing = self
+                .selection(input)
+                .include(&input.pattern)
+                .collect(&input.base)?;
+            skipped += listing.skipped.len();
+            for file in listing.images {
+                if seen.insert(file.clone()) {
+                    files.push((file, input));
+                }
+            }
+        }
+        Ok((files, skipped))
+    }
+
+    // An output the next run would take for an input, with the index of that input: an output
+    // named like its input, or written where an input looks without the input leaving it out
+    fn read_back(&self) -> Result<Option<(PathBuf, usize)>, ImagixError> {
+        let (files, _) = self.collect()?;
+        let outputs: Vec<PathBuf> = files
+            .iter()
+            .filter_map(|(file, input)| {
+                let format = self.encoding.target_format(sniff(file)).ok()?;
+                Some(self.output(input, file, extension(format), None))
+            })
+            .collect();
+        for (i, input) in self.inputs.iter().enumerate() {
+            let selection = self.selection(input).include(&input.pattern);
+            if let Some(output) = selection.first_selected(&input.base, &outputs)? {
+                return Ok(Some((output.clone(), i)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn output(&self, input: &Input, file: &Path, ext: &str, size: Option<(u32, u32)>) -> PathBuf {
+        let rendered = self.output.render(&self.name, input, file, ext, size);
+        // Folds the `//` left by an empty {dir}
+        let path: PathBuf = Path::new(&rendered)
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect();
+        self.dir.join(path)
+    }
+
+    // Lists what the job would write, without reading the images
+    pub fn dry_run(&self) -> Result<BatchReport, ImagixError> {
+        let (files, skipped) = self.collect()?;
+        let planned = files
+            .iter()
+            .map(|(file, input)| {
+                let (output, reason) = match self.encoding.target_format(sniff(file)) {
+                    Ok(format) => {
+                        let output = self.output(input, file, extension(format), None);
+                        (Some(output), "new".to_string())
+                    }
+                    Err(e) => (None, e.to_string()),
+                };
+                Planned {
+                    input: file.clone(),
+                    output,
+                    reason,
+                }
+            })
+            .collect();
+        Ok(BatchReport {
+            skipped,
+            dry_run: true,
+            planned,
+            ..BatchReport::default()
+        })
+    }
+
+    pub fn run(&self, jobs: usize) -> Result<BatchReport, ImagixError> {
+        let (files, skipped) = self.collect()?;
+        let inputs: HashMap<&Path, &Input> = files
+            .iter()
+            .map(|(file, input)| (file.as_path(), *input))
+            .collect();
+        let paths: Vec<PathBuf> = files.iter().map(|(file, _)| file.clone()).collect();
+        let written = Mutex::new(HashSet::new());
+        run_batch(&paths, skipped, jobs, |file| {
+            let timer = Instant::now();
+            let source = open_upright(file)?;
+            let format = self
+                .encoding
+                .target_format(source.format)
+                .at("convert", file)?;
+            let img = self.pipeline.apply(source.image).at("transform", file)?;
+            let metadata = match &source.exif {
+                Some(exif) if self.encoding.keep_metadata => {
+                    kept_tags(exif).at("copy metadata of", file)?
+                }
+                _ => None,
+            };
+            let output = self.output(
+                inputs[file],
+                file,
+                extension(format),
+                Some(img.dimensions()),
+            );
+            // Two inputs named alike, or an output named like its input, would lose an image;
+            // `./photos/a.png` and `photos/a.png` are the same file
+            let resolved = resolve(&output);
+            if resolved == resolve(file) || !written.lock().unwrap().insert(resolved) {
+                return Err(ImagixError::UserInputError(format!(
+                    "{:?} would be overwritten, add {{stem}} or {{dir}} to the output of job `{}`",
+                    output, self.name
+                )));
+            }
+            let bytes = self
+                .encoding
+                .encode(&img, format, metadata)
+                .at("encode", &output)?;
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent).at("create folder", parent)?;
+            }
+            fs::write(&output, bytes).at("write", &output)?;
+            Ok(format!(
+                "Processed file: {:?} to size {}x{} in {}. Output file in {:?}",
+                file,
+                img.width(),
+                img.height(),
+                Elapsed::from(&timer),
+                output
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imagix::format::OutputFormat;
+    use image::{DynamicImage, ImageFormat};
+
+    #[test]
+    fn test_parse_jobs() {
+        let dir = std::env::temp_dir();
+        let text = r#"
+            [profiles.web]
+            steps = [{ resize = "400x" }, { convert = { format = "webp", quality = 80 } }]
+            output = "web/{dir}/{stem}-{width}w.{ext}"
+
+            [[jobs]]
+            name = "thumbs"
+            inputs = ["*.png"]
+            profile = "web"
+            steps = [{ ops = "grayscale" }, { metadata = "keep" }]
+        "#;
+        let jobs = parse_jobs(text, &dir).unwrap();
+        assert_eq!(2, jobs[0].pipeline.operations().len());
+        assert_eq!(OutputFormat::Webp, jobs[0].encoding.format);
+        assert!(jobs[0].encoding.keep_metadata);
+        assert_eq!(PathBuf::from("web"), jobs[0].output.root());
+
+        // Every mistake is reported, not only the first one
+        let text = r#"
+            [profiles.web]
+            steps = [{ resize = "huge" }]
+
+            [[jobs]]
+            name = "a"
+            inputs = ["*.png"]
+            profile = "mobile"
+            output = "{stem}.{ext}"
+
+            [[jobs]]
+            name = "b"
+            inputs = ["*.png"]
+            output = "{stem}-{size}.{ext}"
+        "#;
+        let error = parse_jobs(text, &dir).unwrap_err().to_string();
+        assert!(error.starts_with("3 errors"), "{}", error);
+        assert!(error.contains("profile `web`: step 1: Wrong operation `resize:huge`"));
+        assert!(error.contains("job `a`: no profile named `mobile`"));
+        assert!(error.contains("job `b`: Wrong placeholder `{size}`"));
+        assert!(parse_jobs("[[jobs]]\nname = 1", &dir).is_err());
+    }
+
+    #[test]
+    fn test_run_job() {
+        let root = std::env::temp_dir().join("imagix-job-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("photos/shoes")).unwrap();
+        for (path, width) in [("photos/a.png", 40), ("photos/shoes/b.png", 60)] {
+            DynamicImage::new_rgb8(width, 20)
+                .save_with_format(root.join(path), ImageFormat::Png)
+                .unwrap();
+        }
+        let text = r#"
+            [[jobs]]
+            name = "small"
+            inputs = ["photos/**/*.png"]
+            steps = [{ resize = "50%" }, { convert = { format = "jpeg" } }]
+            output = "out/{job}/{dir}/{stem}-{width}w.{ext}"
+        "#;
+        fs::write(root.join("jobs.toml"), text).unwrap();
+        let jobs = load_jobs(&root.join("jobs.toml")).unwrap();
+        let report = jobs[0].run(2).unwrap();
+        assert_eq!(2, report.succeeded);
+        assert!(root.join("out/small/a-20w.jpg").exists());
+        assert!(root.join("out/small/shoes/b-30w.jpg").exists());
+        // The outputs are not picked up again
+        assert_eq!(2, jobs[0].run(2).unwrap().succeeded);
+
+        // Outputs an input would match, or the input itself, are refused
+        for (input, output) in [
+            ("photos/*.png", "photos/{stem}-{width}w.{ext}"),
+            ("./photos/a.png", "photos/{stem}.{ext}"),
+        ] {
+            let text = format!(
+                "[[jobs]]\nname = \"in-place\"\ninputs = [\"{}\"]\noutput = \"{}\"",
+                input, output
+            );
+            let error = parse_jobs(&text, &root).unwrap_err().to_string();
+            let expected = format!("would be read back by input `{}`", input);
+            assert!(error.contains(&expected), "{}", error);
+        }
+        // Converted next to the inputs, which the glob leaves out
+        let text = r#"
+            [[jobs]]
+            name = "jpeg"
+            inputs = ["photos/*.png"]
+            steps = [{ convert = { format = "jpeg" } }]
+            output = "photos/{stem}.{ext}"
+        "#;
+        let jobs = parse_jobs(text, &root).unwrap();
+        assert_eq!(1, jobs[0].run(2).unwrap().succeeded);
+        assert_eq!(1, jobs[0].run(2).unwrap().succeeded);
+        assert!(root.join("photos/a.jpg").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
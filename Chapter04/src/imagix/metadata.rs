@@ -0,0 +1,186 @@
+use exif::experimental::Writer;
+use exif::{Context, Exif, Field, In, Reader, Tag};
+use image::metadata::Orientation;
+use std::fs;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use super::error::{Context as _, ImagixError};
+
+// Tags copied to the output with --keep-metadata: the camera, the date and where the photo was
+// taken. The orientation is not among them, as the pixels are turned upright instead.
+const CAMERA_TAGS: [Tag; 10] = [
+    Tag::Make,
+    Tag::Model,
+    Tag::LensMake,
+    Tag::LensModel,
+    Tag::ExposureTime,
+    Tag::FNumber,
+    Tag::PhotographicSensitivity,
+    Tag::FocalLength,
+    Tag::ExposureProgram,
+    Tag::Flash,
+];
+
+const DATE_TAGS: [Tag; 7] = [
+    Tag::DateTime,
+    Tag::DateTimeOriginal,
+    Tag::DateTimeDigitized,
+    Tag::OffsetTime,
+    Tag::OffsetTimeOriginal,
+    Tag::OffsetTimeDigitized,
+    Tag::SubSecTimeOriginal,
+];
+
+// EXIF data of a JPEG, PNG, TIFF or WebP file
+pub fn read_exif(path: &Path) -> Result<Exif, ImagixError> {
+    let file = fs::File::open(path).at("open", path)?;
+    Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .map_err(|e| {
+            ImagixError::FormatError(format!("No EXIF data in {:?}", path).into()).caused_by(e)
+        })
+}
+
+// How the image has to be turned to be seen upright
+pub fn orientation(exif: &Exif) -> Option<Orientation> {
+    let value = exif
+        .get_field(Tag::Orientation, In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    Orientation::from_exif(u8::try_from(value).ok()?)
+}
+
+// The tags kept with --keep-metadata, as a raw EXIF block, or None if there are none
+pub fn kept_tags(exif: &Exif) -> Result<Option<Vec<u8>>, ImagixError> {
+    let fields: Vec<&Field> = exif
+        .fields()
+        .filter(|f| f.ifd_num == In::PRIMARY)
+        .filter(|f| {
+            CAMERA_TAGS.contains(&f.tag)
+                || DATE_TAGS.contains(&f.tag)
+                || f.tag.context() == Context::Gps
+        })
+        .collect();
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut block = Cursor::new(Vec::new());
+    writer
+        .write(&mut block, exif.little_endian())
+        .map_err(|e| ImagixError::FormatError("Unable to write EXIF data".into()).caused_by(e))?;
+    Ok(Some(block.into_inner()))
+}
+
+// One line per tag, eg. `Model: "Pixel 7"`, for the exif subcommand
+pub fn describe(exif: &Exif) -> Vec<String> {
+    exif.fields()
+        .map(|f| {
+            let ifd = if f.ifd_num == In::PRIMARY {
+                String::new()
+            } else {
+                format!(" ({})", f.ifd_num)
+            };
+            format!("{}{}: {}", f.tag, ifd, f.display_value().with_unit(exif))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imagix::cache::Incremental;
+    use crate::imagix::files::Selection;
+    use crate::imagix::format::EncodeOptions;
+    use crate::imagix::pipeline::{process_transform_request, Pipeline};
+    use crate::imagix::resize::Mode;
+    use exif::{Rational, Value};
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{DynamicImage, ImageEncoder};
+
+    // A JPEG taken with the phone turned, as phones write them
+    fn sideways_jpeg(path: &Path) {
+        let fields = [
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+            Field {
+                tag: Tag::Model,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"Pixel 7".to_vec()]),
+            },
+            Field {
+                tag: Tag::XResolution,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational { num: 72, denom: 1 }]),
+            },
+            Field {
+                tag: Tag::GPSLatitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"N".to_vec()]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut block = Cursor::new(Vec::new());
+        writer.write(&mut block, false).unwrap();
+        let mut bytes = Vec::new();
+        let mut encoder = JpegEncoder::new(&mut bytes);
+        encoder.set_exif_metadata(block.into_inner()).unwrap();
+        DynamicImage::new_rgb8(8, 4)
+            .write_with_encoder(encoder)
+            .unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_exif_tags() {
+        let path = std::env::temp_dir().join("imagix-exif-test.jpg");
+        sideways_jpeg(&path);
+        let exif = read_exif(&path).unwrap();
+        assert_eq!(Some(Orientation::Rotate90), orientation(&exif));
+        assert!(describe(&exif).contains(&"Model: \"Pixel 7\"".to_string()));
+
+        let kept = Reader::new()
+            .read_raw(kept_tags(&exif).unwrap().unwrap())
+            .unwrap();
+        let tags: Vec<Tag> = kept.fields().map(|f| f.tag).collect();
+        assert_eq!(vec![Tag::Model, Tag::GPSLatitudeRef], tags);
+
+        // The output is upright and keeps the model but not the orientation
+        let out_dir = std::env::temp_dir().join("imagix-exif-test");
+        let encoding = EncodeOptions {
+            keep_metadata: true,
+            ..EncodeOptions::default()
+        };
+        let selection = Selection::new().out_dir(Some(out_dir.clone()));
+        let report = process_transform_request(
+            &Pipeline::new(),
+            &encoding,
+            Mode::Single,
+            &path,
+            &selection,
+            &Incremental::default(),
+            1,
+        )
+        .unwrap();
+        assert!(report.is_success());
+        let output = out_dir.join("imagix-exif-test.jpg");
+        assert_eq!((4, 8), image::image_dimensions(&output).unwrap());
+        let exif = read_exif(&output).unwrap();
+        assert_eq!(None, orientation(&exif));
+        assert!(exif.get_field(Tag::Model, In::PRIMARY).is_some());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_dir_all(&out_dir).unwrap();
+        assert!(read_exif(&path).is_err());
+    }
+}
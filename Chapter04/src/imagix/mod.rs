@@ -1,3 +1,15 @@
+pub mod batch;
+pub mod cache;
+pub mod compare;
+pub mod dedupe;
 pub mod error;
+pub mod files;
+pub mod format;
+pub mod job;
+pub mod metadata;
+pub mod pipeline;
 pub mod resize;
+pub mod sheet;
+pub mod srcset;
 pub mod stats;
+pub mod watermark;
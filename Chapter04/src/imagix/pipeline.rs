@@ -0,0 +1,554 @@
+use exif::Exif;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{fs, io};
+
+use super::batch::{run_batch, BatchReport, Planned};
+use super::cache::{parameters, Check, Entry, Incremental, Manifest, MANIFEST_FILE};
+use super::error::{Context, ImagixError};
+use super::files::{collect_inputs, sniff, Selection};
+use super::format::{extension, EncodeOptions};
+use super::metadata::{kept_tags, orientation, read_exif};
+use super::resize::{Background, Elapsed, Fit, Mode, ResizeOptions, Scale};
+use super::watermark::Watermark;
+
+// A step of a pipeline, written `name:arguments` in --ops
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Resize(ResizeOptions), // resize:200x150, resize:200x, resize:x150 or resize:50%, then :fit and :background
+    Rotate(u32),           // rotate:90, 180 或 270 度, 顺时针
+    FlipHorizontal,        // flip:h
+    FlipVertical,          // flip:v
+    // crop:x,y,width,height
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Grayscale,     // grayscale
+    Blur(f32),     // blur:sigma
+    Brighten(i32), // brighten:value, 负数变暗
+    Contrast(f32), // contrast:value, 负数降低对比度
+    // Not available in --ops, see the watermark subcommand
+    Watermark(Arc<Watermark>),
+}
+
+impl Operation {
+    pub fn apply(&self, img: DynamicImage) -> Result<DynamicImage, ImagixError> {
+        Ok(match *self {
+            Operation::Watermark(ref watermark) => watermark.apply(&img),
+            Operation::Resize(options) => options.apply(&img),
+            Operation::Rotate(90) => img.rotate90(),
+            Operation::Rotate(180) => img.rotate180(),
+            Operation::Rotate(270) => img.rotate270(),
+            Operation::Rotate(degrees) => {
+                return Err(ImagixError::UserInputError(format!(
+                    "Cannot rotate by {} degrees, only by 90, 180 or 270",
+                    degrees
+                )))
+            }
+            Operation::FlipHorizontal => img.fliph(),
+            Operation::FlipVertical => img.flipv(),
+            Operation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let (image_width, image_height) = img.dimensions();
+                if x as u64 + width as u64 > image_width as u64
+                    || y as u64 + height as u64 > image_height as u64
+                {
+                    return Err(ImagixError::ImageResizingError(
+                        format!(
+                            "Cannot crop {}x{} at {},{} from a {}x{} image",
+                            width, height, x, y, image_width, image_height
+                        )
+                        .into(),
+                    ));
+                }
+                img.crop_imm(x, y, width, height)
+            }
+            Operation::Grayscale => img.grayscale(),
+            Operation::Blur(sigma) => img.blur(sigma),
+            Operation::Brighten(value) => img.brighten(value),
+            Operation::Contrast(value) => img.adjust_contrast(value),
+        })
+    }
+
+    fn parse(name: &str, args: &[&str]) -> Result<Operation, ImagixError> {
+        let error = |expected: &str| {
+            ImagixError::UserInputError(format!(
+                "Wrong operation `{}`, expected {}",
+                if args.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}:{}", name, args.join(","))
+                },
+                expected
+            ))
+        };
+        let number = |expected: &str| -> Result<f32, ImagixError> {
+            match args {
+                [value] => value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or_else(|| error(expected)),
+                _ => Err(error(expected)),
+            }
+        };
+        match name {
+            "resize" => match args {
+                [spec] => parse_resize(spec).ok_or_else(|| error(RESIZE_SYNTAX)),
+                _ => Err(error(RESIZE_SYNTAX)),
+            },
+            "rotate" => match args {
+                ["90"] => Ok(Operation::Rotate(90)),
+                ["180"] => Ok(Operation::Rotate(180)),
+                ["270"] => Ok(Operation::Rotate(270)),
+                _ => Err(error("rotate:90, rotate:180 or rotate:270")),
+            },
+            "flip" => match args {
+                ["h"] => Ok(Operation::FlipHorizontal),
+                ["v"] => Ok(Operation::FlipVertical),
+                _ => Err(error("flip:h or flip:v")),
+            },
+            "crop" => {
+                let values: Vec<u32> = args.iter().filter_map(|a| a.parse().ok()).collect();
+                match values[..] {
+                    [x, y, width, height]
+                        if values.len() == args.len() && width > 0 && height > 0 =>
+                    {
+                        Ok(Operation::Crop {
+                            x,
+                            y,
+                            width,
+                            height,
+                        })
+                    }
+                    _ => Err(error("crop:x,y,width,height")),
+                }
+            }
+            "grayscale" if args.is_empty() => Ok(Operation::Grayscale),
+            "blur" => {
+                let sigma = number("blur:sigma with a positive sigma")?;
+                if sigma > 0.0 {
+                    Ok(Operation::Blur(sigma))
+                } else {
+                    Err(error("blur:sigma with a positive sigma"))
+                }
+            }
+            "brighten" => match args {
+                [value] => value
+                    .parse()
+                    .map(Operation::Brighten)
+                    .map_err(|_| error("brighten:value with a whole number")),
+                _ => Err(error("brighten:value with a whole number")),
+            },
+            "contrast" => Ok(Operation::Contrast(number("contrast:value")?)),
+            _ => Err(error(
+                "one of resize, rotate, flip, crop, grayscale, blur, brighten or contrast",
+            )),
+        }
+    }
+}
+
+const RESIZE_SYNTAX: &str =
+    "resize:WIDTHxHEIGHT or resize:PERCENT%, optionally followed by :fit and :background";
+
+// resize:200x150, resize:200x, resize:x150 or resize:50%, optionally followed by a fit and a
+// background, eg. resize:200x200:pad:#000000
+fn parse_resize(spec: &str) -> Option<Operation> {
+    let mut parts = spec.split(':');
+    let size = parts.next()?;
+    let fit: Fit = parts.next().unwrap_or("contain").parse().ok()?;
+    let background: Background = parts.next().unwrap_or("white").parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    let dimension = |s: &str| -> Option<Option<u32>> {
+        match s {
+            "" => Some(None),
+            _ => s.parse::<u32>().ok().filter(|d| *d > 0).map(Some),
+        }
+    };
+    let options = match size.split_once('x') {
+        Some((width, height)) => ResizeOptions::new(
+            None,
+            dimension(width)?,
+            dimension(height)?,
+            None,
+            fit,
+            background,
+        ),
+        None => ResizeOptions::new(
+            None,
+            None,
+            None,
+            Some(Scale::from_str(size).ok()?),
+            fit,
+            background,
+        ),
+    };
+    options.ok().map(Operation::Resize)
+}
+
+// Operations applied to an image one after the other
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    operations: Vec<Operation>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn then(mut self, operation: Operation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn apply(&self, img: DynamicImage) -> Result<DynamicImage, ImagixError> {
+        self.operations
+            .iter()
+            .try_fold(img, |img, operation| operation.apply(img))
+    }
+}
+
+// Operations separated by commas, eg. "rotate:90,crop:10,10,400,300,grayscale". Numbers that
+// follow an operation are more of its arguments, as in crop.
+impl FromStr for Pipeline {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps: Vec<(&str, Vec<&str>)> = Vec::new();
+        for token in s.split(',').map(str::trim) {
+            let continues = token.starts_with(|c: char| c.is_ascii_digit() || c == '-');
+            match steps.last_mut() {
+                Some((_, args)) if continues => args.push(token),
+                _ => match token.split_once(':') {
+                    Some((name, arg)) => steps.push((name, vec![arg])),
+                    None => steps.push((token, Vec::new())),
+                },
+            }
+        }
+        let pipeline = steps
+            .iter()
+            .map(|(name, args)| Operation::parse(name, args))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|operations| Pipeline { operations })?;
+        if pipeline.operations.is_empty() || s.trim().is_empty() {
+            return Err(ImagixError::UserInputError(
+                "Specify at least one operation".to_string(),
+            ));
+        }
+        Ok(pipeline)
+    }
+}
+
+// A decoded image turned upright, with the format and EXIF data of its file
+pub struct Source {
+    pub image: DynamicImage,
+    pub format: Option<ImageFormat>,
+    pub exif: Option<Exif>,
+}
+
+pub fn open_upright(path: &Path) -> Result<Source, ImagixError> {
+    // The format is told from the content of the file, not its extension
+    let reader = ImageReader::open(path)
+        .at("open", path)?
+        .with_guessed_format()
+        .at("read", path)?;
+    let format = reader.format();
+    let exif = read_exif(path).ok();
+    let mut image = reader.decode().at("decode", path)?;
+    // Phones store photos as taken and tell in EXIF how to turn them upright
+    if let Some(orientation) = exif.as_ref().and_then(orientation) {
+        image.apply_orientation(orientation);
+    }
+    Ok(Source {
+        image,
+        format,
+        exif,
+    })
+}
+
+pub fn process_transform_request(
+    pipeline: &Pipeline,
+    encoding: &EncodeOptions,
+    mode: Mode,
+    src_folder: &Path,
+    selection: &Selection,
+    incremental: &Incremental,
+    jobs: usize,
+) -> Result<BatchReport, ImagixError> {
+    let (listing, layout) = collect_inputs(mode, src_folder, selection)?;
+    // Outputs are named up front so that two inputs never write to the same file
+    let planned: Vec<(PathBuf, &str)> = listing
+        .images
+        .iter()
+        .filter_map(|file| {
+            let format = encoding.target_format(sniff(file)).ok()?;
+            Some((file.clone(), extension(format)))
+        })
+        .collect();
+    let outputs = layout.plan(&planned);
+
+    // The manifest of earlier runs tells which outputs are up to date
+    let manifest_path = layout.out_root().join(MANIFEST_FILE);
+    let mut manifest = Manifest::load(&manifest_path);
+    let parameters = parameters(&format!("{:?} {:?}", pipeline, encoding));
+    let mut removed = Vec::new();
+    for orphan in manifest.orphans() {
+        // A file the manifest lists outside the output folders is left alone
+        if !layout.owns(&orphan.output) {
+            manifest.remove(&orphan.output);
+            continue;
+        }
+        if incremental.dry_run {
+            if orphan.output.exists() {
+                removed.push(orphan.output);
+            }
+            continue;
+        }
+        match fs::remove_file(&orphan.output) {
+            Ok(()) => removed.push(orphan.output.clone()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        manifest.remove(&orphan.output);
+    }
+    let mut stale = Vec::new();
+    let mut up_to_date = 0;
+    for file in &listing.images {
+        let check = match outputs.get(file) {
+            Some(_) if incremental.force => Check::Stale("forced"),
+            Some(output) => manifest.check(file, output, &parameters)?,
+            None => Check::Stale("new"),
+        };
+        match check {
+            Check::UpToDate => up_to_date += 1,
+            Check::Stale(reason) => stale.push((file.clone(), reason)),
+        }
+    }
+    if incremental.dry_run {
+        let planned = stale
+            .into_iter()
+            .map(|(file, reason)| Planned {
+                output: outputs.get(&file).cloned(),
+                input: file,
+                reason: reason.to_string(),
+            })
+            .collect();
+        return Ok(BatchReport {
+            skipped: listing.skipped.len(),
+            up_to_date,
+            dry_run: true,
+            planned,
+            removed,
+            ..BatchReport::default()
+        });
+    }
+
+    let files: Vec<PathBuf> = stale.into_iter().map(|(file, _)| file).collect();
+    let entries = Mutex::new(Vec::new());
+    let mut report = run_batch(&files, listing.skipped.len(), jobs, |file| {
+        let message = transform_image(pipeline, encoding, file, outputs.get(file))?;
+        if let Some(output) = outputs.get(file) {
+            let entry = Entry::new(file, output, &parameters)?;
+            entries.lock().unwrap().push(entry);
+        }
+        Ok(message)
+    })?;
+    report.up_to_date = up_to_date;
+    report.removed = removed;
+    // Failed inputs are tried again on the next run
+    for (file, _) in &report.failed {
+        if let Some(output) = outputs.get(file) {
+            manifest.remove(output);
+        }
+    }
+    for entry in entries.into_inner().unwrap() {
+        manifest.insert(entry);
+    }
+    if !manifest.is_empty() || manifest_path.exists() {
+        manifest.save(&manifest_path)?;
+    }
+    Ok(report)
+}
+
+fn transform_image(
+    pipeline: &Pipeline,
+    encoding: &EncodeOptions,
+    src_folder: &Path,
+    dest_folder: Option<&PathBuf>,
+) -> Result<String, ImagixError> {
+    let format = encoding
+        .target_format(sniff(src_folder))
+        .at("convert", src_folder)?;
+    let dest_folder = dest_folder.ok_or_else(|| {
+        ImagixError::UserInputError(format!("No output file for {:?}", src_folder))
+    })?;
+
+    // Create the destination folder if it does not exist
+    if let Some(parent) = dest_folder.parent() {
+        fs::create_dir_all(parent).at("create folder", parent)?;
+    }
+
+    // Open source image file, run the pipeline on it and write output to destination-folder/destination-file
+    let timer = Instant::now();
+    let source = open_upright(src_folder)?;
+    let img = pipeline.apply(source.image).at("transform", src_folder)?;
+    let metadata = match &source.exif {
+        Some(exif) if encoding.keep_metadata => {
+            kept_tags(exif).at("copy metadata of", src_folder)?
+        }
+        _ => None,
+    };
+    let bytes = encoding
+        .encode(&img, format, metadata)
+        .at("encode", dest_folder)?;
+    fs::write(dest_folder, bytes).at("write", dest_folder)?;
+    Ok(format!(
+        "Processed file: {:?} to size {}x{} in {}. Output file in {:?}",
+        src_folder,
+        img.width(),
+        img.height(),
+        Elapsed::from(&timer),
+        dest_folder
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imagix::files::resolve;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_pipeline_parse_and_apply() {
+        let pipeline: Pipeline =
+            "rotate:90, flip:h,crop:10,10,40,30,grayscale,blur:1.5,brighten:-10,contrast:5,resize:50%"
+                .parse()
+                .unwrap();
+        assert_eq!(8, pipeline.operations().len());
+        assert_eq!(
+            Operation::Crop {
+                x: 10,
+                y: 10,
+                width: 40,
+                height: 30
+            },
+            pipeline.operations()[2]
+        );
+        let img = DynamicImage::new_rgb8(60, 100);
+        assert_eq!((20, 15), pipeline.apply(img).unwrap().dimensions());
+
+        // Operations run in order: the pixel at the top left ends up at the top right
+        let mut img = RgbaImage::new(4, 2);
+        img.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let flipped = "flip:h".parse::<Pipeline>().unwrap();
+        let img = flipped.apply(DynamicImage::ImageRgba8(img)).unwrap();
+        assert_eq!(Rgba([255, 0, 0, 255]), img.get_pixel(3, 0));
+        let resize: Pipeline = "resize:x10:pad:#000".parse().unwrap();
+        assert_eq!(
+            (10, 10),
+            resize
+                .apply(DynamicImage::new_rgb8(20, 20))
+                .unwrap()
+                .dimensions()
+        );
+    }
+    #[test]
+    fn test_invalid_pipeline() {
+        for ops in [
+            "",
+            "rotate:45",
+            "flip:x",
+            "crop:1,2,3",
+            "crop:0,0,0,5",
+            "blur:0",
+            "brighten:1.5",
+            "sharpen",
+            "resize:0x10",
+            "resize:10x10:fill",
+        ] {
+            assert!(
+                matches!(ops.parse::<Pipeline>(), Err(ImagixError::UserInputError(_))),
+                "{}",
+                ops
+            );
+        }
+        let crop: Pipeline = "crop:50,0,20,20".parse().unwrap();
+        assert!(crop.apply(DynamicImage::new_rgb8(60, 60)).is_err());
+    }
+    #[test]
+    fn test_incremental_runs() {
+        let root = std::env::temp_dir().join("imagix-pipeline-test");
+        let _ = fs::remove_dir_all(&root);
+        let src = root.join("photos");
+        fs::create_dir_all(root.join("tmp")).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        for name in ["a.png", "b.png"] {
+            DynamicImage::new_rgb8(8, 8).save(src.join(name)).unwrap();
+        }
+        let run = |dry_run: bool| {
+            let incremental = Incremental {
+                dry_run,
+                ..Incremental::default()
+            };
+            let pipeline: Pipeline = "flip:h".parse().unwrap();
+            let encoding = EncodeOptions::default();
+            let selection = Selection::new();
+            process_transform_request(
+                &pipeline,
+                &encoding,
+                Mode::All,
+                &src,
+                &selection,
+                &incremental,
+                1,
+            )
+            .unwrap()
+        };
+        assert_eq!(2, run(false).succeeded);
+        assert_eq!(2, run(true).up_to_date);
+
+        // An entry naming a file outside the output folders, as a manifest written by hand could
+        let stray = root.join("tmp/a.png");
+        fs::write(&stray, b"not an output").unwrap();
+        let manifest_path = src.join("tmp").join(MANIFEST_FILE);
+        let mut manifest = Manifest::load(&manifest_path);
+        fs::write(root.join("gone.png"), b"").unwrap();
+        manifest.insert(Entry::new(&root.join("gone.png"), &stray, "p").unwrap());
+        manifest.save(&manifest_path).unwrap();
+        fs::remove_file(root.join("gone.png")).unwrap();
+
+        fs::remove_file(src.join("b.png")).unwrap();
+        fs::write(src.join("c.png"), fs::read(src.join("a.png")).unwrap()).unwrap();
+        let report = run(true);
+        assert!(report.dry_run);
+        let orphan = resolve(&src.join("tmp/b.png"));
+        assert_eq!(vec![orphan.clone()], report.removed);
+        let planned: Vec<&str> = report.planned.iter().map(|p| p.reason.as_str()).collect();
+        assert_eq!(vec!["new"], planned);
+        assert!(orphan.exists());
+
+        let report = run(false);
+        assert_eq!(vec![orphan.clone()], report.removed);
+        assert_eq!(1, report.succeeded);
+        assert!(!orphan.exists());
+        assert!(stray.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,11 +1,17 @@
-use image::ImageFormat;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::result::Result;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
-use std::{fmt, fs, io};
 
+use super::batch::BatchReport;
+use super::cache::Incremental;
 use super::error::ImagixError;
+use super::files::Selection;
+use super::format::EncodeOptions;
+use super::pipeline::{process_transform_request, Operation, Pipeline};
 
 #[derive(Debug)]
 pub enum SizeOption {
@@ -22,12 +28,246 @@ impl FromStr for SizeOption {
             "small" => Ok(SizeOption::Small),
             "medium" => Ok(SizeOption::Medium),
             "large" => Ok(SizeOption::Large),
-            _ => Ok(SizeOption::Small),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for size `{}`, expected small, medium or large",
+                s
+            ))),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl SizeOption {
+    // The box a preset fits the image into
+    fn pixels(&self) -> u32 {
+        match self {
+            SizeOption::Small => 200,
+            SizeOption::Medium => 400,
+            SizeOption::Large => 800,
+        }
+    }
+}
+
+// How the image is made to fit the requested width and height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+    Contain, // 保持比例, 完整放入
+    Cover,   // 保持比例, 填满后居中裁剪
+    Exact,   // 拉伸到指定尺寸
+    Pad,     // 保持比例, 用背景色补边
+}
+
+impl FromStr for Fit {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "contain" => Ok(Fit::Contain),
+            "cover" => Ok(Fit::Cover),
+            "exact" => Ok(Fit::Exact),
+            "pad" => Ok(Fit::Pad),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for fit `{}`, expected contain, cover, exact or pad",
+                s
+            ))),
+        }
+    }
+}
+
+// A scale factor given as a percentage, eg. 50%, or a fraction, eg. 0.5
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale(pub f64);
+
+impl FromStr for Scale {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let factor = match s.trim().strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+            None => s.trim().parse::<f64>(),
+        };
+        match factor {
+            Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(Scale(factor)),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for scale `{}`, expected a positive percentage such as 50% or a factor such as 0.5",
+                s
+            ))),
+        }
+    }
+}
+
+// Background color for the pad fit: #rgb, #rrggbb, #rrggbbaa, white, black or transparent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Background(pub Rgba<u8>);
+
+impl FromStr for Background {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            ImagixError::UserInputError(format!(
+                "Wrong value for background `{}`, expected a color such as #ffffff, white, black or transparent",
+                s
+            ))
+        };
+        match s {
+            "white" => return Ok(Background(Rgba([255, 255, 255, 255]))),
+            "black" => return Ok(Background(Rgba([0, 0, 0, 255]))),
+            "transparent" => return Ok(Background(Rgba([0, 0, 0, 0]))),
+            _ => {}
+        }
+        let hex = s.strip_prefix('#').ok_or_else(error)?;
+        // #rgb is short for #rrggbb
+        let hex: String = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 | 8 => hex.to_string(),
+            _ => return Err(error()),
+        };
+        let mut channels = [255u8; 4];
+        for (i, channel) in channels.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = hex
+                .get(i * 2..i * 2 + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+                .ok_or_else(error)?;
+        }
+        Ok(Background(Rgba(channels)))
+    }
+}
+
+// Reads --width and --height, which must be positive numbers of pixels
+pub fn parse_dimension(s: &str) -> Result<u32, ImagixError> {
+    match s.parse::<u32>() {
+        Ok(pixels) if pixels > 0 => Ok(pixels),
+        _ => Err(ImagixError::UserInputError(format!(
+            "Wrong value for dimension `{}`, expected a positive number of pixels",
+            s
+        ))),
+    }
+}
+
+// The target size of the resized image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    // Either dimension may be left out, it then follows from the aspect ratio
+    Box {
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    Scale(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeOptions {
+    pub target: Target,
+    pub fit: Fit,
+    pub background: Rgba<u8>,
+}
+
+impl ResizeOptions {
+    // Checks that exactly one way of giving the size is used: a preset, a width and/or height, or a scale
+    pub fn new(
+        size: Option<SizeOption>,
+        width: Option<u32>,
+        height: Option<u32>,
+        scale: Option<Scale>,
+        fit: Fit,
+        background: Background,
+    ) -> Result<ResizeOptions, ImagixError> {
+        let dimensions = width.is_some() || height.is_some();
+        let target = match (size, dimensions, scale) {
+            (Some(size), false, None) => Target::Box {
+                width: Some(size.pixels()),
+                height: Some(size.pixels()),
+            },
+            (None, true, None) => Target::Box { width, height },
+            (None, false, Some(Scale(factor))) => Target::Scale(factor),
+            (None, false, None) => {
+                return Err(ImagixError::UserInputError(
+                    "Specify the size with --size, --width and/or --height, or --scale".to_string(),
+                ))
+            }
+            _ => {
+                return Err(ImagixError::UserInputError(
+                    "Use only one of --size, --width/--height and --scale".to_string(),
+                ))
+            }
+        };
+        if width == Some(0) || height == Some(0) {
+            return Err(ImagixError::UserInputError(
+                "Width and height must be positive".to_string(),
+            ));
+        }
+        Ok(ResizeOptions {
+            target,
+            fit,
+            background: background.0,
+        })
+    }
+
+    // The size of the output for an image of the given size
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (box_width, box_height) = self.box_size(width, height);
+        match self.fit {
+            Fit::Contain => fit_within(width, height, box_width, box_height),
+            Fit::Cover | Fit::Exact | Fit::Pad => (box_width, box_height),
+        }
+    }
+
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let (box_width, box_height) = self.box_size(width, height);
+        match self.fit {
+            Fit::Contain => img.thumbnail(box_width, box_height),
+            Fit::Cover => img.resize_to_fill(box_width, box_height, FilterType::Lanczos3),
+            Fit::Exact => img.resize_exact(box_width, box_height, FilterType::Lanczos3),
+            Fit::Pad => {
+                let scaled = img.thumbnail(box_width, box_height);
+                let mut canvas = RgbaImage::from_pixel(box_width, box_height, self.background);
+                let x = (box_width - scaled.width()) / 2;
+                let y = (box_height - scaled.height()) / 2;
+                imageops::overlay(&mut canvas, &scaled.to_rgba8(), x as i64, y as i64);
+                DynamicImage::ImageRgba8(canvas)
+            }
+        }
+    }
+
+    // The box the image is fitted to; a missing dimension keeps the aspect ratio
+    fn box_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scaled = |size: u32, factor: f64| ((size as f64 * factor).round() as u32).max(1);
+        match self.target {
+            Target::Scale(factor) => (scaled(width, factor), scaled(height, factor)),
+            Target::Box {
+                width: Some(w),
+                height: Some(h),
+            } => (w, h),
+            Target::Box {
+                width: Some(w),
+                height: None,
+            } => (w, scaled(height, w as f64 / width as f64)),
+            Target::Box {
+                width: None,
+                height: Some(h),
+            } => (scaled(width, h as f64 / height as f64), h),
+            Target::Box {
+                width: None,
+                height: None,
+            } => (width, height),
+        }
+    }
+}
+
+// The largest size with the image's aspect ratio that fits in the box
+fn fit_within(width: u32, height: u32, box_width: u32, box_height: u32) -> (u32, u32) {
+    let ratio = f64::min(
+        box_width as f64 / width as f64,
+        box_height as f64 / height as f64,
+    );
+    (
+        ((width as f64 * ratio).round() as u32).clamp(1, box_width),
+        ((height as f64 * ratio).round() as u32).clamp(1, box_height),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Single,
     All,
@@ -48,26 +288,30 @@ impl FromStr for Mode {
 }
 
 pub fn process_resize_request(
-    size: SizeOption,
+    options: &ResizeOptions,
+    encoding: &EncodeOptions,
     mode: Mode,
-    src_folder: &mut PathBuf,
-) -> Result<(), ImagixError> {
-    let size = match size {
-        SizeOption::Small => 200,
-        SizeOption::Medium => 400,
-        SizeOption::Large => 800,
-    };
-    let _ = match mode {
-        Mode::All => resize_all(size, src_folder)?,
-        Mode::Single => resize_single(size, src_folder)?,
-    };
-    Ok(())
-}
-
-struct Elapsed(Duration);
+    src_folder: &Path,
+    selection: &Selection,
+    incremental: &Incremental,
+    jobs: usize,
+) -> Result<BatchReport, ImagixError> {
+    let pipeline = Pipeline::new().then(Operation::Resize(*options));
+    process_transform_request(
+        &pipeline,
+        encoding,
+        mode,
+        src_folder,
+        selection,
+        incremental,
+        jobs,
+    )
+}
+
+pub(crate) struct Elapsed(pub(crate) Duration);
 
 impl Elapsed {
-    fn from(start: &Instant) -> Self {
+    pub(crate) fn from(start: &Instant) -> Self {
         Elapsed(start.elapsed())
     }
 }
@@ -76,104 +320,162 @@ impl fmt::Display for Elapsed {
     fn fmt(&self, out: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match (self.0.as_secs(), self.0.subsec_nanos()) {
             (0, n) if n < 1000 => write!(out, "{} ns", n),
-            (0, n) if n < 1000_000 => write!(out, "{} µs", n / 1000),
-            (0, n) => write!(out, "{} ms", n / 1000_000),
+            (0, n) if n < 1_000_000 => write!(out, "{} µs", n / 1000),
+            (0, n) => write!(out, "{} ms", n / 1_000_000),
             (s, n) if s < 10 => write!(out, "{}.{:02} s", s, n / 10_000_000),
             (s, _) => write!(out, "{} s", s),
         }
     }
 }
 
-fn resize_single(size: u32, src_folder: &PathBuf) -> Result<(), ImagixError> {
-    let src_folder = src_folder;
-    // Get file stem from src_folder
-    resize_image(size, &src_folder)?;
-    Ok(())
-}
-
-fn resize_all(size: u32, src_folder: &PathBuf) -> Result<(), ImagixError> {
-    if let Ok(entries) = get_image_files(src_folder.to_path_buf()) {
-        for entry in entries {
-            resize_image(size, &entry)?;
-        }
-    };
-    Ok(())
-}
-
-fn resize_image(size: u32, src_folder: &PathBuf) -> Result<(), ImagixError> {
-    // Construct destination file name th .png extension
-    let new_file_name = src_folder
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .ok_or(std::io::ErrorKind::InvalidInput)
-        .map(|f| format!("{}.png", f));
-
-    // Construct path to destination folder i.e. create /tmp under source folder if not exists
-    let mut dest_folder = src_folder.clone();
-    dest_folder.pop();
-    dest_folder.push("tmp/");
-    if !dest_folder.exists() {
-        fs::create_dir(&dest_folder)?;
-    }
-    dest_folder.pop();
-    dest_folder.push("tmp/tmp.png");
-    dest_folder.set_file_name(new_file_name?.as_str());
-
-    // Open source image file, scale it to desired size and write output to destination-folder/destination-file
-    let timer = Instant::now();
-    let img = image::open(&src_folder)?;
-    let scaled = img.thumbnail(size, size);
-    let mut output = fs::File::create(&dest_folder)?;
-    scaled.write_to(&mut output, ImageFormat::Png)?;
-    println!(
-        "Thumbnailed file: {:?} to size {}x{} in {}. Output file in {:?}",
-        src_folder,
-        size,
-        size,
-        Elapsed::from(&timer),
-        dest_folder
-    );
-    Ok(())
-}
-
-// The program supports only files of type jpg/JPG and png/PNG.
+// Images directly in the folder, whatever their extension
 pub fn get_image_files<P: AsRef<Path>>(src_folder: P) -> Result<Vec<PathBuf>, ImagixError> {
-    let entries = fs::read_dir(src_folder)
-        .map_err(|_e| ImagixError::UserInputError("Invalid source folder".to_string()))?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?
-        .into_iter()
-        .filter(|r| {
-            r.extension() == Some("JPG".as_ref())
-                || r.extension() == Some("jpg".as_ref())
-                || r.extension() == Some("PNG".as_ref())
-                || r.extension() == Some("png".as_ref())
-        })
-        .collect();
-    Ok(entries)
+    Ok(Selection::new().collect(src_folder.as_ref())?.images)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::imagix::format::OutputFormat;
+    use image::ImageFormat;
+    use std::fs;
+
+    fn small() -> ResizeOptions {
+        ResizeOptions::new(
+            Some(SizeOption::Small),
+            None,
+            None,
+            None,
+            Fit::Contain,
+            Background(Rgba([255, 255, 255, 255])),
+        )
+        .unwrap()
+    }
+
+    fn png() -> EncodeOptions {
+        EncodeOptions {
+            format: OutputFormat::Png,
+            ..EncodeOptions::default()
+        }
+    }
+
+    fn options(width: Option<u32>, height: Option<u32>, fit: Fit) -> ResizeOptions {
+        ResizeOptions::new(None, width, height, None, fit, "#00ff0080".parse().unwrap()).unwrap()
+    }
+
     #[test]
     fn test_single_image_resize() {
-        let mut path = PathBuf::from("/tmp/images/image1.jpg");
+        let path = PathBuf::from("/tmp/images/image1.jpg");
         let destination_path = PathBuf::from("/tmp/images/tmp/image1.png");
-        match process_resize_request(SizeOption::Small, Mode::Single, &mut path) {
+        match process_resize_request(
+            &small(),
+            &png(),
+            Mode::Single,
+            &path,
+            &Selection::new(),
+            &Incremental::default(),
+            1,
+        ) {
             Ok(_) => println!("Successful resize of single image"),
             Err(e) => println!("Error in single image: {:?}", e),
         }
-        assert_eq!(true, destination_path.exists());
+        assert!(destination_path.exists());
     }
     #[test]
     fn test_multiple_image_resize() {
-        let mut path = PathBuf::from("/tmp/images/");
-        let _res = process_resize_request(SizeOption::Small, Mode::All, &mut path);
+        let path = PathBuf::from("/tmp/images/");
+        let _res = process_resize_request(
+            &small(),
+            &png(),
+            Mode::All,
+            &path,
+            &Selection::new(),
+            &Incremental::default(),
+            2,
+        );
         let destination_path1 = PathBuf::from("/tmp/images/tmp/image1.png");
         let destination_path2 = PathBuf::from("/tmp/images/tmp/image2.png");
-        assert_eq!(true, destination_path1.exists());
-        assert_eq!(true, destination_path2.exists());
+        assert!(destination_path1.exists());
+        assert!(destination_path2.exists());
+    }
+    #[test]
+    fn test_keep_input_format() {
+        let root = std::env::temp_dir().join("imagix-keep-format-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("photo.jpg");
+        DynamicImage::new_rgb8(40, 30).save(&path).unwrap();
+        let report = process_resize_request(
+            &small(),
+            &EncodeOptions::default(),
+            Mode::Single,
+            &path,
+            &Selection::new(),
+            &Incremental::default(),
+            1,
+        )
+        .unwrap();
+        assert!(report.is_success());
+        let bytes = fs::read(root.join("tmp/photo.jpg")).unwrap();
+        assert_eq!(ImageFormat::Jpeg, image::guess_format(&bytes).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn test_fit_modes() {
+        let img = DynamicImage::new_rgb8(400, 200);
+        let sizes: Vec<(u32, u32)> = [Fit::Contain, Fit::Cover, Fit::Exact, Fit::Pad]
+            .iter()
+            .map(|fit| options(Some(100), Some(100), *fit).apply(&img).dimensions())
+            .collect();
+        assert_eq!(vec![(100, 50), (100, 100), (100, 100), (100, 100)], sizes);
+        let padded = options(Some(100), Some(100), Fit::Pad)
+            .apply(&img)
+            .to_rgba8();
+        assert_eq!(&Rgba([0, 255, 0, 128]), padded.get_pixel(50, 10));
+        assert_eq!(&Rgba([0, 0, 0, 255]), padded.get_pixel(50, 50));
+        assert_eq!(
+            (50, 25),
+            options(Some(50), None, Fit::Cover).output_size(400, 200)
+        );
+        let half = ResizeOptions::new(
+            None,
+            None,
+            None,
+            Some("50%".parse().unwrap()),
+            Fit::Contain,
+            Background(Rgba([0, 0, 0, 0])),
+        )
+        .unwrap();
+        assert_eq!((200, 100), half.apply(&img).dimensions());
+    }
+    #[test]
+    fn test_invalid_size_input() {
+        assert!(matches!(
+            "huge".parse::<SizeOption>(),
+            Err(ImagixError::UserInputError(_))
+        ));
+        assert!(matches!(
+            "-5%".parse::<Scale>(),
+            Err(ImagixError::UserInputError(_))
+        ));
+        assert!(matches!(
+            parse_dimension("0"),
+            Err(ImagixError::UserInputError(_))
+        ));
+        assert!(matches!(
+            "#12345".parse::<Background>(),
+            Err(ImagixError::UserInputError(_))
+        ));
+        assert!(matches!(
+            ResizeOptions::new(
+                Some(SizeOption::Small),
+                Some(100),
+                None,
+                None,
+                Fit::Contain,
+                Background(Rgba([0, 0, 0, 0]))
+            ),
+            Err(ImagixError::UserInputError(_))
+        ));
     }
 }
@@ -0,0 +1,510 @@
+use image::imageops;
+use image::{DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use super::batch::{run_batch, BatchReport};
+use super::error::{Context, ImagixError};
+use super::format::EncodeOptions;
+use super::pipeline::open_upright;
+use super::resize::{get_image_files, Fit, ResizeOptions, Target};
+use super::watermark::{draw_text, load_font};
+
+// Fonts tried for the labels when --font is not given
+const DEFAULT_FONTS: [&str; 4] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+// Height of the labels under the tiles, in pixels
+const LABEL_SIZE: f32 = 14.0;
+
+#[derive(Debug, Clone)]
+pub struct MontageOptions {
+    pub cols: u32,
+    // Side of the square each image is fitted in
+    pub tile: u32,
+    // Between the tiles and around them
+    pub spacing: u32,
+    pub labels: bool,
+    pub font: Option<PathBuf>,
+    pub background: Rgba<u8>,
+}
+
+// Which coordinate maps are written next to the sprite sheet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpriteMap {
+    Css,
+    Json,
+    Both, // css 和 json
+}
+
+impl FromStr for SpriteMap {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "css" => Ok(SpriteMap::Css),
+            "json" => Ok(SpriteMap::Json),
+            "both" => Ok(SpriteMap::Both),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for map `{}`, expected css, json or both",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpriteOptions {
+    // Icons larger than this are scaled down to fit in a square of this side
+    pub max_size: Option<u32>,
+    // Transparent pixels between icons, so that neighbours do not bleed in when scaled
+    pub padding: u32,
+    // Of the CSS classes, eg. icon for .icon-home
+    pub prefix: String,
+    pub map: SpriteMap,
+}
+
+// Where an icon is on the sprite sheet
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sprite {
+    pub name: String,
+    pub file: PathBuf,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct SpriteSheet<'a> {
+    image: String,
+    width: u32,
+    height: u32,
+    sprites: &'a [Sprite],
+}
+
+#[derive(Debug)]
+pub struct SheetReport {
+    // The sheet and its maps
+    pub written: Vec<PathBuf>,
+    // Loading of the images
+    pub batch: BatchReport,
+}
+
+// The images of the folder, in the order of their names, scaled with `thumbnail`
+fn load_images<F>(
+    src_folder: &Path,
+    jobs: usize,
+    thumbnail: F,
+) -> Result<(Vec<(PathBuf, DynamicImage)>, BatchReport), ImagixError>
+where
+    F: Fn(DynamicImage) -> DynamicImage + Sync,
+{
+    let files = get_image_files(src_folder)?;
+    let images = Mutex::new(Vec::new());
+    let batch = run_batch(&files, 0, jobs, |file| {
+        let image = thumbnail(open_upright(file)?.image);
+        let message = format!("Added {:?}", file);
+        images.lock().unwrap().push((file.to_path_buf(), image));
+        Ok(message)
+    })?;
+    let mut images = images.into_inner().unwrap();
+    if images.is_empty() {
+        return Err(ImagixError::UserInputError(format!(
+            "No images to put on a sheet in {:?}",
+            src_folder
+        )));
+    }
+    images.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok((images, batch))
+}
+
+// Fits the image in a box, as the resize subcommand does, without enlarging it
+fn thumbnail(image: DynamicImage, size: u32) -> DynamicImage {
+    if image.width() <= size && image.height() <= size {
+        return image;
+    }
+    ResizeOptions {
+        target: Target::Box {
+            width: Some(size),
+            height: Some(size),
+        },
+        fit: Fit::Contain,
+        background: Rgba([0, 0, 0, 0]),
+    }
+    .apply(&image)
+}
+
+// Encodes the sheet in the format told by the extension of `output`
+fn save(sheet: RgbaImage, output: &Path) -> Result<(), ImagixError> {
+    let format = ImageFormat::from_path(output).map_err(|_| {
+        ImagixError::UserInputError(format!(
+            "Unable to tell the format of {:?} from its extension",
+            output
+        ))
+    })?;
+    let bytes = EncodeOptions::default()
+        .encode(&DynamicImage::ImageRgba8(sheet), format, None)
+        .at("encode", output)?;
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir).at("create folder", dir)?;
+    }
+    fs::write(output, bytes).at("write", output)?;
+    Ok(())
+}
+
+// A grid of the images of the folder, `cols` wide, with their file names under them
+pub fn process_montage_request(
+    options: &MontageOptions,
+    src_folder: &Path,
+    output: &Path,
+    jobs: usize,
+) -> Result<SheetReport, ImagixError> {
+    if options.cols == 0 || options.tile == 0 {
+        return Err(ImagixError::UserInputError(
+            "Columns and tile size must be positive".to_string(),
+        ));
+    }
+    let font = match (&options.font, options.labels) {
+        (_, false) => None,
+        (Some(font), true) => Some(load_font(font)?),
+        (None, true) => {
+            let font = DEFAULT_FONTS
+                .iter()
+                .map(Path::new)
+                .find(|f| f.exists())
+                .ok_or_else(|| {
+                    ImagixError::UserInputError(
+                        "No default font found, specify the font of the labels with --font"
+                            .to_string(),
+                    )
+                })?;
+            Some(load_font(font)?)
+        }
+    };
+    let (images, batch) = load_images(src_folder, jobs, |image| thumbnail(image, options.tile))?;
+
+    // Dark labels on a light background, light labels on a dark one
+    let [r, g, b, _] = options.background.0;
+    let light = r as u32 * 299 + g as u32 * 587 + b as u32 * 114 > 128_000;
+    let color = if light {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    };
+    let labels: Vec<RgbaImage> = match &font {
+        Some(font) => images
+            .iter()
+            .map(|(file, _)| {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                label(&name, font, options.tile, color)
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let label_height = labels.iter().map(|l| l.height()).max().unwrap_or(0);
+
+    let cols = options.cols.min(images.len() as u32);
+    let rows = (images.len() as u32).div_ceil(cols);
+    let cell_width = options.tile + options.spacing;
+    let cell_height = options.tile + label_height + options.spacing;
+    let mut sheet = RgbaImage::from_pixel(
+        cols * cell_width + options.spacing,
+        rows * cell_height + options.spacing,
+        options.background,
+    );
+    for (i, (_, image)) in images.iter().enumerate() {
+        let (col, row) = (i as u32 % cols, i as u32 / cols);
+        let x = options.spacing + col * cell_width;
+        let y = options.spacing + row * cell_height;
+        // Centered in the tile
+        imageops::overlay(
+            &mut sheet,
+            &image.to_rgba8(),
+            (x + (options.tile - image.width()) / 2) as i64,
+            (y + (options.tile - image.height()) / 2) as i64,
+        );
+        if let Some(label) = labels.get(i) {
+            imageops::overlay(
+                &mut sheet,
+                label,
+                (x + (options.tile - label.width()) / 2) as i64,
+                (y + options.tile) as i64,
+            );
+        }
+    }
+    save(sheet, output)?;
+    Ok(SheetReport {
+        written: vec![output.to_path_buf()],
+        batch,
+    })
+}
+
+// The name drawn at most `width` pixels wide, shortened with ... if needed
+fn label(name: &str, font: &ab_glyph::FontVec, width: u32, color: Rgba<u8>) -> RgbaImage {
+    let mut text = name.to_string();
+    let mut drawn = draw_text(&text, font, LABEL_SIZE, color);
+    let mut chars: Vec<char> = name.chars().collect();
+    while drawn.width() > width && !chars.is_empty() {
+        chars.pop();
+        text = chars.iter().collect::<String>() + "...";
+        drawn = draw_text(&text, font, LABEL_SIZE, color);
+    }
+    if drawn.width() > width {
+        drawn = imageops::crop_imm(&drawn, 0, 0, width, drawn.height()).to_image();
+    }
+    drawn
+}
+
+// Packs the icons of the folder into one image, with a CSS and/or JSON map of where each one is
+pub fn process_sprite_request(
+    options: &SpriteOptions,
+    src_folder: &Path,
+    output: &Path,
+    jobs: usize,
+) -> Result<SheetReport, ImagixError> {
+    let (images, batch) = load_images(src_folder, jobs, |image| match options.max_size {
+        Some(size) => thumbnail(image, size),
+        None => image,
+    })?;
+    let sizes: Vec<(u32, u32)> = images.iter().map(|(_, i)| i.dimensions()).collect();
+    let (width, height, positions) = pack(&sizes, options.padding);
+
+    let mut sheet = RgbaImage::new(width, height);
+    let mut names = HashSet::new();
+    let mut sprites = Vec::new();
+    for ((file, image), (x, y)) in images.iter().zip(positions) {
+        imageops::overlay(&mut sheet, &image.to_rgba8(), x as i64, y as i64);
+        sprites.push(Sprite {
+            name: unique_name(file, &mut names),
+            file: file.clone(),
+            x,
+            y,
+            width: image.width(),
+            height: image.height(),
+        });
+    }
+    save(sheet, output)?;
+
+    let mut written = vec![output.to_path_buf()];
+    let image = output
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    if matches!(options.map, SpriteMap::Css | SpriteMap::Both) {
+        let path = output.with_extension("css");
+        fs::write(&path, css(&sprites, &image, &options.prefix)).at("write", &path)?;
+        written.push(path);
+    }
+    if matches!(options.map, SpriteMap::Json | SpriteMap::Both) {
+        let path = output.with_extension("json");
+        let sheet = SpriteSheet {
+            image,
+            width,
+            height,
+            sprites: &sprites,
+        };
+        let json = serde_json::to_string_pretty(&sheet).map_err(|e| {
+            ImagixError::FormatError("Unable to serialize the sprite map".into()).caused_by(e)
+        })?;
+        fs::write(&path, json + "\n").at("write", &path)?;
+        written.push(path);
+    }
+    Ok(SheetReport { written, batch })
+}
+
+// Width and height of a sheet, and the top left corner of each icon on it
+pub type Packing = (u32, u32, Vec<(u32, u32)>);
+
+// Shelf packing, first fit by decreasing height: the tallest icons are laid out in rows, each
+// going into the first row it fits in. Several widths of the sheet are tried and the one with
+// the smallest area wins, the squarest on a tie. Returns the size of the sheet and the position
+// of each icon, in the order given.
+pub fn pack(sizes: &[(u32, u32)], padding: u32) -> Packing {
+    let padded: Vec<(u32, u32)> = sizes
+        .iter()
+        .map(|(w, h)| (w + padding, h + padding))
+        .collect();
+    let widest = padded.iter().map(|s| s.0).max().unwrap_or(0);
+    let area: u64 = padded.iter().map(|(w, h)| *w as u64 * *h as u64).sum();
+    let side = (area as f64).sqrt().ceil() as u32;
+    let mut best: Option<Packing> = None;
+    for step in 0..=10 {
+        let width = widest.max(side + side * step / 10);
+        let (used_width, height, positions) = shelves(&padded, width);
+        let better = match &best {
+            None => true,
+            Some((w, h, _)) => {
+                let (area, best_area) = (used_width as u64 * height as u64, *w as u64 * *h as u64);
+                area < best_area
+                    || (area == best_area && used_width.abs_diff(height) < w.abs_diff(*h))
+            }
+        };
+        if better {
+            best = Some((used_width, height, positions));
+        }
+    }
+    let (width, height, positions) = best.unwrap_or((0, 0, Vec::new()));
+    // The padding after the last column and row is not needed
+    (
+        width.saturating_sub(padding).max(1),
+        height.saturating_sub(padding).max(1),
+        positions,
+    )
+}
+
+fn shelves(sizes: &[(u32, u32)], width: u32) -> Packing {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|a, b| sizes[*b].1.cmp(&sizes[*a].1).then(a.cmp(b)));
+    // Each shelf: its top, its height and how much of its width is taken
+    let mut shelves: Vec<(u32, u32, u32)> = Vec::new();
+    let mut positions = vec![(0, 0); sizes.len()];
+    for i in order {
+        let (w, h) = sizes[i];
+        let shelf = shelves.iter_mut().find(|s| s.2 + w <= width && h <= s.1);
+        let shelf = match shelf {
+            Some(shelf) => shelf,
+            None => {
+                let top = shelves.last().map_or(0, |s| s.0 + s.1);
+                shelves.push((top, h, 0));
+                shelves.last_mut().unwrap()
+            }
+        };
+        positions[i] = (shelf.2, shelf.0);
+        shelf.2 += w;
+    }
+    let used_width = shelves.iter().map(|s| s.2).max().unwrap_or(0);
+    let height = shelves.last().map_or(0, |s| s.0 + s.1);
+    (used_width, height, positions)
+}
+
+// The file stem made fit for a CSS class, eg. `Home Icon.png` is home-icon, numbered if taken
+fn unique_name(file: &Path, taken: &mut HashSet<String>) -> String {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = String::new();
+    for c in stem.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            name.push(c);
+        } else if !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_matches('-');
+    let name = if name.is_empty() { "sprite" } else { name };
+    let mut unique = name.to_string();
+    let mut n = 1;
+    while !taken.insert(unique.clone()) {
+        unique = format!("{}-{}", name, n);
+        n += 1;
+    }
+    unique
+}
+
+fn css(sprites: &[Sprite], image: &str, prefix: &str) -> String {
+    let mut css = String::new();
+    let _ = writeln!(
+        css,
+        ".{} {{\n  background-image: url(\"{}\");\n  background-repeat: no-repeat;\n  display: inline-block;\n}}",
+        prefix,
+        image.replace('"', "\\\"")
+    );
+    for sprite in sprites {
+        let _ = writeln!(
+            css,
+            ".{}-{} {{ background-position: {} {}; width: {}px; height: {}px; }}",
+            prefix,
+            sprite.name,
+            offset(sprite.x),
+            offset(sprite.y),
+            sprite.width,
+            sprite.height
+        );
+    }
+    css
+}
+
+// The background is moved left and up by the position of the sprite
+fn offset(pixels: u32) -> String {
+    if pixels == 0 {
+        "0".to_string()
+    } else {
+        format!("-{}px", pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack() {
+        let sizes = [(32, 32), (64, 16), (16, 16), (32, 64), (16, 48)];
+        let (width, height, positions) = pack(&sizes, 2);
+        // Every icon is on the sheet and no two overlap, padding included
+        for (i, ((w, h), (x, y))) in sizes.iter().zip(&positions).enumerate() {
+            assert!(x + w <= width && y + h <= height);
+            for ((w2, h2), (x2, y2)) in sizes.iter().zip(&positions).skip(i + 1) {
+                let apart =
+                    x + w + 2 <= *x2 || x2 + w2 + 2 <= *x || y + h + 2 <= *y2 || y2 + h2 + 2 <= *y;
+                assert!(apart, "{:?} and {:?}", (x, y), (x2, y2));
+            }
+        }
+        let area: u32 = sizes.iter().map(|(w, h)| (w + 2) * (h + 2)).sum();
+        assert!(width * height < area * 2);
+        assert_eq!((1, 1, Vec::new()), pack(&[], 0));
+    }
+    #[test]
+    fn test_sprite_and_montage() {
+        let root = std::env::temp_dir().join("imagix-sheet-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for (name, size) in [
+            ("Home Icon.png", 40),
+            ("home-icon.png", 20),
+            ("user.png", 100),
+        ] {
+            RgbaImage::from_pixel(size, size, Rgba([255, 0, 0, 255]))
+                .save(root.join(name))
+                .unwrap();
+        }
+        let options = SpriteOptions {
+            max_size: Some(50),
+            padding: 0,
+            prefix: "icon".to_string(),
+            map: SpriteMap::Both,
+        };
+        let output = root.join("tmp/sprite.png");
+        let report = process_sprite_request(&options, &root, &output, 2).unwrap();
+        assert_eq!(3, report.written.len());
+        let css = fs::read_to_string(root.join("tmp/sprite.css")).unwrap();
+        assert!(css.contains("url(\"sprite.png\")"));
+        assert!(css.contains(".icon-home-icon {"));
+        assert!(css.contains(".icon-home-icon-1 { background-position: "));
+        assert!(css.contains("width: 50px; height: 50px;"));
+        let sheet = image::open(&output).unwrap();
+        assert!(sheet.width() >= 50 && sheet.height() >= 50);
+
+        let options = MontageOptions {
+            cols: 2,
+            tile: 30,
+            spacing: 5,
+            labels: false,
+            font: None,
+            background: Rgba([255, 255, 255, 255]),
+        };
+        let output = root.join("tmp/montage.jpg");
+        process_montage_request(&options, &root, &output, 2).unwrap();
+        assert_eq!((75, 75), image::image_dimensions(&output).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+use image::imageops::FilterType;
+use image::{GenericImageView, ImageFormat};
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use super::batch::{run_batch, BatchReport};
+use super::error::{Context, ImagixError};
+use super::files::{collect_inputs, Selection};
+use super::format::{extension, EncodeOptions, OutputFormat};
+use super::metadata::kept_tags;
+use super::pipeline::open_upright;
+use super::resize::{Elapsed, Mode};
+
+// Widths of the variants, eg. 320,640,1280
+#[derive(Debug, Clone, PartialEq)]
+pub struct Widths(pub Vec<u32>);
+
+impl FromStr for Widths {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut widths = s
+            .split(',')
+            .map(|w| match w.trim().parse::<u32>() {
+                Ok(width) if width > 0 => Ok(width),
+                _ => Err(ImagixError::UserInputError(format!(
+                    "Wrong width `{}`, expected positive numbers of pixels such as 320,640,1280",
+                    w
+                ))),
+            })
+            .collect::<Result<Vec<u32>, ImagixError>>()?;
+        widths.sort_unstable();
+        widths.dedup();
+        Ok(Widths(widths))
+    }
+}
+
+// Formats of the variants, eg. webp,jpeg. The last one is the fallback of the <picture>.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formats(pub Vec<OutputFormat>);
+
+impl FromStr for Formats {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut formats: Vec<OutputFormat> = Vec::new();
+        for format in s.split(',') {
+            let format = format.trim().parse()?;
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        Ok(Formats(formats))
+    }
+}
+
+// What is written next to the variants of each image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Manifest {
+    Html, // <picture> 片段
+    Json,
+    None,
+}
+
+impl FromStr for Manifest {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Manifest::Html),
+            "json" => Ok(Manifest::Json),
+            "none" => Ok(Manifest::None),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for manifest `{}`, expected html, json or none",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SrcsetOptions {
+    pub widths: Widths,
+    pub formats: Formats,
+    pub manifest: Manifest,
+    // Quality, compression and metadata; the format is taken from `formats`
+    pub encoding: EncodeOptions,
+}
+
+// One file written for an image
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Variant {
+    pub file: String,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+}
+
+// The variants of an image, as written to its JSON manifest
+#[derive(Debug, Serialize)]
+struct ImageSet<'a> {
+    source: &'a Path,
+    width: u32,
+    height: u32,
+    variants: &'a [Variant],
+}
+
+pub fn process_srcset_request(
+    options: &SrcsetOptions,
+    mode: Mode,
+    src_folder: &Path,
+    selection: &Selection,
+    jobs: usize,
+) -> Result<BatchReport, ImagixError> {
+    if options.widths.0.is_empty() || options.formats.0.is_empty() {
+        return Err(ImagixError::UserInputError(
+            "Specify at least one width and one format".to_string(),
+        ));
+    }
+    let (listing, layout) = collect_inputs(mode, src_folder, selection)?;
+    // Images with the same name in a folder get different names for their variants, as the
+    // outputs of the transform subcommand do
+    let planned: Vec<(PathBuf, &str)> = listing
+        .images
+        .iter()
+        .map(|file| (file.clone(), "set"))
+        .collect();
+    let names = layout.plan(&planned);
+    run_batch(&listing.images, listing.skipped.len(), jobs, |file| {
+        let name = &names[file];
+        let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+        let dest_folder = name.parent().unwrap_or(Path::new(""));
+        generate_srcset(options, file, dest_folder, &stem)
+    })
+}
+
+// Writes `<stem>-<width>w.<ext>` for every width and format, decoding the image only once
+fn generate_srcset(
+    options: &SrcsetOptions,
+    src: &Path,
+    dest_folder: &Path,
+    stem: &str,
+) -> Result<String, ImagixError> {
+    let timer = Instant::now();
+    let source = open_upright(src)?;
+    let (width, height) = source.image.dimensions();
+    fs::create_dir_all(dest_folder).at("create folder", dest_folder)?;
+    let metadata = match &source.exif {
+        Some(exif) if options.encoding.keep_metadata => kept_tags(exif)?,
+        _ => None,
+    };
+
+    // Images are not enlarged: widths beyond the image are left out, and an image narrower
+    // than all of them gets a single variant at its own width
+    let mut widths: Vec<u32> = options
+        .widths
+        .0
+        .iter()
+        .copied()
+        .filter(|w| *w <= width)
+        .collect();
+    if widths.is_empty() {
+        widths.push(width);
+    }
+    // `keep` may come out as another of the formats, eg. keep,jpeg for a JPEG
+    let mut formats: Vec<ImageFormat> = Vec::new();
+    for format in &options.formats.0 {
+        let encoding = EncodeOptions {
+            format: *format,
+            ..options.encoding
+        };
+        let format = encoding.target_format(source.format)?;
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    let mut variants = Vec::new();
+    for target in widths {
+        let target_height =
+            ((height as u64 * target as u64 + width as u64 / 2) / width as u64).max(1) as u32;
+        let resized = if target == width {
+            source.image.clone()
+        } else {
+            source
+                .image
+                .resize_exact(target, target_height, FilterType::Lanczos3)
+        };
+        for &format in &formats {
+            let file = format!("{}-{}w.{}", stem, target, extension(format));
+            let path = dest_folder.join(&file);
+            let bytes = options
+                .encoding
+                .encode(&resized, format, metadata.clone())
+                .at("encode", &path)?;
+            fs::write(&path, &bytes).at("write", &path)?;
+            variants.push(Variant {
+                file,
+                mime_type: format.to_mime_type().to_string(),
+                width: target,
+                height: target_height,
+                bytes: bytes.len() as u64,
+            });
+        }
+    }
+
+    match options.manifest {
+        Manifest::Html => {
+            let path = dest_folder.join(format!("{}.html", stem));
+            fs::write(&path, picture(&variants)).at("write", &path)?;
+        }
+        Manifest::Json => {
+            let set = ImageSet {
+                source: src,
+                width,
+                height,
+                variants: &variants,
+            };
+            let json = serde_json::to_string_pretty(&set).map_err(|e| {
+                ImagixError::FormatError("Unable to serialize the manifest".into()).caused_by(e)
+            })?;
+            let path = dest_folder.join(format!("{}.json", stem));
+            fs::write(&path, json + "\n").at("write", &path)?;
+        }
+        Manifest::None => {}
+    }
+    Ok(format!(
+        "Generated {} variants of {:?} in {}. Output files in {:?}",
+        variants.len(),
+        src,
+        Elapsed::from(&timer),
+        dest_folder
+    ))
+}
+
+// A <picture> with a <source> per format and the last format as the <img> fallback
+pub fn picture(variants: &[Variant]) -> String {
+    let mut types: Vec<&str> = Vec::new();
+    for variant in variants {
+        if !types.contains(&variant.mime_type.as_str()) {
+            types.push(&variant.mime_type);
+        }
+    }
+    let srcset = |mime_type: &str| -> Vec<&Variant> {
+        variants
+            .iter()
+            .filter(|v| v.mime_type == mime_type)
+            .collect()
+    };
+    let list = |variants: &[&Variant]| -> String {
+        variants
+            .iter()
+            .map(|v| format!("{} {}w", escape(&v.file), v.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut html = String::from("<picture>\n");
+    let Some((fallback, sources)) = types.split_last() else {
+        return html + "</picture>\n";
+    };
+    for mime_type in sources {
+        let _ = writeln!(
+            html,
+            "  <source type=\"{}\" srcset=\"{}\" sizes=\"100vw\">",
+            mime_type,
+            list(&srcset(mime_type))
+        );
+    }
+    let fallback = srcset(fallback);
+    // The largest variant is the src of browsers without srcset support
+    if let Some(largest) = fallback.last() {
+        let _ = writeln!(
+            html,
+            "  <img src=\"{}\" srcset=\"{}\" sizes=\"100vw\" width=\"{}\" height=\"{}\" alt=\"\">",
+            escape(&largest.file),
+            list(&fallback),
+            largest.width,
+            largest.height
+        );
+    }
+    html + "</picture>\n"
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    #[test]
+    fn test_srcset_variants() {
+        let root = std::env::temp_dir().join("imagix-srcset-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        DynamicImage::new_rgb8(1000, 500)
+            .save_with_format(root.join("photo.jpg"), ImageFormat::Jpeg)
+            .unwrap();
+        let options = SrcsetOptions {
+            widths: "1280, 640,320,640".parse().unwrap(),
+            formats: "webp,jpeg".parse().unwrap(),
+            manifest: Manifest::Json,
+            encoding: EncodeOptions::default(),
+        };
+        let out = root.join("out");
+        let selection = Selection::new().out_dir(Some(out.clone()));
+        let report = process_srcset_request(&options, Mode::All, &root, &selection, 2).unwrap();
+        assert!(report.is_success());
+        for file in [
+            "photo-320w.webp",
+            "photo-640w.webp",
+            "photo-320w.jpg",
+            "photo-640w.jpg",
+        ] {
+            assert!(out.join(file).exists(), "{}", file);
+        }
+        assert!(!out.join("photo-1280w.jpg").exists());
+        let manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(out.join("photo.json")).unwrap()).unwrap();
+        assert_eq!(4, manifest["variants"].as_array().unwrap().len());
+        assert_eq!("image/webp", manifest["variants"][0]["type"]);
+        assert_eq!(160, manifest["variants"][0]["height"]);
+
+        // Kept as JPEG, the same as the jpeg asked for
+        let options = SrcsetOptions {
+            formats: "keep,jpeg".parse().unwrap(),
+            manifest: Manifest::Html,
+            ..options
+        };
+        let report = process_srcset_request(&options, Mode::All, &root, &selection, 2).unwrap();
+        assert!(report.is_success());
+        let html = fs::read_to_string(out.join("photo.html")).unwrap();
+        assert!(!html.contains("<source"), "{}", html);
+        assert!(
+            html.contains(r#"srcset="photo-320w.jpg 320w, photo-640w.jpg 640w""#),
+            "{}",
+            html
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn test_picture_html() {
+        let variant = |file: &str, mime_type: &str, width: u32| Variant {
+            file: file.to_string(),
+            mime_type: mime_type.to_string(),
+            width,
+            height: width / 2,
+            bytes: 0,
+        };
+        let html = picture(&[
+            variant("a-320w.webp", "image/webp", 320),
+            variant("a-320w.jpg", "image/jpeg", 320),
+            variant("a-640w.webp", "image/webp", 640),
+            variant("a-640w.jpg", "image/jpeg", 640),
+        ]);
+        assert_eq!(
+            "<picture>\n  <source type=\"image/webp\" srcset=\"a-320w.webp 320w, a-640w.webp 640w\" sizes=\"100vw\">\n  <img src=\"a-640w.jpg\" srcset=\"a-320w.jpg 320w, a-640w.jpg 640w\" sizes=\"100vw\" width=\"640\" height=\"320\" alt=\"\">\n</picture>\n",
+            html
+        );
+        assert!("320,0".parse::<Widths>().is_err());
+        assert!("webp,avif".parse::<Formats>().is_err());
+    }
+}
@@ -1,27 +1,418 @@
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
 use super::error::ImagixError;
-use super::resize::get_image_files;
-use std::path::Path;
+use super::files::Selection;
+use super::metadata::{orientation, read_exif};
+
+// How many of the largest files are listed
+const LARGEST_FILES: usize = 5;
+
+// Buckets of the longest side of the images, in pixels
+const DIMENSION_BUCKETS: [(u32, &str); 5] = [
+    (640, "under 640 px"),
+    (1280, "640-1279 px"),
+    (1920, "1280-1919 px"),
+    (3840, "1920-3839 px"),
+    (u32::MAX, "3840 px and more"),
+];
+
+// Common aspect ratios, landscape; images within 1.5% of one of them are counted in its bucket
+const ASPECT_RATIOS: [(u32, u32); 7] = [(1, 1), (5, 4), (4, 3), (3, 2), (16, 10), (16, 9), (21, 9)];
+
+// How the stats are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Table,
+    Json,
+    Csv, // 每个文件一行
+}
+
+impl FromStr for ReportFormat {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(ReportFormat::Table),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for format `{}`, expected table, json or csv",
+                s
+            ))),
+        }
+    }
+}
+
+// What the header of an image tells about it; the pixels are not decoded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageInfo {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub format: String,
+    // As displayed, after the EXIF orientation
+    pub width: u32,
+    pub height: u32,
+    pub color_type: String,
+    pub bit_depth: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Unreadable {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    pub label: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub total_bytes: u64,
+    pub mean_bytes: u64,
+    pub min_bytes: u64,
+    pub max_bytes: u64,
+    pub formats: BTreeMap<String, usize>,
+    pub dimensions: Vec<Bucket>,
+    pub aspect_ratios: Vec<Bucket>,
+    pub color_types: BTreeMap<String, usize>,
+    pub bit_depths: BTreeMap<u16, usize>,
+    pub largest: Vec<ImageInfo>,
+    pub unreadable: Vec<Unreadable>,
+    pub images: Vec<ImageInfo>,
+}
+
+pub fn get_stats<P: AsRef<Path>>(src_folder: P) -> Result<Stats, ImagixError> {
+    let listing = Selection::new().collect(src_folder.as_ref())?;
+    let mut images = Vec::new();
+    let mut unreadable = Vec::new();
+    for path in listing.images {
+        match inspect(&path) {
+            Ok(info) => images.push(info),
+            // The path is already there, only the error that caused the failure is kept
+            Err(e) => unreadable.push(Unreadable {
+                path,
+                error: e.chain().pop().unwrap_or_default(),
+            }),
+        }
+    }
+    // A file named like an image whose content is not one is corrupt rather than unrelated
+    for path in listing.skipped {
+        if ImageFormat::from_path(&path).is_ok() {
+            unreadable.push(Unreadable {
+                path,
+                error: "Not a valid image".to_string(),
+            });
+        }
+    }
+    unreadable.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(Stats::new(images, unreadable))
+}
+
+fn inspect(path: &Path) -> Result<ImageInfo, ImagixError> {
+    let bytes = path.metadata()?.len();
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = match reader.format() {
+        Some(format) => format!("{:?}", format).to_lowercase(),
+        None => "unknown".to_string(),
+    };
+    let decoder = reader.into_decoder()?;
+    let (mut width, mut height) = decoder.dimensions();
+    let color_type = decoder.original_color_type();
+    // A file cut short still has a valid header, only decoding the pixels tells
+    DynamicImage::from_decoder(decoder)?;
+    let turned = read_exif(path).ok().as_ref().and_then(orientation);
+    if matches!(
+        turned,
+        Some(
+            Orientation::Rotate90
+                | Orientation::Rotate270
+                | Orientation::Rotate90FlipH
+                | Orientation::Rotate270FlipH
+        )
+    ) {
+        std::mem::swap(&mut width, &mut height);
+    }
+    Ok(ImageInfo {
+        path: path.to_path_buf(),
+        bytes,
+        format,
+        width,
+        height,
+        color_type: format!("{:?}", color_type),
+        bit_depth: color_type.bits_per_pixel() / (color_type.channel_count().max(1) as u16),
+    })
+}
+
+impl Stats {
+    fn new(images: Vec<ImageInfo>, unreadable: Vec<Unreadable>) -> Self {
+        let mut stats = Stats {
+            count: images.len(),
+            total_bytes: images.iter().map(|i| i.bytes).sum(),
+            min_bytes: images.iter().map(|i| i.bytes).min().unwrap_or(0),
+            max_bytes: images.iter().map(|i| i.bytes).max().unwrap_or(0),
+            dimensions: DIMENSION_BUCKETS
+                .iter()
+                .map(|(_, label)| Bucket {
+                    label: label.to_string(),
+                    count: 0,
+                })
+                .collect(),
+            unreadable,
+            ..Stats::default()
+        };
+        if stats.count > 0 {
+            stats.mean_bytes = (stats.total_bytes as f64 / stats.count as f64).round() as u64;
+        }
+        let mut aspect_ratios: BTreeMap<String, usize> = BTreeMap::new();
+        for image in &images {
+            *stats.formats.entry(image.format.clone()).or_default() += 1;
+            *stats
+                .color_types
+                .entry(image.color_type.clone())
+                .or_default() += 1;
+            *stats.bit_depths.entry(image.bit_depth).or_default() += 1;
+            let longest = image.width.max(image.height);
+            if let Some(i) = DIMENSION_BUCKETS.iter().position(|(max, _)| longest < *max) {
+                stats.dimensions[i].count += 1;
+            }
+            *aspect_ratios
+                .entry(aspect_ratio(image.width, image.height))
+                .or_default() += 1;
+        }
+        stats.aspect_ratios = aspect_ratios
+            .into_iter()
+            .map(|(label, count)| Bucket { label, count })
+            .collect();
+        // The most common first
+        stats
+            .aspect_ratios
+            .sort_by_key(|b| std::cmp::Reverse(b.count));
+
+        let mut largest = images.clone();
+        largest.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+        largest.truncate(LARGEST_FILES);
+        stats.largest = largest;
+        stats.images = images;
+        stats
+    }
 
-pub fn get_stats<P: AsRef<Path>>(src_folder: P) -> Result<(usize, f64), ImagixError> {
-    let image_files = get_image_files(src_folder)?;
-    let size = image_files
+    pub fn render(&self, format: ReportFormat) -> Result<String, ImagixError> {
+        match format {
+            ReportFormat::Table => Ok(self.table()),
+            ReportFormat::Json => serde_json::to_string_pretty(self)
+                .map(|json| json + "\n")
+                .map_err(|e| {
+                    ImagixError::FormatError("Unable to serialize the stats".into()).caused_by(e)
+                }),
+            ReportFormat::Csv => Ok(self.csv()),
+        }
+    }
+
+    fn table(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "Found {} image files with aggregate size of {}",
+            self.count,
+            megabytes(self.total_bytes)
+        );
+        if self.count > 0 {
+            let _ = writeln!(
+                out,
+                "File sizes: mean {}, smallest {}, largest {}",
+                megabytes(self.mean_bytes),
+                megabytes(self.min_bytes),
+                megabytes(self.max_bytes)
+            );
+            section(&mut out, "Formats", counts(&self.formats));
+            section(
+                &mut out,
+                "Dimensions (longest side)",
+                buckets(&self.dimensions),
+            );
+            section(&mut out, "Aspect ratios", buckets(&self.aspect_ratios));
+            section(&mut out, "Color types", counts(&self.color_types));
+            section(
+                &mut out,
+                "Bit depths",
+                self.bit_depths
+                    .iter()
+                    .map(|(depth, count)| (format!("{} bits", depth), *count))
+                    .collect(),
+            );
+            let _ = writeln!(out, "Largest files:");
+            for image in &self.largest {
+                let _ = writeln!(
+                    out,
+                    "  {:>10}  {:>11}  {}",
+                    megabytes(image.bytes),
+                    format!("{}x{}", image.width, image.height),
+                    image.path.display()
+                );
+            }
+        }
+        if !self.unreadable.is_empty() {
+            let _ = writeln!(out, "Unreadable files:");
+            for file in &self.unreadable {
+                let _ = writeln!(out, "  {}: {}", file.path.display(), file.error);
+            }
+        }
+        out
+    }
+
+    // One row per file, the unreadable ones last
+    fn csv(&self) -> String {
+        let mut out =
+            String::from("path,status,format,bytes,width,height,color_type,bit_depth,error\n");
+        for image in &self.images {
+            let _ = writeln!(
+                out,
+                "{},ok,{},{},{},{},{},{},",
+                csv_field(&image.path.to_string_lossy()),
+                image.format,
+                image.bytes,
+                image.width,
+                image.height,
+                image.color_type,
+                image.bit_depth
+            );
+        }
+        for file in &self.unreadable {
+            let _ = writeln!(
+                out,
+                "{},unreadable,,,,,,,{}",
+                csv_field(&file.path.to_string_lossy()),
+                csv_field(&file.error)
+            );
+        }
+        out
+    }
+}
+
+// Nearest common ratio, eg. 3:2, or 2:3 for a portrait image
+fn aspect_ratio(width: u32, height: u32) -> String {
+    if width == 0 || height == 0 {
+        return "other".to_string();
+    }
+    let portrait = height > width;
+    let ratio = width.max(height) as f64 / width.min(height) as f64;
+    let common = ASPECT_RATIOS
         .iter()
-        .map(move |f| f.metadata().unwrap().len())
-        .sum::<u64>();
-    Ok((image_files.len(), (size / 1000000) as f64))
+        .find(|(w, h)| (ratio / (*w as f64 / *h as f64) - 1.0).abs() <= 0.015);
+    match (common, portrait) {
+        (Some((w, h)), false) => format!("{}:{}", w, h),
+        (Some((w, h)), true) => format!("{}:{}", h, w),
+        (None, false) => "other landscape".to_string(),
+        (None, true) => "other portrait".to_string(),
+    }
+}
+
+pub(crate) fn megabytes(bytes: u64) -> String {
+    format!("{:.2} MB", bytes as f64 / 1_000_000.0)
+}
+
+fn counts<K: ToString>(map: &BTreeMap<K, usize>) -> Vec<(String, usize)> {
+    map.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+}
+
+fn buckets(buckets: &[Bucket]) -> Vec<(String, usize)> {
+    buckets
+        .iter()
+        .filter(|b| b.count > 0)
+        .map(|b| (b.label.clone(), b.count))
+        .collect()
+}
+
+fn section(out: &mut String, title: &str, rows: Vec<(String, usize)>) {
+    let _ = writeln!(out, "{}:", title);
+    for (label, count) in rows {
+        let _ = writeln!(out, "  {:<20} {:>6}", label, count);
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::DynamicImage;
+    use std::fs;
     use std::path::PathBuf;
     #[test]
     fn test_get_stats() {
         let path = PathBuf::from("/tmp/images");
-        let (count, size) = get_stats(path).unwrap();
+        let stats = get_stats(path).unwrap();
         // Note: For this test to pass,
         // alter the count and size with the right values
-        assert_eq!(count, 2);
-        assert_eq!(size, 17.0);
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.total_bytes, 17815931);
+    }
+    #[test]
+    fn test_stats_report() {
+        let root = std::env::temp_dir().join("imagix-stats-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        DynamicImage::new_rgb8(300, 200)
+            .save(root.join("a.png"))
+            .unwrap();
+        DynamicImage::new_luma8(90, 160)
+            .save(root.join("b.jpg"))
+            .unwrap();
+        fs::write(root.join("broken, really.jpg"), b"not an image").unwrap();
+        fs::write(root.join("notes.txt"), b"not an image either").unwrap();
+
+        let stats = get_stats(&root).unwrap();
+        assert_eq!(2, stats.count);
+        assert_eq!(Some(&1), stats.formats.get("jpeg"));
+        assert_eq!(Some(&2), stats.bit_depths.get(&8));
+        assert_eq!(2, stats.dimensions[0].count);
+        let ratios: Vec<&str> = stats
+            .aspect_ratios
+            .iter()
+            .map(|b| b.label.as_str())
+            .collect();
+        assert_eq!(vec!["3:2", "9:16"], ratios);
+        assert_eq!(1, stats.unreadable.len());
+        assert_eq!(
+            stats.total_bytes,
+            stats.largest.iter().map(|i| i.bytes).sum::<u64>()
+        );
+
+        let csv = stats.render(ReportFormat::Csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[1].contains("a.png,ok,png,"));
+        assert!(lines[3].contains("broken, really.jpg\",unreadable,"));
+        let json: serde_json::Value =
+            serde_json::from_str(&stats.render(ReportFormat::Json).unwrap()).unwrap();
+        assert_eq!("L8", json["images"][1]["color_type"]);
+        assert!(stats
+            .render(ReportFormat::Table)
+            .unwrap()
+            .starts_with("Found 2 image files with aggregate size of 0.00 MB\n"));
+
+        // A file cut short is corrupt, even though its header reads fine
+        let bytes = fs::read(root.join("a.png")).unwrap();
+        fs::write(root.join("cut.png"), &bytes[..bytes.len() / 2]).unwrap();
+        let stats = get_stats(&root).unwrap();
+        assert_eq!(2, stats.count);
+        assert!(stats.unreadable.iter().any(|u| u.path.ends_with("cut.png")));
+        fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -0,0 +1,374 @@
+use ab_glyph::{point, Font, FontVec, Glyph, PxScale, ScaleFont};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::error::ImagixError;
+
+// Largest font size of a text watermark, and widest text it may be, in pixels
+const MAX_FONT_SIZE: f32 = 1000.0;
+const MAX_TEXT_WIDTH: u32 = 16384;
+
+// Where the watermark is placed on the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl FromStr for Position {
+    type Err = ImagixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Position::TopLeft),
+            "top" => Ok(Position::Top),
+            "top-right" => Ok(Position::TopRight),
+            "left" => Ok(Position::Left),
+            "center" => Ok(Position::Center),
+            "right" => Ok(Position::Right),
+            "bottom-left" => Ok(Position::BottomLeft),
+            "bottom" => Ok(Position::Bottom),
+            "bottom-right" => Ok(Position::BottomRight),
+            _ => Err(ImagixError::UserInputError(format!(
+                "Wrong value for position `{}`, expected top-left, top, top-right, left, center, right, bottom-left, bottom or bottom-right",
+                s
+            ))),
+        }
+    }
+}
+
+// Alignment of the watermark along one side of the image
+enum Align {
+    Start,
+    Middle,
+    End,
+}
+
+impl Align {
+    fn place(&self, size: u32, mark: u32, margin: u32) -> i64 {
+        match self {
+            Align::Start => margin as i64,
+            Align::Middle => (size as i64 - mark as i64) / 2,
+            Align::End => size as i64 - mark as i64 - margin as i64,
+        }
+    }
+}
+
+impl Position {
+    // Top left corner of a mark of the given size, `margin` pixels away from the edges it is
+    // placed against
+    fn origin(&self, image: (u32, u32), mark: (u32, u32), margin: u32) -> (i64, i64) {
+        let (horizontal, vertical) = match self {
+            Position::TopLeft => (Align::Start, Align::Start),
+            Position::Top => (Align::Middle, Align::Start),
+            Position::TopRight => (Align::End, Align::Start),
+            Position::Left => (Align::Start, Align::Middle),
+            Position::Center => (Align::Middle, Align::Middle),
+            Position::Right => (Align::End, Align::Middle),
+            Position::BottomLeft => (Align::Start, Align::End),
+            Position::Bottom => (Align::Middle, Align::End),
+            Position::BottomRight => (Align::End, Align::End),
+        };
+        (
+            horizontal.place(image.0, mark.0, margin),
+            vertical.place(image.1, mark.1, margin),
+        )
+    }
+}
+
+// Opacity of the watermark, from 0 to 1
+pub fn parse_opacity(s: &str) -> Result<f32, ImagixError> {
+    match s.parse::<f32>() {
+        Ok(opacity) => check_opacity(opacity),
+        Err(_) => Err(wrong_opacity(s)),
+    }
+}
+
+pub fn check_opacity(opacity: f32) -> Result<f32, ImagixError> {
+    if (0.0..=1.0).contains(&opacity) {
+        Ok(opacity)
+    } else {
+        Err(wrong_opacity(opacity))
+    }
+}
+
+fn wrong_opacity(value: impl fmt::Display) -> ImagixError {
+    ImagixError::UserInputError(format!(
+        "Wrong value for opacity `{}`, expected a number from 0 to 1",
+        value
+    ))
+}
+
+// Width of the watermark as a fraction of the image width, above 0 and up to 1
+pub fn parse_relative_width(s: &str) -> Result<f32, ImagixError> {
+    match s.parse::<f32>() {
+        Ok(width) => check_relative_width(width),
+        Err(_) => Err(wrong_relative_width(s)),
+    }
+}
+
+pub fn check_relative_width(width: f32) -> Result<f32, ImagixError> {
+    if width > 0.0 && width <= 1.0 {
+        Ok(width)
+    } else {
+        Err(wrong_relative_width(width))
+    }
+}
+
+fn wrong_relative_width(value: impl fmt::Display) -> ImagixError {
+    ImagixError::UserInputError(format!(
+        "Wrong value for relative width `{}`, expected a fraction of the image width above 0 and up to 1",
+        value
+    ))
+}
+
+// A logo or a line of text blended onto images
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    mark: RgbaImage,
+    position: Position,
+    opacity: f32,
+    margin: u32,
+    // Width of the mark as a fraction of the width of the image, if it is scaled
+    relative_width: Option<f32>,
+}
+
+impl Watermark {
+    fn new(mark: RgbaImage) -> Self {
+        Watermark {
+            mark,
+            position: Position::BottomRight,
+            opacity: 1.0,
+            margin: 0,
+            relative_width: None,
+        }
+    }
+
+    pub fn from_image(path: &Path) -> Result<Self, ImagixError> {
+        let mark = image::open(path).map_err(|e| {
+            ImagixError::UserInputError(format!("Unable to open watermark {:?}: {}", path, e))
+        })?;
+        Ok(Watermark::new(mark.to_rgba8()))
+    }
+
+    // Draws `text` with the TrueType or OpenType font at `font`, `size` pixels high
+    pub fn from_text(
+        text: &str,
+        font: &Path,
+        size: f32,
+        color: Rgba<u8>,
+    ) -> Result<Self, ImagixError> {
+        let font = load_font(font)?;
+        if !size.is_finite() || size <= 0.0 || size > MAX_FONT_SIZE {
+            return Err(ImagixError::UserInputError(format!(
+                "Font size must be positive and at most {} pixels",
+                MAX_FONT_SIZE
+            )));
+        }
+        let (_, width) = layout(text, &font, size);
+        if width.ceil() > MAX_TEXT_WIDTH as f32 {
+            return Err(ImagixError::UserInputError(format!(
+                "The watermark text would be {} pixels wide, more than {}",
+                width.ceil(),
+                MAX_TEXT_WIDTH
+            )));
+        }
+        Ok(Watermark::new(draw_text(text, &font, size, color)))
+    }
+
+    pub fn position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn margin(mut self, margin: u32) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn relative_width(mut self, relative_width: Option<f32>) -> Self {
+        self.relative_width = relative_width;
+        self
+    }
+
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let scaled;
+        let mark = match self.relative_width {
+            Some(fraction) => {
+                let mark_width = ((width as f32 * fraction).round() as u32).max(1);
+                let mark_height = ((self.mark.height() as f32 * mark_width as f32
+                    / self.mark.width().max(1) as f32)
+                    .round() as u32)
+                    .max(1);
+                scaled =
+                    imageops::resize(&self.mark, mark_width, mark_height, FilterType::Lanczos3);
+                &scaled
+            }
+            None => &self.mark,
+        };
+        let (x, y) = self
+            .position
+            .origin((width, height), mark.dimensions(), self.margin);
+        let mut canvas = img.to_rgba8();
+        blend(&mut canvas, mark, x, y, self.opacity);
+        DynamicImage::ImageRgba8(canvas)
+    }
+}
+
+// A TrueType or OpenType font file
+pub(crate) fn load_font(path: &Path) -> Result<FontVec, ImagixError> {
+    let data = fs::read(path).map_err(|e| {
+        ImagixError::UserInputError(format!("Unable to read font {:?}: {}", path, e))
+    })?;
+    FontVec::try_from_vec(data)
+        .map_err(|e| ImagixError::UserInputError(format!("Invalid font: {}", e)))
+}
+
+// Alpha blends `mark`, made `opacity` times as opaque, onto `canvas` at x, y. The parts of the
+// mark outside the canvas are left out.
+fn blend(canvas: &mut RgbaImage, mark: &RgbaImage, x: i64, y: i64, opacity: f32) {
+    for (mx, my, pixel) in mark.enumerate_pixels() {
+        let (cx, cy) = (x + mx as i64, y + my as i64);
+        if cx < 0 || cy < 0 || cx >= canvas.width() as i64 || cy >= canvas.height() as i64 {
+            continue;
+        }
+        let alpha = pixel[3] as f32 / 255.0 * opacity;
+        let below = canvas.get_pixel_mut(cx as u32, cy as u32);
+        let below_alpha = below[3] as f32 / 255.0;
+        let out_alpha = alpha + below_alpha * (1.0 - alpha);
+        if out_alpha <= 0.0 {
+            continue;
+        }
+        for c in 0..3 {
+            let value = (pixel[c] as f32 * alpha + below[c] as f32 * below_alpha * (1.0 - alpha))
+                / out_alpha;
+            below[c] = value.round().clamp(0.0, 255.0) as u8;
+        }
+        below[3] = (out_alpha * 255.0).round() as u8;
+    }
+}
+
+// Renders one line of text on a transparent image just large enough to hold it
+pub(crate) fn draw_text(text: &str, font: &FontVec, size: f32, color: Rgba<u8>) -> RgbaImage {
+    let (glyphs, caret) = layout(text, font, size);
+    let font = font.as_scaled(PxScale::from(size));
+    let width = caret.ceil().max(1.0) as u32;
+    let height = font.height().ceil().max(1.0) as u32;
+    let mut mark = RgbaImage::new(width, height);
+    for glyph in glyphs {
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let x = bounds.min.x as i64 + gx as i64;
+                let y = bounds.min.y as i64 + gy as i64;
+                if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                    let pixel = mark.get_pixel_mut(x as u32, y as u32);
+                    let alpha = (coverage * color[3] as f32).round() as u8;
+                    *pixel = Rgba([color[0], color[1], color[2], pixel[3].max(alpha)]);
+                }
+            });
+        }
+    }
+    mark
+}
+
+// The glyphs of `text` on one line, and how wide it is
+fn layout(text: &str, font: &FontVec, size: f32) -> (Vec<Glyph>, f32) {
+    let font = font.as_scaled(PxScale::from(size));
+    let mut glyphs: Vec<Glyph> = Vec::new();
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += font.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(font.scale(), point(caret, font.ascent())));
+        caret += font.h_advance(id);
+        previous = Some(id);
+    }
+    (glyphs, caret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logo() -> Watermark {
+        Watermark::new(RgbaImage::from_pixel(10, 5, Rgba([255, 0, 0, 255])))
+    }
+
+    #[test]
+    fn test_watermark_placement_and_opacity() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 50, Rgba([0, 0, 255, 255])));
+        let marked = logo().margin(4).opacity(0.5).apply(&img).to_rgba8();
+        assert_eq!(
+            &Rgba([128, 0, 128, 255]),
+            marked.get_pixel(100 - 4 - 1, 50 - 4 - 1)
+        );
+        assert_eq!(&Rgba([0, 0, 255, 255]), marked.get_pixel(100 - 3, 50 - 3));
+        assert_eq!(
+            &Rgba([0, 0, 255, 255]),
+            marked.get_pixel(100 - 4 - 11, 50 - 5)
+        );
+
+        // Scaled to a fifth of the width, 20x10, in the center
+        let marked = logo()
+            .position(Position::Center)
+            .relative_width(Some(0.2))
+            .apply(&img)
+            .to_rgba8();
+        assert_eq!(&Rgba([255, 0, 0, 255]), marked.get_pixel(40, 20));
+        assert_eq!(&Rgba([255, 0, 0, 255]), marked.get_pixel(59, 29));
+        assert_eq!(&Rgba([0, 0, 255, 255]), marked.get_pixel(39, 20));
+        assert_eq!(&Rgba([0, 0, 255, 255]), marked.get_pixel(60, 30));
+
+        // A mark larger than the image is clipped
+        let small = DynamicImage::new_rgba8(4, 4);
+        assert_eq!(
+            (4, 4),
+            logo()
+                .position(Position::TopLeft)
+                .apply(&small)
+                .dimensions()
+        );
+    }
+    #[test]
+    fn test_text_watermark() {
+        assert!(parse_opacity("1.5").is_err());
+        assert!(check_opacity(f32::NAN).is_err());
+        assert_eq!(0.2, parse_relative_width("0.2").unwrap());
+        let error = check_relative_width(0.0).unwrap_err().to_string();
+        assert!(error.contains("relative width `0`"), "{}", error);
+        assert!("middle".parse::<Position>().is_err());
+        let font = Path::new("/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf");
+        assert!(Watermark::from_text("©", Path::new("missing.ttf"), 24.0, Rgba([0; 4])).is_err());
+        // Only where the font is installed
+        if font.exists() {
+            let text =
+                Watermark::from_text("© ACME", font, 24.0, Rgba([255, 255, 255, 255])).unwrap();
+            assert!(text.mark.width() > text.mark.height());
+            assert!(text.mark.pixels().any(|p| p[3] == 255));
+            let white = Rgba([255, 255, 255, 255]);
+            assert!(Watermark::from_text("© ACME", font, 1e9, white).is_err());
+            assert!(Watermark::from_text(&"ACME ".repeat(100), font, 1000.0, white).is_err());
+        }
+    }
+}
@@ -1,9 +1,31 @@
 #[allow(dead_code)]
 mod imagix;
-use crate::imagix::error::ImagixError;
-use crate::imagix::resize::{process_resize_request, Mode, SizeOption};
-use crate::imagix::stats::get_stats;
+use crate::imagix::batch::{default_jobs, parse_jobs, BatchReport};
+use crate::imagix::cache::Incremental;
+use crate::imagix::compare::{compare, diff_image, open_pair, PixelLimit, Thresholds};
+use crate::imagix::dedupe::{
+    parse_threshold, process_dedupe_request, Action, DedupeOptions, HashKind,
+};
+use crate::imagix::error::{Context, ImagixError};
+use crate::imagix::files::{Selection, DEFAULT_OUTPUT_FOLDER};
+use crate::imagix::format::{parse_quality, EncodeOptions, OutputFormat, PngCompression};
+use crate::imagix::job::load_jobs;
+use crate::imagix::metadata::{describe, read_exif};
+use crate::imagix::pipeline::{process_transform_request, Operation, Pipeline};
+use crate::imagix::resize::{
+    parse_dimension, process_resize_request, Background, Fit, Mode, ResizeOptions, Scale,
+    SizeOption,
+};
+use crate::imagix::sheet::{
+    process_montage_request, process_sprite_request, MontageOptions, SheetReport, SpriteMap,
+    SpriteOptions,
+};
+use crate::imagix::srcset::{process_srcset_request, Formats, Manifest, SrcsetOptions, Widths};
+use crate::imagix::stats::{get_stats, ReportFormat};
+use crate::imagix::watermark::{parse_opacity, parse_relative_width, Position, Watermark};
 use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use structopt::StructOpt;
 // Define commandline arguments in a struct
 
@@ -11,23 +33,449 @@ use structopt::StructOpt;
 #[structopt(
     name = "resize",
     about = "This is a tool for image resizing and stats",
-    help = "Specify subcommand resize or stats. For help, type imagecli resize --help or imagecli stats --help"
+    help = "Specify subcommand resize, transform, watermark, srcset, montage, sprite, dedupe, compare, exif, stats or run. For help, type imagecli <subcommand> --help",
+    after_help = "Exit status: 0 on success, 1 if some images failed or a comparison exceeded its thresholds, 2 for wrong input, 3 for file errors, 4 for image processing errors, 5 for format errors"
 )]
 enum Commandline {
-    #[structopt(help = "Specify size(small/medium/large) , mode(single/all) and srcfolder")]
+    #[structopt(
+        help = "Specify size(small/medium/large), width/height or scale, fit, mode(single/all) and srcfolder"
+    )]
     Resize {
         #[structopt(long)]
-        size: SizeOption,
+        size: Option<SizeOption>,
+        #[structopt(long, parse(try_from_str = parse_dimension))]
+        width: Option<u32>,
+        #[structopt(long, parse(try_from_str = parse_dimension))]
+        height: Option<u32>,
+        #[structopt(long, help = "Scale factor, eg. 50% or 0.5")]
+        scale: Option<Scale>,
+        #[structopt(long, default_value = "contain", help = "contain, cover, exact or pad")]
+        fit: Fit,
+        #[structopt(
+            long,
+            default_value = "white",
+            help = "Background color for the pad fit"
+        )]
+        background: Background,
+        #[structopt(flatten)]
+        encoding: EncodingArgs,
+        #[structopt(flatten)]
+        batch: BatchArgs,
+        #[structopt(flatten)]
+        cache: CacheArgs,
+    },
+    #[structopt(
+        help = "Specify ops, eg. \"rotate:90,flip:h,crop:10,10,400,300,grayscale,blur:1.5,brighten:10,contrast:5,resize:200x\", mode(single/all) and srcfolder"
+    )]
+    Transform {
         #[structopt(long)]
-        mode: Mode,
-        #[structopt(long, parse(from_os_str))]
-        srcfolder: PathBuf,
+        ops: Pipeline,
+        #[structopt(flatten)]
+        encoding: EncodingArgs,
+        #[structopt(flatten)]
+        batch: BatchArgs,
+        #[structopt(flatten)]
+        cache: CacheArgs,
+    },
+    #[structopt(
+        help = "Specify a watermark image or a text and font, its position and opacity, mode(single/all) and srcfolder"
+    )]
+    Watermark {
+        #[structopt(long, parse(from_os_str), help = "Logo to blend onto the images")]
+        image: Option<PathBuf>,
+        #[structopt(long, help = "Text to write onto the images, with --font")]
+        text: Option<String>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "TrueType or OpenType font of the text"
+        )]
+        font: Option<PathBuf>,
+        #[structopt(
+            long,
+            default_value = "24",
+            help = "Height of the text in pixels, at most 1000"
+        )]
+        size: f32,
+        #[structopt(long, default_value = "white", help = "Color of the text")]
+        color: Background,
+        #[structopt(
+            long,
+            default_value = "bottom-right",
+            help = "top-left, top, top-right, left, center, right, bottom-left, bottom or bottom-right"
+        )]
+        position: Position,
+        #[structopt(long, default_value = "1", parse(try_from_str = parse_opacity), help = "From 0, invisible, to 1")]
+        opacity: f32,
+        #[structopt(long, default_value = "0", help = "Distance to the edges in pixels")]
+        margin: u32,
+        #[structopt(
+            long,
+            parse(try_from_str = parse_relative_width),
+            help = "Scale the watermark to this fraction of the image width, eg. 0.2"
+        )]
+        relative_width: Option<f32>,
+        #[structopt(long, help = "Operations run before the watermark, eg. resize:800x")]
+        ops: Option<Pipeline>,
+        #[structopt(flatten)]
+        encoding: EncodingArgs,
+        #[structopt(flatten)]
+        batch: BatchArgs,
+        #[structopt(flatten)]
+        cache: CacheArgs,
+    },
+    #[structopt(
+        help = "Specify widths, eg. 320,640,1280, formats, eg. webp,jpeg, the manifest, mode(single/all) and srcfolder"
+    )]
+    Srcset {
+        #[structopt(long, help = "Widths of the variants in pixels, eg. 320,640,1280")]
+        widths: Widths,
+        #[structopt(
+            long,
+            default_value = "webp,jpeg",
+            help = "Formats of the variants, eg. webp,jpeg; the last one is the <img> fallback"
+        )]
+        formats: Formats,
+        #[structopt(
+            long,
+            default_value = "html",
+            help = "Write a <picture> snippet (html) or a json manifest per image, or none"
+        )]
+        manifest: Manifest,
+        #[structopt(flatten)]
+        quality: QualityArgs,
+        #[structopt(flatten)]
+        batch: BatchArgs,
+    },
+    #[structopt(help = "Specify the folder, the number of columns and the size of the tiles")]
+    Montage {
+        #[structopt(parse(from_os_str))]
+        folder: PathBuf,
+        #[structopt(long, default_value = "6")]
+        cols: u32,
+        #[structopt(
+            long,
+            default_value = "200",
+            parse(try_from_str = parse_dimension),
+            help = "Side of the square each image is fitted in, in pixels"
+        )]
+        tile: u32,
+        #[structopt(long, default_value = "10", help = "Space between the tiles in pixels")]
+        spacing: u32,
+        #[structopt(long, help = "Write the file names under the images")]
+        labels: bool,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "TrueType or OpenType font of the labels, a system font by default"
+        )]
+        font: Option<PathBuf>,
+        #[structopt(long, default_value = "white")]
+        background: Background,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "The contact sheet, <folder>/tmp/montage.png by default"
+        )]
+        output: Option<PathBuf>,
+        #[structopt(long, parse(try_from_str = parse_jobs), help = "Number of images loaded in parallel, one per CPU by default")]
+        jobs: Option<usize>,
+    },
+    #[structopt(help = "Specify the folder of icons to pack into one image")]
+    Sprite {
+        #[structopt(parse(from_os_str))]
+        folder: PathBuf,
+        #[structopt(
+            long,
+            parse(try_from_str = parse_dimension),
+            help = "Scale larger icons down to fit in a square of this side"
+        )]
+        max_size: Option<u32>,
+        #[structopt(long, default_value = "2", help = "Space between the icons in pixels")]
+        padding: u32,
+        #[structopt(long, default_value = "icon", help = "Prefix of the CSS classes")]
+        prefix: String,
+        #[structopt(long, default_value = "both", help = "css, json or both")]
+        map: SpriteMap,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "The sprite sheet, <folder>/tmp/sprite.png by default; the maps are written next to it"
+        )]
+        output: Option<PathBuf>,
+        #[structopt(long, parse(try_from_str = parse_jobs), help = "Number of images loaded in parallel, one per CPU by default")]
+        jobs: Option<usize>,
+    },
+    #[structopt(
+        help = "Specify the folder to find duplicates in, the hash, the threshold and the action"
+    )]
+    Dedupe {
+        #[structopt(parse(from_os_str))]
+        folder: PathBuf,
+        #[structopt(long, default_value = "phash", help = "ahash, dhash or phash")]
+        hash: HashKind,
+        #[structopt(
+            long,
+            default_value = "5",
+            parse(try_from_str = parse_threshold),
+            help = "Largest number of bits the hashes of near-duplicates differ in, 0 for identical hashes"
+        )]
+        threshold: u32,
+        #[structopt(
+            long,
+            default_value = "report",
+            help = "report, move (with --move-to) or hardlink, which replaces exact copies only"
+        )]
+        action: Action,
+        #[structopt(long, parse(from_os_str), help = "Folder the duplicates are moved to")]
+        move_to: Option<PathBuf>,
+        #[structopt(long, parse(try_from_str = parse_jobs), help = "Number of images hashed in parallel, one per CPU by default")]
+        jobs: Option<usize>,
+    },
+    #[structopt(
+        help = "Specify the two images, the diff image and the thresholds; exits with 1 if they are exceeded"
+    )]
+    Compare {
+        #[structopt(parse(from_os_str))]
+        first: PathBuf,
+        #[structopt(parse(from_os_str))]
+        second: PathBuf,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Write the differing pixels in red over the faded first image there"
+        )]
+        diff: Option<PathBuf>,
+        #[structopt(
+            long,
+            default_value = "0",
+            help = "Largest difference of a channel, from 0 to 255, still counted as the same"
+        )]
+        tolerance: u8,
+        #[structopt(
+            long,
+            help = "Number of pixels allowed to differ, or a percentage such as 0.5%. With no limit given, no pixel may differ"
+        )]
+        max_diff_pixels: Option<PixelLimit>,
+        #[structopt(long, help = "Lowest PSNR allowed, in dB")]
+        min_psnr: Option<f64>,
+        #[structopt(long, help = "Lowest SSIM allowed, up to 1")]
+        min_ssim: Option<f64>,
+    },
+    #[structopt(help = "Specify the image file to list the EXIF tags of")]
+    Exif {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
     },
-    #[structopt(help = "Specify srcfolder")]
+    #[structopt(help = "Specify srcfolder and the format of the report")]
     Stats {
         #[structopt(long, parse(from_os_str))]
         srcfolder: PathBuf,
+        #[structopt(long, default_value = "table", help = "table, json or csv")]
+        format: ReportFormat,
     },
+    #[structopt(
+        help = "Specify a TOML job file of inputs, steps and output names; it is checked as a whole before any job runs"
+    )]
+    Run {
+        #[structopt(parse(from_os_str))]
+        jobfile: PathBuf,
+        #[structopt(long, help = "Run only the job of this name")]
+        only: Option<String>,
+        #[structopt(
+            long,
+            help = "Check the file and list the outputs without writing them"
+        )]
+        dry_run: bool,
+        #[structopt(long, parse(try_from_str = parse_jobs), help = "Number of images processed in parallel, one per CPU by default")]
+        jobs: Option<usize>,
+    },
+}
+
+// How output images are written
+#[derive(StructOpt, Debug)]
+struct EncodingArgs {
+    #[structopt(
+        long,
+        default_value = "keep",
+        help = "keep, png, jpeg, webp, gif, bmp or tiff"
+    )]
+    format: OutputFormat,
+    #[structopt(flatten)]
+    quality: QualityArgs,
+}
+
+impl EncodingArgs {
+    fn options(&self) -> EncodeOptions {
+        EncodeOptions {
+            format: self.format,
+            ..self.quality.options()
+        }
+    }
+}
+
+// How output images are encoded, whatever their format
+#[derive(StructOpt, Debug)]
+struct QualityArgs {
+    #[structopt(long, default_value = "85", parse(try_from_str = parse_quality), help = "Quality of jpeg and webp output, from 1 to 100")]
+    quality: u8,
+    #[structopt(
+        long,
+        default_value = "default",
+        help = "PNG compression: fast, default, best, none or a level from 1 to 9"
+    )]
+    png_compression: PngCompression,
+    #[structopt(long, help = "Copy camera, date and GPS tags to jpeg and png outputs")]
+    keep_metadata: bool,
+    #[structopt(
+        long,
+        conflicts_with = "keep-metadata",
+        help = "Drop all metadata, the default"
+    )]
+    strip_metadata: bool,
+}
+
+impl QualityArgs {
+    fn options(&self) -> EncodeOptions {
+        EncodeOptions {
+            format: OutputFormat::Keep,
+            quality: self.quality,
+            png_compression: self.png_compression,
+            keep_metadata: self.keep_metadata && !self.strip_metadata,
+        }
+    }
+}
+
+// Which images are processed and where the outputs go
+#[derive(StructOpt, Debug)]
+struct BatchArgs {
+    #[structopt(long)]
+    mode: Mode,
+    #[structopt(long, parse(from_os_str))]
+    srcfolder: PathBuf,
+    #[structopt(long, parse(try_from_str = parse_jobs), help = "Number of images processed in parallel, one per CPU by default")]
+    jobs: Option<usize>,
+    #[structopt(long, help = "Also process images in subfolders")]
+    recursive: bool,
+    #[structopt(long, help = "Only process files matching this glob, eg. '**/*.jpg'")]
+    include: Vec<String>,
+    #[structopt(long, help = "Skip files matching this glob")]
+    exclude: Vec<String>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write outputs there, mirroring the source folders, instead of <folder>/tmp"
+    )]
+    out_dir: Option<PathBuf>,
+}
+
+impl BatchArgs {
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(default_jobs)
+    }
+
+    fn selection(&self) -> Selection {
+        let selection = self
+            .include
+            .iter()
+            .fold(Selection::new(), |s, pattern| s.include(pattern));
+        self.exclude
+            .iter()
+            .fold(selection, |s, pattern| s.exclude(pattern))
+            .recursive(self.recursive)
+            .out_dir(self.out_dir.clone())
+    }
+}
+
+// Whether outputs that are up to date are made again
+#[derive(StructOpt, Debug)]
+struct CacheArgs {
+    #[structopt(long, help = "Process every image, even if its output is up to date")]
+    force: bool,
+    #[structopt(
+        long,
+        help = "List the images that would be processed and the outputs that would be removed"
+    )]
+    dry_run: bool,
+}
+
+impl CacheArgs {
+    fn incremental(&self) -> Incremental {
+        Incremental {
+            force: self.force,
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+// Prints the error and the errors that caused it, then exits with the status of its kind
+fn fail(e: ImagixError) -> ! {
+    let mut chain = e.chain().into_iter();
+    if let Some(error) = chain.next() {
+        eprintln!("Error: {}", error);
+    }
+    for cause in chain {
+        eprintln!("  caused by: {}", cause);
+    }
+    process::exit(e.exit_code());
+}
+
+// Prints the summary of a batch and the files that failed
+fn print_batch(report: &BatchReport) {
+    let removed = if report.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    for output in &report.removed {
+        println!("{} orphaned output {:?}", removed, output);
+    }
+    if report.dry_run {
+        for planned in &report.planned {
+            match &planned.output {
+                Some(output) => println!(
+                    "Would process {:?} to {:?} ({})",
+                    planned.input, output, planned.reason
+                ),
+                None => println!("Would process {:?} ({})", planned.input, planned.reason),
+            }
+        }
+        println!(
+            "Dry run: {} images to process, nothing written",
+            report.planned.len()
+        );
+    }
+    println!("{}", report);
+    for (file, e) in &report.failed {
+        // The file is left out when the error already names it
+        match e.path() {
+            Some(_) => eprintln!("Failed: {}", e.chain().join(": ")),
+            None => eprintln!("Failed {:?}: {}", file, e.chain().join(": ")),
+        }
+    }
+}
+
+// Prints the summary of a batch and exits with an error status if any file failed
+fn report_batch(result: Result<BatchReport, ImagixError>) {
+    match result {
+        Ok(report) => {
+            print_batch(&report);
+            if !report.is_success() {
+                process::exit(1);
+            }
+        }
+        Err(e) => fail(e),
+    };
+}
+
+// Prints the files written for a contact or sprite sheet, then the summary of the batch
+fn report_sheet(result: Result<SheetReport, ImagixError>) {
+    report_batch(result.map(|report| {
+        for file in &report.written {
+            println!("Wrote {:?}", file);
+        }
+        report.batch
+    }));
 }
 
 fn main() {
@@ -35,29 +483,279 @@ fn main() {
     match args {
         Commandline::Resize {
             size,
-            mode,
-            mut srcfolder,
-        } => {
-            match process_resize_request(size, mode, &mut srcfolder) {
-                Ok(_) => println!("Image(s) resized successfully"),
-                Err(e) => match e {
-                    ImagixError::FileIOError(e) => println!("{}", e),
-                    ImagixError::UserInputError(e) => println!("{}", e),
-                    ImagixError::ImageResizingError(e) => println!("{}", e),
-                    _ => println!("Error in processing"),
+            width,
+            height,
+            scale,
+            fit,
+            background,
+            encoding,
+            batch,
+            cache,
+        } => {
+            let result = ResizeOptions::new(size, width, height, scale, fit, background).and_then(
+                |options| {
+                    process_resize_request(
+                        &options,
+                        &encoding.options(),
+                        batch.mode,
+                        &batch.srcfolder,
+                        &batch.selection(),
+                        &cache.incremental(),
+                        batch.jobs(),
+                    )
                 },
+            );
+            report_batch(result);
+        }
+        Commandline::Transform {
+            ops,
+            encoding,
+            batch,
+            cache,
+        } => report_batch(process_transform_request(
+            &ops,
+            &encoding.options(),
+            batch.mode,
+            &batch.srcfolder,
+            &batch.selection(),
+            &cache.incremental(),
+            batch.jobs(),
+        )),
+        Commandline::Watermark {
+            image,
+            text,
+            font,
+            size,
+            color: Background(color),
+            position,
+            opacity,
+            margin,
+            relative_width,
+            ops,
+            encoding,
+            batch,
+            cache,
+        } => {
+            let watermark = match (image, text, font) {
+                (Some(image), None, _) => Watermark::from_image(&image),
+                (None, Some(text), Some(font)) => Watermark::from_text(&text, &font, size, color),
+                (None, Some(_), None) => Err(ImagixError::UserInputError(
+                    "Specify the font of the text with --font".to_string(),
+                )),
+                _ => Err(ImagixError::UserInputError(
+                    "Specify either --image or --text".to_string(),
+                )),
+            };
+            let result = watermark.and_then(|watermark| {
+                let watermark = watermark
+                    .position(position)
+                    .opacity(opacity)
+                    .margin(margin)
+                    .relative_width(relative_width);
+                let pipeline = ops
+                    .unwrap_or_default()
+                    .then(Operation::Watermark(Arc::new(watermark)));
+                process_transform_request(
+                    &pipeline,
+                    &encoding.options(),
+                    batch.mode,
+                    &batch.srcfolder,
+                    &batch.selection(),
+                    &cache.incremental(),
+                    batch.jobs(),
+                )
+            });
+            report_batch(result);
+        }
+        Commandline::Srcset {
+            widths,
+            formats,
+            manifest,
+            quality,
+            batch,
+        } => {
+            let options = SrcsetOptions {
+                widths,
+                formats,
+                manifest,
+                encoding: quality.options(),
             };
+            report_batch(process_srcset_request(
+                &options,
+                batch.mode,
+                &batch.srcfolder,
+                &batch.selection(),
+                batch.jobs(),
+            ))
         }
-        Commandline::Stats { srcfolder } => match get_stats(srcfolder) {
-            Ok((count, size)) => println!(
-                "Found {:?} image files with aggregate size of {:?} MB",
-                count, size
-            ),
-            Err(e) => match e {
-                ImagixError::FileIOError(e) => println!("{}", e),
-                ImagixError::UserInputError(e) => println!("{}", e),
-                _ => println!("Error in processing"),
-            },
+        Commandline::Montage {
+            folder,
+            cols,
+            tile,
+            spacing,
+            labels,
+            font,
+            background: Background(background),
+            output,
+            jobs,
+        } => {
+            let options = MontageOptions {
+                cols,
+                tile,
+                spacing,
+                labels,
+                font,
+                background,
+            };
+            let output =
+                output.unwrap_or_else(|| folder.join(DEFAULT_OUTPUT_FOLDER).join("montage.png"));
+            report_sheet(process_montage_request(
+                &options,
+                &folder,
+                &output,
+                jobs.unwrap_or_else(default_jobs),
+            ));
+        }
+        Commandline::Sprite {
+            folder,
+            max_size,
+            padding,
+            prefix,
+            map,
+            output,
+            jobs,
+        } => {
+            let options = SpriteOptions {
+                max_size,
+                padding,
+                prefix,
+                map,
+            };
+            let output =
+                output.unwrap_or_else(|| folder.join(DEFAULT_OUTPUT_FOLDER).join("sprite.png"));
+            report_sheet(process_sprite_request(
+                &options,
+                &folder,
+                &output,
+                jobs.unwrap_or_else(default_jobs),
+            ));
+        }
+        Commandline::Dedupe {
+            folder,
+            hash,
+            threshold,
+            action,
+            move_to,
+            jobs,
+        } => {
+            let result = action.with_folder(move_to).and_then(|action| {
+                let options = DedupeOptions {
+                    hash,
+                    threshold,
+                    action,
+                };
+                process_dedupe_request(&options, &folder, jobs.unwrap_or_else(default_jobs))
+            });
+            report_batch(result.map(|report| {
+                print!("{}", report);
+                report.batch
+            }));
+        }
+        Commandline::Compare {
+            first,
+            second,
+            diff,
+            tolerance,
+            max_diff_pixels,
+            min_psnr,
+            min_ssim,
+        } => {
+            let thresholds = Thresholds {
+                max_different: max_diff_pixels,
+                min_psnr,
+                min_ssim,
+            };
+            let result = open_pair(&first, &second).and_then(|(a, b)| {
+                let comparison = compare(&a, &b, tolerance)?;
+                if let Some(diff) = &diff {
+                    diff_image(&a, &b, tolerance)?
+                        .save(diff)
+                        .at("write", diff)?;
+                }
+                Ok(comparison)
+            });
+            match result {
+                Ok(comparison) => {
+                    println!(
+                        "Compared {:?} and {:?} ({}x{})",
+                        first, second, comparison.width, comparison.height
+                    );
+                    println!("{}", comparison);
+                    if let Some(diff) = diff {
+                        println!("Diff image written to {:?}", diff);
+                    }
+                    let failures = comparison.failures(&thresholds);
+                    for failure in &failures {
+                        println!("Failed: {}", failure);
+                    }
+                    if !failures.is_empty() {
+                        process::exit(1);
+                    }
+                }
+                Err(e) => fail(e),
+            }
+        }
+        Commandline::Exif { file } => match read_exif(&file) {
+            Ok(exif) => {
+                for line in describe(&exif) {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => fail(e),
         },
+        Commandline::Stats { srcfolder, format } => {
+            match get_stats(srcfolder).and_then(|stats| stats.render(format)) {
+                Ok(report) => print!("{}", report),
+                Err(e) => fail(e),
+            }
+        }
+        Commandline::Run {
+            jobfile,
+            only,
+            dry_run,
+            jobs,
+        } => {
+            let selected = load_jobs(&jobfile).and_then(|all| match &only {
+                Some(name) if !all.iter().any(|job| &job.name == name) => {
+                    Err(ImagixError::UserInputError(format!(
+                        "No job named `{}` in {:?}",
+                        name, jobfile
+                    )))
+                }
+                Some(name) => Ok(all.into_iter().filter(|job| &job.name == name).collect()),
+                None => Ok(all),
+            });
+            let selected = selected.unwrap_or_else(|e| fail(e));
+            // A job that fails on some images does not stop the next ones
+            let mut success = true;
+            for job in &selected {
+                println!("Job `{}`", job.name);
+                let result = if dry_run {
+                    job.dry_run()
+                } else {
+                    job.run(jobs.unwrap_or_else(default_jobs))
+                };
+                match result {
+                    Ok(report) => {
+                        print_batch(&report);
+                        success &= report.is_success();
+                    }
+                    Err(e) => fail(e),
+                }
+            }
+            if !success {
+                process::exit(1);
+            }
+        }
     }
 }